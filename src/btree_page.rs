@@ -1,10 +1,11 @@
 #![allow(dead_code)]
 
-use std::io::{prelude::*, SeekFrom};
-use std::{error::Error, fmt};
+use std::{cmp::Ordering, collections::HashSet, error::Error, fmt, ops::Range};
 
-use crate::cell::Cell;
+use crate::cell::{Cell, CellContent};
 use crate::db::Database;
+use crate::error::RuSqliteError;
+use crate::helpers::{read_u16_be, read_u32_be};
 
 const LEAF_BTREE_HEADER_SIZE: u8 = 8;
 const INTERIOR_BTREE_HEADER_SIZE: u8 = 12;
@@ -15,9 +16,9 @@ struct BtreeTypeError {
 }
 
 impl BtreeTypeError {
-    fn new() -> Self {
+    fn new(flag: u8) -> Self {
         Self {
-            details: "invalid b-tree type".to_owned(),
+            details: format!("invalid b-tree type 0x{:02x}", flag),
         }
     }
 }
@@ -51,7 +52,7 @@ impl fmt::Display for PagesExceededError {
 
 impl Error for PagesExceededError {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PageType {
     InteriorIndex,
     InteriorTable,
@@ -60,13 +61,14 @@ pub enum PageType {
 }
 
 impl PageType {
-    fn get_page_type(flag: u8) -> Result<Self, BtreeTypeError> {
-        match flag {
+    // Classify a page from the first byte of its b-tree header
+    pub fn from_byte(b: u8) -> Result<Self, Box<dyn Error>> {
+        match b {
             0x02 => Ok(Self::InteriorIndex),
             0x05 => Ok(Self::InteriorTable),
             0x0a => Ok(Self::LeafIndex),
             0x0d => Ok(Self::LeafTable),
-            _ => Err(BtreeTypeError::new()),
+            _ => Err(BtreeTypeError::new(b).into()),
         }
     }
 
@@ -89,7 +91,7 @@ pub struct BtreePage {
     pub header_size: u8,
     pub header: [u8; 8],
     pub rightmost_ptr: Option<u32>,
-    page_size: u16, // for calculating cell sizes (from db)
+    page_size: u32, // for calculating cell sizes (from db)
 }
 
 impl Default for BtreePage {
@@ -112,86 +114,105 @@ impl Default for BtreePage {
 impl BtreePage {
     pub fn new(db: &mut Database) -> Result<Self, Box<dyn Error>> {
         let mut btree_pg = BtreePage::default();
+        let schema_root = db.schema_root();
         btree_pg
-            .read_page_header(db, 1)
+            .read_page_header(db, schema_root)
             .map_err(|e| e.to_string())?;
         btree_pg.page_size = db.page_size;
         Ok(btree_pg)
     }
 
+    // Read `page_no`, interpreting it as `force_type` when given instead of trusting its type
+    // byte, for recovering cells from pages whose type byte is damaged
+    pub fn at(
+        db: &mut Database,
+        page_no: u32,
+        force_type: Option<PageType>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut btree_pg = BtreePage::default();
+        btree_pg.read_page_header_as(db, page_no, force_type)?;
+        Ok(btree_pg)
+    }
+
     pub fn read_page_header(&mut self, db: &mut Database, page: u32) -> Result<(), Box<dyn Error>> {
+        self.read_page_header_as(db, page, None)
+    }
+
+    fn read_page_header_as(
+        &mut self,
+        db: &mut Database,
+        page: u32,
+        force_type: Option<PageType>,
+    ) -> Result<(), Box<dyn Error>> {
         validate_page_num(db, page).map_err(|e| e.to_string())?;
+        if db.lock_byte_page() == Some(page) {
+            return Err(RuSqliteError::Corrupt(format!(
+                "page {} is the lock-byte page and holds no b-tree",
+                page
+            ))
+            .into());
+        }
         self.page_num = page;
-        self.file_starting_position = ((page - 1) as u64) * (db.page_size as u64);
+        self.file_starting_position = db.page_start(page);
+        self.page_size = db.page_size;
 
-        self.header = [0u8; 8];
-        let pg_header_start: u64 = if page == 1 {
-            100
-        } else {
-            self.file_starting_position
-        };
+        // the b-tree header of page 1 follows the 100-byte database header
+        let pg_header_start: usize = if page == 1 { 100 } else { 0 };
+        let page_data = db.read_page(page).map_err(|e| e.to_string())?;
 
-        db.file
-            .seek(SeekFrom::Start(pg_header_start))
-            .map_err(|e| e.to_string())?;
-        db.file
-            .read_exact(&mut self.header)
-            .map_err(|e| "error reading page header: ".to_owned() + &e.to_string())?;
-        // db.file.read_exact_at(&mut page_header, pg_header_start);
+        self.header = page_data[pg_header_start..pg_header_start + 8]
+            .try_into()
+            .map_err(|e: std::array::TryFromSliceError| {
+                "error reading page header: ".to_owned() + &e.to_string()
+            })?;
 
         // read btree page type from first byte and get header size
-        self.page_type = PageType::get_page_type(self.header[0]).map_err(|e| e.to_string())?;
+        self.page_type = match force_type {
+            Some(page_type) => page_type,
+            None => PageType::from_byte(self.header[0]).map_err(|e| e.to_string())?,
+        };
         self.header_size = self.page_type.get_header_size();
-        self.num_cells = u16::from_be_bytes([self.header[3], self.header[4]]);
-        self.first_cell_start = u16::from_be_bytes([self.header[5], self.header[6]]);
+        self.num_cells = read_u16_be(&self.header[3..])?;
+        self.first_cell_start = read_u16_be(&self.header[5..])?;
 
         // read the right-most pointer if the page is an interior b-tree
         self.rightmost_ptr = match self.page_type {
             PageType::InteriorTable | PageType::InteriorIndex => {
-                let mut pointer_buf = [0u8; 4];
-                db.file
-                    .seek(SeekFrom::Start(
-                        pg_header_start + u64::from(self.header_size) - 4,
-                    ))
-                    .map_err(|e| e.to_string())?;
-                db.file
-                    .read_exact(&mut pointer_buf)
-                    .map_err(|e| e.to_string())?;
-                Some(u32::from_be_bytes(pointer_buf))
+                let ptr_start = pg_header_start + self.header_size as usize - 4;
+                Some(read_u32_be(&page_data[ptr_start..])?)
             }
             _ => None,
         };
 
         // read the cell pointer array immediately following the page header
-        self.cell_pointers = vec![];
-        let mut cell_ptr = [0u8; 2];
-        for i in (0..self.num_cells * 2).step_by(2) {
-            db.file
-                .seek(SeekFrom::Start(
-                    pg_header_start + u64::from(self.header_size) + u64::from(i),
-                ))
-                .map_err(|e| e.to_string())?;
-            db.file
-                .read_exact(&mut cell_ptr)
-                .map_err(|e| e.to_string())?;
-            self.cell_pointers.push(u16::from_be_bytes(cell_ptr))
-        }
+        let ptr_array_start = pg_header_start + self.header_size as usize;
+        self.cell_pointers = page_data
+            .get(ptr_array_start..ptr_array_start + self.num_cells as usize * 2)
+            .ok_or("cell pointer array runs past the end of the page")?
+            .chunks_exact(2)
+            .map(read_u16_be)
+            .collect::<Result<Vec<u16>, _>>()?;
 
+        db.progress.page_visited();
         Ok(())
     }
 
+    // Cells are returned in cell pointer array order (i.e. key order). Each cell's size is the
+    // distance to the next cell in the content area, or to the end of the page for the last one.
     pub fn get_page_cells(&self) -> Vec<Cell> {
-        let mut pointers = self.cell_pointers.clone();
-        pointers.sort_unstable();
+        let mut sorted_pointers = self.cell_pointers.clone();
+        sorted_pointers.sort_unstable();
 
-        pointers
+        self.cell_pointers
             .iter()
-            .enumerate()
-            .map(|(i, offset)| {
-                let size = if i == pointers.len() - 1 {
-                    self.page_size - offset
+            .map(|offset| {
+                let next = sorted_pointers.partition_point(|ptr| ptr <= offset);
+                // a pointer past the end of the page gets no room at all, the cell is reported
+                // as corrupt when it is parsed
+                let size = if next == sorted_pointers.len() {
+                    self.page_size.saturating_sub(u32::from(*offset))
                 } else {
-                    pointers[i + 1] - offset
+                    u32::from(sorted_pointers[next] - offset)
                 };
                 Cell {
                     offset: *offset as u64,
@@ -200,12 +221,503 @@ impl BtreePage {
             })
             .collect::<Vec<Cell>>()
     }
+
+    // The cell pointer array exactly as stored on the page: offsets relative to the start of the
+    // page, in array order, and unsorted. Out-of-order or overlapping values can point at
+    // corruption or hidden data.
+    pub fn raw_cell_pointers(&self, db: &mut Database) -> Result<Vec<u16>, Box<dyn Error>> {
+        let ptr_array_start = if self.page_num == 1 { 100 } else { 0 } + self.header_size as usize;
+        let page_data = db.read_page(self.page_num).map_err(|e| e.to_string())?;
+        page_data
+            .get(ptr_array_start..ptr_array_start + self.num_cells as usize * 2)
+            .ok_or("cell pointer array runs past the end of the page")?
+            .chunks_exact(2)
+            .map(|ptr| Ok(read_u16_be(ptr)?))
+            .collect()
+    }
+
+    // Offset of the cell content area from the start of the page. The header stores 65536 as
+    // 0, so the value doesn't fit the u16 header field.
+    pub fn content_area_start(&self) -> u32 {
+        match self.first_cell_start {
+            0 => 65536,
+            start => u32::from(start),
+        }
+    }
+
+    // Offsets (from the start of the page) of the unused space between the end of the cell
+    // pointer array and the start of the cell content area. Freeblocks and fragments inside the
+    // content area aren't included.
+    pub fn unallocated_region(&self) -> Range<u32> {
+        let ptr_array_end = if self.page_num == 1 { 100 } else { 0 }
+            + u32::from(self.header_size)
+            + u32::from(self.num_cells) * 2;
+        ptr_array_end..self.content_area_start().max(ptr_array_end)
+    }
+
+    // Absolute offsets of the cells from the beginning of the database file, in array order
+    pub fn cell_offsets(&self) -> Vec<u64> {
+        self.cell_pointers
+            .iter()
+            .map(|ptr| self.file_starting_position + u64::from(*ptr))
+            .collect()
+    }
+
+    // Index (in cell pointer order) of the cell holding `rowid` on a leaf table page, found by
+    // binary search over the row_ids, or None if the page doesn't hold it. Row_ids are compared
+    // signed, in the order `verify_rowid_order` checks.
+    pub fn find_rowid(
+        &self,
+        db: &mut Database,
+        rowid: i64,
+    ) -> Result<Option<usize>, Box<dyn Error>> {
+        if !matches!(self.page_type, PageType::LeafTable) {
+            return Err(format!("page {} is not a leaf table page", self.page_num).into());
+        }
+
+        let cells = self.get_page_cells();
+        let (mut low, mut high) = (0, cells.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let row_id = CellContent::get_cell_data(self, db, cells[mid])?.get_row_id()?;
+            match row_id.cmp(&rowid) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(Some(mid)),
+            }
+        }
+        Ok(None)
+    }
+
+    // Row_ids of a leaf table page must be strictly ascending in cell pointer order, which
+    // row_id lookups rely on. Row_ids are signed: negative ones are stored as varints of their
+    // two's complement and sort before every positive one.
+    pub fn verify_rowid_order(&self, db: &mut Database) -> Result<(), Box<dyn Error>> {
+        if !matches!(self.page_type, PageType::LeafTable) {
+            return Ok(());
+        }
+
+        let row_ids = self
+            .get_page_cells()
+            .into_iter()
+            .map(|cell| Ok(CellContent::get_cell_data(self, db, cell)?.get_row_id()?))
+            .collect::<Result<Vec<i64>, Box<dyn Error>>>()?;
+        self.check_ascending(&row_ids, "row_ids")
+    }
+
+    // The integer keys of an interior table page must be strictly ascending like the row_ids
+    // they separate. Keys out of order usually mean the cells were parsed from the wrong
+    // offsets rather than a real ordering problem.
+    pub fn verify_key_order(&self, db: &mut Database) -> Result<(), Box<dyn Error>> {
+        if !matches!(self.page_type, PageType::InteriorTable) {
+            return Ok(());
+        }
+
+        let mut keys = vec![];
+        for cell in self.get_page_cells() {
+            if let CellContent::InteriorTable { integer_key, .. } =
+                CellContent::get_cell_data(self, db, cell)?
+            {
+                keys.push(integer_key);
+            }
+        }
+        self.check_ascending(&keys, "integer keys")
+    }
+
+    fn check_ascending(&self, keys: &[i64], what: &str) -> Result<(), Box<dyn Error>> {
+        match keys.windows(2).find(|pair| pair[1] <= pair[0]) {
+            Some(pair) => Err(RuSqliteError::Corrupt(format!(
+                "{} out of order on page {}: {} follows {}",
+                what, self.page_num, pair[1], pair[0]
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Database {
+    // Walk every page of the b-tree rooted at `root` and check the invariants of each page
+    pub fn check_btree(&mut self, root: u32) -> Result<(), Box<dyn Error>> {
+        let mut btree_pg = BtreePage::default();
+        let mut visited = HashSet::new();
+        let mut pending_pages = vec![root];
+        while let Some(page) = pending_pages.pop() {
+            if !visited.insert(page) {
+                return Err(RuSqliteError::Corrupt(format!(
+                    "page {} is referenced more than once in the b-tree rooted at page {}",
+                    page, root
+                ))
+                .into());
+            }
+
+            btree_pg.read_page_header(self, page)?;
+            match btree_pg.page_type {
+                PageType::InteriorTable | PageType::InteriorIndex => {
+                    btree_pg.verify_key_order(self)?;
+                    for cell in btree_pg.get_page_cells() {
+                        let content = CellContent::get_cell_data(&btree_pg, self, cell)?;
+                        pending_pages.push(content.get_left_child_pointer()?);
+                    }
+                    pending_pages.extend(btree_pg.rightmost_ptr);
+                }
+                PageType::LeafTable => btree_pg.verify_rowid_order(self)?,
+                PageType::LeafIndex => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Page numbers of the leaves of the table or index b-tree rooted at `root`, left to right
+    // (i.e. in key order), the right-most subtree of every interior page included. Every page
+    // must be of the same kind of b-tree as the root.
+    pub fn leaf_pages(&mut self, root: u32) -> Result<Vec<u32>, Box<dyn Error>> {
+        let mut btree_pg = BtreePage::default();
+        let mut leaves = vec![];
+        let mut visited = HashSet::new();
+        let mut pending_pages = vec![root];
+        let mut table_btree = None;
+        while let Some(page) = pending_pages.pop() {
+            if !visited.insert(page) {
+                return Err(RuSqliteError::Corrupt(format!(
+                    "page {} is referenced more than once in the b-tree rooted at page {}",
+                    page, root
+                ))
+                .into());
+            }
+
+            btree_pg.read_page_header(self, page)?;
+            let is_table = matches!(
+                btree_pg.page_type,
+                PageType::LeafTable | PageType::InteriorTable
+            );
+            if *table_btree.get_or_insert(is_table) != is_table {
+                let kind = if is_table { "an index" } else { "a table" };
+                return Err(format!("page {} is not {} b-tree page", page, kind).into());
+            }
+            match btree_pg.page_type {
+                PageType::LeafTable | PageType::LeafIndex => leaves.push(page),
+                PageType::InteriorTable | PageType::InteriorIndex => {
+                    let mut children = vec![];
+                    for cell in btree_pg.get_page_cells() {
+                        let content = CellContent::get_cell_data(&btree_pg, self, cell)?;
+                        children.push(content.get_left_child_pointer()?);
+                    }
+                    children.extend(btree_pg.rightmost_ptr);
+                    pending_pages.extend(children.into_iter().rev());
+                }
+            }
+        }
+        Ok(leaves)
+    }
+
+    // Number of levels from `root` down to the leaves, following the left-most child of each
+    // interior page. All leaves of a b-tree are at the same depth, so one path is enough.
+    pub fn btree_depth(&mut self, root: u32) -> Result<u32, Box<dyn Error>> {
+        let mut btree_pg = BtreePage::default();
+        let mut page = root;
+        let mut depth = 1;
+        loop {
+            btree_pg.read_page_header(self, page)?;
+            let leftmost_child = match btree_pg.page_type {
+                PageType::LeafTable | PageType::LeafIndex => return Ok(depth),
+                PageType::InteriorTable | PageType::InteriorIndex => {
+                    match btree_pg.get_page_cells().into_iter().next() {
+                        Some(cell) => CellContent::get_cell_data(&btree_pg, self, cell)?
+                            .get_left_child_pointer()?,
+                        None => btree_pg.rightmost_ptr.unwrap_or_default(),
+                    }
+                }
+            };
+
+            // a path longer than the number of pages can only come from a cycle
+            if depth >= self.page_count {
+                return Err(RuSqliteError::Corrupt(format!(
+                    "b-tree rooted at page {} is deeper than the database",
+                    root
+                ))
+                .into());
+            }
+            page = leftmost_child;
+            depth += 1;
+        }
+    }
 }
 
 fn validate_page_num(db: &Database, page: u32) -> Result<(), PagesExceededError> {
-    if page > db.page_count {
+    if page == 0 || page > db.page_count {
         Err(PagesExceededError::new())
     } else {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    // `t (id INTEGER PRIMARY KEY, s TEXT)` of 512 byte pages holding `rows` rows of 100
+    // characters each, about four to a leaf
+    fn wide_rows(rows: u32) -> Fixture {
+        Fixture::with_page_size(
+            512,
+            &format!(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+                WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {})
+                INSERT INTO t SELECT i, printf('%0100d', i) FROM n;",
+                rows
+            ),
+        )
+    }
+
+    #[test]
+    fn page_type_from_byte() {
+        assert_eq!(PageType::from_byte(0x02).unwrap(), PageType::InteriorIndex);
+        assert_eq!(PageType::from_byte(0x05).unwrap(), PageType::InteriorTable);
+        assert_eq!(PageType::from_byte(0x0a).unwrap(), PageType::LeafIndex);
+        assert_eq!(PageType::from_byte(0x0d).unwrap(), PageType::LeafTable);
+        for b in [0x00, 0x01, 0x0c, 0xff] {
+            let err = PageType::from_byte(b).unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid b-tree type 0x{:02x}", b));
+        }
+    }
+
+    #[test]
+    fn single_page_table_has_depth_one() {
+        let fixture = wide_rows(2);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert_eq!(db.btree_depth(root).unwrap(), 1);
+    }
+
+    #[test]
+    fn multi_level_table_depth() {
+        let fixture = wide_rows(60);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert_eq!(db.btree_depth(root).unwrap(), 2);
+
+        let fixture = wide_rows(2000);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert_eq!(db.btree_depth(root).unwrap(), 3);
+    }
+
+    #[test]
+    fn rowid_order_of_clean_and_swapped_leaves() {
+        let fixture = wide_rows(3);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        leaf.verify_rowid_order(&mut db).unwrap();
+        db.check_btree(root).unwrap();
+
+        // swap the first two entries of the cell pointer array
+        let pointers = leaf.raw_cell_pointers(&mut db).unwrap();
+        let array_start = db.page_start(root) + 8;
+        fixture.patch(array_start, &pointers[1].to_be_bytes());
+        fixture.patch(array_start + 2, &pointers[0].to_be_bytes());
+
+        let mut db = fixture.open();
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        let err = leaf.verify_rowid_order(&mut db).unwrap_err();
+        assert!(err.to_string().contains(&format!(
+            "row_ids out of order on page {}: 1 follows 2",
+            root
+        )));
+        assert!(db.check_btree(root).is_err());
+    }
+
+    #[test]
+    fn raw_pointers_match_cell_offsets() {
+        let fixture = wide_rows(3);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        let raw = leaf.raw_cell_pointers(&mut db).unwrap();
+        assert_eq!(raw.len(), 3);
+        assert_eq!(raw, leaf.cell_pointers);
+        let absolute = raw
+            .iter()
+            .map(|ptr| db.page_start(root) + u64::from(*ptr))
+            .collect::<Vec<_>>();
+        assert_eq!(leaf.cell_offsets(), absolute);
+    }
+
+    #[test]
+    fn forced_type_reads_a_page_with_a_damaged_type_byte() {
+        let fixture = wide_rows(3);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let intact = BtreePage::at(&mut db, root, None).unwrap();
+
+        fixture.patch(db.page_start(root), &[0x00]);
+        let mut db = fixture.open();
+        let err = BtreePage::at(&mut db, root, None).unwrap_err();
+        assert!(err.to_string().contains("invalid b-tree type 0x00"));
+
+        let forced = BtreePage::at(&mut db, root, Some(PageType::LeafTable)).unwrap();
+        assert_eq!(forced.page_type, PageType::LeafTable);
+        assert_eq!(forced.num_cells, 3);
+        assert_eq!(forced.cell_pointers, intact.cell_pointers);
+        assert_eq!(forced.find_rowid(&mut db, 2).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn unallocated_region_between_pointers_and_content() {
+        let fixture = wide_rows(3);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        let lowest_cell = u32::from(*leaf.cell_pointers.iter().min().unwrap());
+        assert_eq!(leaf.content_area_start(), lowest_cell);
+        assert_eq!(leaf.unallocated_region(), 8 + 3 * 2..lowest_cell);
+
+        // on page 1 the b-tree header follows the database header
+        let schema = BtreePage::at(&mut db, 1, None).unwrap();
+        assert_eq!(schema.unallocated_region().start, 100 + 8 + 2);
+
+        // a stored content area start of 0 means 65536
+        fixture.patch(db.page_start(root) + 5, &[0, 0]);
+        let mut db = fixture.open();
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        assert_eq!(leaf.content_area_start(), 65536);
+    }
+
+    #[test]
+    fn lock_byte_page_is_not_a_btree_page() {
+        let fixture = wide_rows(3);
+        assert_eq!(fixture.open().lock_byte_page(), None);
+
+        // claim enough 512 byte pages to reach 1 GiB, the lock-byte page is the one after
+        let lock_page = (1u32 << 30) / 512 + 1;
+        fixture.patch(28, &(lock_page + 1).to_be_bytes());
+        let mut db = fixture.open();
+        assert_eq!(db.lock_byte_page(), Some(lock_page));
+        let err = BtreePage::at(&mut db, lock_page, None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RuSqliteError>(),
+            Some(RuSqliteError::Corrupt(details))
+                if *details == format!("page {} is the lock-byte page and holds no b-tree", lock_page)
+        ));
+    }
+
+    #[test]
+    fn find_rowid_orders_negative_row_ids_first() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            INSERT INTO t VALUES (-9223372036854775808, 'min'), (-3, 'a'), (-1, 'b'), (1, 'c'),
+                (2, 'd'), (9223372036854775807, 'max');",
+        );
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        leaf.verify_rowid_order(&mut db).unwrap();
+        for (idx, rowid) in [i64::MIN, -3, -1, 1, 2, i64::MAX].into_iter().enumerate() {
+            assert_eq!(
+                leaf.find_rowid(&mut db, rowid).unwrap(),
+                Some(idx),
+                "{}",
+                rowid
+            );
+        }
+        for missing in [-2, 0, 3, i64::MIN + 1] {
+            assert_eq!(leaf.find_rowid(&mut db, missing).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn interior_keys_are_ordered_as_signed_row_ids() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 60)
+            INSERT INTO t SELECT i - 30, printf('%0100d', i) FROM n;",
+        );
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let interior = BtreePage::at(&mut db, root, None).unwrap();
+        assert_eq!(interior.page_type, PageType::InteriorTable);
+        assert!(interior.num_cells > 2);
+        interior.verify_key_order(&mut db).unwrap();
+        db.check_btree(root).unwrap();
+
+        let pointers = interior.raw_cell_pointers(&mut db).unwrap();
+        let array_start = db.page_start(root) + 12;
+        fixture.patch(array_start, &pointers[1].to_be_bytes());
+        fixture.patch(array_start + 2, &pointers[0].to_be_bytes());
+        let mut db = fixture.open();
+        let interior = BtreePage::at(&mut db, root, None).unwrap();
+        let err = interior.verify_key_order(&mut db).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("integer keys out of order on page {}", root)));
+        assert!(db.check_btree(root).is_err());
+    }
+
+    #[test]
+    fn leaf_pages_in_key_order() {
+        let fixture = wide_rows(2000);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert_eq!(db.btree_depth(root).unwrap(), 3);
+        let leaves = db.leaf_pages(root).unwrap();
+        assert!(leaves.len() > 400, "{} leaves", leaves.len());
+
+        // reading the leaves in turn gives every row in order, the right-most ones included
+        let mut row_ids = vec![];
+        for &page in &leaves {
+            let leaf = BtreePage::at(&mut db, page, None).unwrap();
+            assert_eq!(leaf.page_type, PageType::LeafTable);
+            for cell in leaf.get_page_cells() {
+                match CellContent::get_cell_data(&leaf, &mut db, cell).unwrap() {
+                    CellContent::LeafTable { row_id, .. } => row_ids.push(row_id),
+                    _ => panic!("not a table leaf cell"),
+                }
+            }
+        }
+        assert_eq!(row_ids, (1..=2000).collect::<Vec<_>>());
+
+        let fixture = wide_rows(2);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert_eq!(db.leaf_pages(root).unwrap(), [root]);
+    }
+
+    #[test]
+    fn leaf_pages_of_a_looping_btree() {
+        let fixture = wide_rows(60);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        // point the root's right-most child back at the root
+        fixture.patch(db.page_start(root) + 8, &root.to_be_bytes());
+        let mut db = fixture.open();
+        let err = db.leaf_pages(root).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            RuSqliteError::Corrupt(format!(
+                "page {} is referenced more than once in the b-tree rooted at page {}",
+                root, root
+            ))
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn leaf_pages_of_one_kind_of_btree() {
+        let fixture = wide_rows(60);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaves = db.leaf_pages(root).unwrap();
+        // turn the last leaf of the table into an index leaf
+        let leaf = *leaves.last().unwrap();
+        fixture.patch(db.page_start(leaf), &[0x0a]);
+        let mut db = fixture.open();
+        let err = db.leaf_pages(root).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("page {} is not a table b-tree page", leaf)
+        );
+    }
+}
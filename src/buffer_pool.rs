@@ -0,0 +1,96 @@
+// Byte buffers handed back after use, so hot paths like cell parsing reuse their allocations
+// instead of allocating a fresh `Vec` for every cell of a scan
+#[derive(Debug)]
+pub struct BufferPool {
+    free: Vec<Vec<u8>>,
+    max_buffers: usize, // buffers kept for reuse, the rest are dropped when handed back
+}
+
+// Buffers kept for reuse, a scan only has a few in use at a time
+const MAX_POOLED_BUFFERS: usize = 8;
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self {
+            free: vec![],
+            max_buffers: MAX_POOLED_BUFFERS,
+        }
+    }
+}
+
+impl BufferPool {
+    // A pool that keeps nothing, so every buffer taken is a fresh allocation
+    #[cfg(test)]
+    pub fn unpooled() -> Self {
+        Self {
+            free: vec![],
+            max_buffers: 0,
+        }
+    }
+
+    // An empty buffer, keeping the capacity of a previously returned one if there is any
+    pub fn take(&mut self) -> Vec<u8> {
+        self.free.pop().unwrap_or_default()
+    }
+
+    // Return `buf` to the pool, it is cleared before being handed out again
+    pub fn give_back(&mut self, mut buf: Vec<u8>) {
+        if self.free.len() < self.max_buffers && buf.capacity() > 0 {
+            buf.clear();
+            self.free.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::TableIterator;
+    use crate::test_util::{allocations, Fixture};
+
+    #[test]
+    fn returned_buffers_keep_their_capacity() {
+        let mut pool = BufferPool::default();
+        let mut buf = pool.take();
+        buf.extend_from_slice(&[1; 100]);
+        pool.give_back(buf);
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 100);
+
+        let mut pool = BufferPool::unpooled();
+        pool.give_back(buf);
+        assert_eq!(pool.take().capacity(), 0);
+    }
+
+    #[test]
+    fn pooled_scan_allocates_less() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+            INSERT INTO t SELECT i, printf('%020d', i) FROM n;",
+        );
+        let scan_allocations = |pool: BufferPool| {
+            let mut db = fixture.open();
+            db.buffers = pool;
+            let root = db.table_def("t").unwrap().rootpage;
+            // read every page first so only the cell parsing is counted
+            for row in TableIterator::new(&mut db, root) {
+                row.unwrap();
+            }
+            let before = allocations();
+            let rows = TableIterator::new(&mut db, root).count();
+            assert_eq!(rows, 2000);
+            allocations() - before
+        };
+        let pooled = scan_allocations(BufferPool::default());
+        let unpooled = scan_allocations(BufferPool::unpooled());
+        // at least the cell buffer of every row is saved
+        assert!(
+            pooled + 2000 <= unpooled,
+            "{} allocations with the pool, {} without",
+            pooled,
+            unpooled
+        );
+    }
+}
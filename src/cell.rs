@@ -1,15 +1,15 @@
 #![allow(dead_code)]
 
-use std::{
-    error::Error,
-    fmt,
-    io::{BufReader, Read, Seek, SeekFrom},
-};
+use std::{borrow::Cow, error::Error, fmt, ops::Range};
 
 use crate::{
     btree_page::{BtreePage, PageType},
     db::Database,
+    error::RuSqliteError,
+    helpers::read_u32_be,
+    record::Record,
     varint::decode_be,
+    warning::Warning,
 };
 
 #[derive(Debug)]
@@ -36,7 +36,7 @@ impl fmt::Display for InvalidFieldError {
 
 impl Error for InvalidFieldError {}
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Cell {
     pub offset: u64,
     pub size: usize,
@@ -50,30 +50,105 @@ pub struct Payload {
 }
 
 impl Payload {
+    // Number of payload bytes stored on overflow pages. The local threshold `x` depends on the
+    // page type (table leaves keep more on the page than index cells), and every cell parser
+    // decides whether an overflow pointer is present from this alone, never from the cell size.
     pub fn calculate_spillage(&self, db: &Database, page: &BtreePage) -> u64 {
         // Variables below are explained in SQLite documentation: https://www.sqlite.org/fileformat2.html#b_tree_pages
         let p = self.size;
+        // `page_size` is always the decoded size (the stored value 1 already turned into 65536),
+        // never the raw header field. The usable size itself can legitimately drop below 512
+        // when a 512-byte page reserves space, so the page size is what gets checked.
+        debug_assert!(
+            db.page_size >= 512 && db.page_size.is_power_of_two(),
+            "page size {} was not normalized",
+            db.page_size
+        );
+        // computed in u64 so products like `(u - 12) * 64` can't overflow, even for 65536-byte
+        // pages
         let u = db.page_size as u64 - db.reserved_space as u64;
         let m = ((u - 12) * 32 / 255) - 23;
-        let k = m + ((p - m) % (u - 4));
         let x = match page.page_type {
             PageType::LeafTable => u - 35,
             PageType::LeafIndex | PageType::InteriorIndex => ((u - 12) * 64 / 255) - 23,
             _ => 0,
         };
         match p {
-            p if (p > x && k <= x) => p - k,
-            p if (p > x && k > x) => p - m,
-            _ => 0,
+            // payloads up to and including the threshold are stored entirely on the page
+            p if p <= x => 0,
+            p => {
+                let k = m + ((p - m) % (u - 4));
+                if k <= x {
+                    p - k
+                } else {
+                    p - m
+                }
+            }
         }
     }
+
+    // Append the spilled part of the payload by following the overflow page chain. Each overflow
+    // page starts with the next page number (0 on the last page) followed by payload content.
+    // The last page only holds what is left of the payload, and the chain must end there.
+    pub fn read_overflow(&mut self, db: &mut Database) -> Result<(), Box<dyn Error>> {
+        let usable_size = db.page_size as usize - db.reserved_space as usize;
+        let mut next_page = self.overflow.map(u32::from_be_bytes).unwrap_or(0);
+
+        while next_page != 0 && (self.payload.len() as u64) < self.size {
+            let page = next_page;
+            let page_data = db.read_page(page).map_err(|e| e.to_string())?;
+            let remaining = self.size as usize - self.payload.len();
+            let content_size = remaining.min(usable_size - 4);
+            self.payload
+                .extend_from_slice(&page_data[4..4 + content_size]);
+            next_page = read_u32_be(page_data)?;
+
+            if content_size == remaining && next_page != 0 {
+                return Err(RuSqliteError::Corrupt(format!(
+                    "overflow page {} completes a {} byte payload but links to page {}",
+                    page, self.size, next_page
+                ))
+                .into());
+            }
+        }
+
+        if (self.payload.len() as u64) < self.size {
+            return Err(RuSqliteError::Corrupt(format!(
+                "overflow chain ended after {} of {} payload bytes",
+                self.payload.len(),
+                self.size
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    // Split the bytes following the payload size varint into the locally stored payload and,
+    // only when some of it spills, the 4-byte overflow page pointer that follows it
+    fn read_local(
+        &mut self,
+        db: &Database,
+        page: &BtreePage,
+        buf: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        let spillage = self.calculate_spillage(db, page);
+        let local_size = (self.size - spillage) as usize;
+        self.payload.clear();
+        self.payload
+            .extend_from_slice(cell_bytes(buf, 0..local_size)?);
+        if spillage > 0 {
+            let overflow: [u8; 4] = cell_bytes(buf, local_size..local_size + 4)?.try_into()?;
+            self.overflow = Some(overflow);
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub enum CellContent {
     LeafTable {
         cell_type: &'static str,
-        row_id: u64,
+        row_id: i64,
         payload: Payload,
     },
     LeafIndex {
@@ -88,7 +163,7 @@ pub enum CellContent {
     InteriorTable {
         cell_type: &'static str,
         left_child_ptr: u32,
-        integer_key: u64,
+        integer_key: i64,
     },
 }
 
@@ -98,20 +173,72 @@ impl CellContent {
         db: &mut Database,
         cell: Cell,
     ) -> Result<Self, Box<dyn Error>> {
-        let mut reader = BufReader::new(&db.file);
-        reader
-            .seek(SeekFrom::Start(pg.file_starting_position + cell.offset))
-            .map_err(|e| e.to_string())?;
-        let mut cell_buf = vec![0u8; cell.size];
-        reader
-            .read_exact(&mut cell_buf)
-            .map_err(|e| e.to_string())?;
+        // the cell is copied into a pooled buffer, which is handed back whatever the outcome
+        let mut cell_buf = db.buffers.take();
+        let content = Self::parse_cell(pg, db, cell, &mut cell_buf);
+        db.buffers.give_back(cell_buf);
+        content
+    }
+
+    fn parse_cell(
+        pg: &BtreePage,
+        db: &mut Database,
+        cell: Cell,
+        cell_buf: &mut Vec<u8>,
+    ) -> Result<Self, Box<dyn Error>> {
+        db.read_page(pg.page_num).map_err(|e| e.to_string())?;
+        let page_data = db
+            .cached_page(pg.page_num)
+            .ok_or_else(|| format!("page {} is not cached", pg.page_num))?;
+        let cell_start = cell.offset as usize;
+        let on_page = page_data.get(cell_start..).ok_or_else(|| {
+            RuSqliteError::Corrupt(format!(
+                "cell offset {} is off page {}",
+                cell_start, pg.page_num
+            ))
+        })?;
+
+        // a cell smaller than its contents would cut off the end of the payload or the
+        // overflow pointer, a sign of a corrupt cell pointer array or a miscalculated size
+        let required = required_cell_size(db, pg, on_page)?;
+        if required > on_page.len() {
+            return Err(RuSqliteError::Corrupt(format!(
+                "cell at offset {} runs past the end of page {}",
+                cell_start, pg.page_num
+            ))
+            .into());
+        }
+        // copy just the cell, which is read in full even if its slot on the page is too small
+        cell_buf.extend_from_slice(&on_page[..required.max(cell.size).min(on_page.len())]);
+        if required > cell.size {
+            let warning = Warning::CellTooSmall {
+                page_no: pg.page_num,
+                offset: cell_start,
+                size: cell.size,
+                required,
+            };
+            if db.is_strict() {
+                return Err(RuSqliteError::Corrupt(warning.to_string()).into());
+            }
+            db.warnings.warn(warning);
+        }
+        let in_cell = |e: Box<dyn Error>| {
+            RuSqliteError::Corrupt(format!(
+                "cell at offset {} on page {}: {}",
+                cell_start, pg.page_num, e
+            ))
+        };
+
+        let mut payload = Payload::default();
+        if !matches!(pg.page_type, PageType::InteriorTable) {
+            payload.payload = db.buffers.take();
+        }
 
         match pg.page_type {
             PageType::LeafTable => {
                 let cell_type = "B-Tree Leaf Table";
-                let (row_id, payload) =
-                    parse_leaf_table_cell(cell, &mut cell_buf).map_err(|e| e.to_string())?;
+                let row_id =
+                    parse_leaf_table_cell(db, pg, cell_buf, &mut payload).map_err(in_cell)?;
                 Ok(CellContent::LeafTable {
                     cell_type,
                     row_id,
@@ -121,7 +248,7 @@ impl CellContent {
             PageType::InteriorTable => {
                 let cell_type = "B-Tree Interior Table";
                 let (left_child_ptr, integer_key) =
-                    parse_interior_table_cell(&mut cell_buf).map_err(|e| e.to_string())?;
+                    parse_interior_table_cell(cell_buf).map_err(in_cell)?;
                 Ok(CellContent::InteriorTable {
                     cell_type,
                     left_child_ptr,
@@ -130,14 +257,13 @@ impl CellContent {
             }
             PageType::LeafIndex => {
                 let cell_type = "B-Tree Leaf Index";
-                let payload =
-                    parse_leaf_index_cell(cell, &mut cell_buf).map_err(|e| e.to_string())?;
+                parse_leaf_index_cell(db, pg, cell_buf, &mut payload).map_err(in_cell)?;
                 Ok(CellContent::LeafIndex { cell_type, payload })
             }
             PageType::InteriorIndex => {
                 let cell_type = "B-Tree Interior Index";
-                let (left_child_ptr, payload) =
-                    parse_interior_index_cell(cell, &mut cell_buf).map_err(|e| e.to_string())?;
+                let left_child_ptr =
+                    parse_interior_index_cell(db, pg, cell_buf, &mut payload).map_err(in_cell)?;
                 Ok(CellContent::InteriorIndex {
                     cell_type,
                     left_child_ptr,
@@ -147,6 +273,52 @@ impl CellContent {
         }
     }
 
+    // The payload of `cell` as a slice of `page_bytes`, the cached contents of `pg`, without
+    // copying it. None when part of the payload is on overflow pages and has to be reassembled.
+    pub fn payload_ref<'a>(
+        db: &Database,
+        pg: &BtreePage,
+        page_bytes: &'a [u8],
+        cell: Cell,
+    ) -> Result<Option<&'a [u8]>, Box<dyn Error>> {
+        let mut position = cell.offset as usize;
+        match pg.page_type {
+            PageType::InteriorTable => {
+                return Err(InvalidFieldError::new("B-Tree Interior Table", "payload").into())
+            }
+            PageType::InteriorIndex => position += 4,
+            _ => {}
+        }
+        let varints = |position: usize| {
+            page_bytes
+                .get(position..)
+                .ok_or_else(|| format!("cell offset {} is off page {}", position, pg.page_num))
+        };
+        let (size, varint_len) = decode_be(varints(position)?)?;
+        position += varint_len;
+        // skip the row_id
+        if matches!(pg.page_type, PageType::LeafTable) {
+            position += decode_be(varints(position)?)?.1;
+        }
+
+        let payload = Payload {
+            size,
+            ..Default::default()
+        };
+        if payload.calculate_spillage(db, pg) > 0 {
+            return Ok(None);
+        }
+        let payload_bytes = page_bytes
+            .get(position..position + size as usize)
+            .ok_or_else(|| {
+                RuSqliteError::Corrupt(format!(
+                    "payload of the cell at offset {} runs past the end of page {}",
+                    cell.offset, pg.page_num
+                ))
+            })?;
+        Ok(Some(payload_bytes))
+    }
+
     pub fn get_payload(&self) -> Result<&[u8], InvalidFieldError> {
         match self {
             CellContent::LeafTable { payload, .. }
@@ -158,6 +330,12 @@ impl CellContent {
         }
     }
 
+    // Number of columns in the cell's record, read from the record header alone
+    pub fn column_count(&self) -> Result<usize, Box<dyn Error>> {
+        let payload = self.get_payload()?;
+        Record::column_count(payload)
+    }
+
     pub fn get_left_child_pointer(&self) -> Result<u32, InvalidFieldError> {
         match self {
             CellContent::InteriorTable { left_child_ptr, .. }
@@ -168,7 +346,7 @@ impl CellContent {
         }
     }
 
-    pub fn get_row_id(&self) -> Result<u64, InvalidFieldError> {
+    pub fn get_row_id(&self) -> Result<i64, InvalidFieldError> {
         match self {
             CellContent::LeafTable { row_id, .. } => Ok(*row_id),
             CellContent::InteriorTable { cell_type, .. }
@@ -178,77 +356,569 @@ impl CellContent {
             }
         }
     }
+
+    // The accessors above as Options, None when the kind of cell has no such field
+    pub fn try_payload(&self) -> Option<&[u8]> {
+        self.get_payload().ok()
+    }
+
+    pub fn try_left_child_pointer(&self) -> Option<u32> {
+        self.get_left_child_pointer().ok()
+    }
+
+    pub fn try_row_id(&self) -> Option<i64> {
+        self.get_row_id().ok()
+    }
+}
+
+impl Database {
+    // The complete payload of `cell`, borrowed from the page cache when it is stored entirely on
+    // the page and reassembled from the overflow chain otherwise
+    pub fn cell_payload(
+        &mut self,
+        pg: &BtreePage,
+        cell: Cell,
+    ) -> Result<Cow<'_, [u8]>, Box<dyn Error>> {
+        self.read_page(pg.page_num)?;
+        let page_bytes = self
+            .cached_page(pg.page_num)
+            .ok_or_else(|| format!("page {} is not cached", pg.page_num))?;
+        if CellContent::payload_ref(self, pg, page_bytes, cell)?.is_none() {
+            let mut payload = match CellContent::get_cell_data(pg, self, cell)? {
+                CellContent::LeafTable { payload, .. }
+                | CellContent::LeafIndex { payload, .. }
+                | CellContent::InteriorIndex { payload, .. } => payload,
+                CellContent::InteriorTable { cell_type, .. } => {
+                    return Err(InvalidFieldError::new(cell_type, "payload").into())
+                }
+            };
+            payload.read_overflow(self)?;
+            return Ok(Cow::Owned(payload.payload));
+        }
+
+        // borrow the page again now that `self` is no longer needed mutably
+        let page_bytes = self
+            .cached_page(pg.page_num)
+            .ok_or_else(|| format!("page {} is not cached", pg.page_num))?;
+        let payload = CellContent::payload_ref(self, pg, page_bytes, cell)?.unwrap_or_default();
+        Ok(Cow::Borrowed(payload))
+    }
+}
+
+// The bytes of a cell buffer in `range`, erroring rather than panicking when a truncated cell
+// is shorter than its contents
+fn cell_bytes(buf: &[u8], range: Range<usize>) -> Result<&[u8], String> {
+    let needed = range.end;
+    buf.get(range)
+        .ok_or_else(|| format!("needs {} bytes but only {} are left", needed, buf.len()))
+}
+
+// Size in bytes of a cell starting at the beginning of `buf`, from its varints and the local
+// part of its payload
+fn required_cell_size(db: &Database, pg: &BtreePage, buf: &[u8]) -> Result<usize, Box<dyn Error>> {
+    let has_child_ptr = matches!(
+        pg.page_type,
+        PageType::InteriorTable | PageType::InteriorIndex
+    );
+    let mut position = if has_child_ptr { 4 } else { 0 };
+    let rest = buf
+        .get(position..)
+        .ok_or("cell runs past the end of the page")?;
+    let (value, varint_len) = decode_be(rest)?;
+    position += varint_len;
+    // the only varint of an interior table cell is its integer key
+    if matches!(pg.page_type, PageType::InteriorTable) {
+        return Ok(position);
+    }
+    if matches!(pg.page_type, PageType::LeafTable) {
+        let rest = buf
+            .get(position..)
+            .ok_or("cell runs past the end of the page")?;
+        position += decode_be(rest)?.1;
+    }
+
+    let payload = Payload {
+        size: value,
+        ..Default::default()
+    };
+    let spillage = payload.calculate_spillage(db, pg);
+    let overflow_ptr = if spillage > 0 { 4 } else { 0 };
+    Ok(position + (payload.size - spillage) as usize + overflow_ptr)
 }
 
 fn parse_leaf_table_cell(
-    cell: Cell,
+    db: &Database,
+    pg: &BtreePage,
     cell_buf: &mut [u8],
-) -> Result<(u64, Payload), Box<dyn Error>> {
-    let mut payload = Payload::default();
+    payload: &mut Payload,
+) -> Result<i64, Box<dyn Error>> {
     let mut varint_len: usize;
     let mut position: usize = 0;
 
     (payload.size, varint_len) = decode_be(cell_buf).map_err(|e| e.to_string())?;
     position += varint_len;
 
-    if payload.size > cell.size as u64 {
-        let overflow: [u8; 4] = cell_buf[cell_buf.len() - 4..].try_into()?;
-        payload.overflow = Some(overflow);
-    }
-
     let rowid: u64;
     (rowid, varint_len) = decode_be(&cell_buf[position..]).map_err(|e| e.to_string())?;
     position += varint_len;
 
-    payload.payload = match payload.overflow {
-        Some(_) => cell_buf[position..cell_buf.len() - 4].to_vec(),
-        None => cell_buf[position..].to_vec(),
-    };
-    Ok((rowid, payload))
+    payload.read_local(db, pg, &cell_buf[position..])?;
+    // row_ids are signed, negative ones are stored as the varint of their two's complement
+    Ok(rowid as i64)
 }
 
-fn parse_interior_table_cell(cell_buf: &mut [u8]) -> Result<(u32, u64), Box<dyn Error>> {
-    let left_child_ptr_buf: [u8; 4] = cell_buf[..4].try_into()?;
-    let left_child_ptr = u32::from_be_bytes(left_child_ptr_buf);
-    let (int_key, _) = decode_be(&cell_buf[4..])?;
-    Ok((left_child_ptr, int_key))
+fn parse_interior_table_cell(cell_buf: &mut [u8]) -> Result<(u32, i64), Box<dyn Error>> {
+    let left_child_ptr = read_u32_be(cell_bytes(cell_buf, 0..4)?)?;
+    let (int_key, _) = decode_be(cell_bytes(cell_buf, 4..cell_buf.len())?)?;
+    Ok((left_child_ptr, int_key as i64))
 }
 
-fn parse_leaf_index_cell(cell: Cell, cell_buf: &mut [u8]) -> Result<Payload, Box<dyn Error>> {
-    let mut payload = Payload::default();
+fn parse_leaf_index_cell(
+    db: &Database,
+    pg: &BtreePage,
+    cell_buf: &mut [u8],
+    payload: &mut Payload,
+) -> Result<(), Box<dyn Error>> {
     let varint_len: usize;
     (payload.size, varint_len) = decode_be(cell_buf).map_err(|e| e.to_string())?;
 
-    if payload.size > cell.size as u64 {
-        let overflow: [u8; 4] = cell_buf[cell_buf.len() - 4..].try_into()?;
-        payload.overflow = Some(overflow);
-    }
-
-    payload.payload = match payload.overflow {
-        Some(_) => cell_buf[varint_len..cell_buf.len() - 4].to_vec(),
-        None => cell_buf[varint_len..].to_vec(),
-    };
-    Ok(payload)
+    payload.read_local(db, pg, &cell_buf[varint_len..])
 }
 
 fn parse_interior_index_cell(
-    cell: Cell,
+    db: &Database,
+    pg: &BtreePage,
     cell_buf: &mut [u8],
-) -> Result<(u32, Payload), Box<dyn Error>> {
-    let left_child_ptr_buf: [u8; 4] = cell_buf[..4].try_into()?;
-    let left_child_ptr = u32::from_be_bytes(left_child_ptr_buf);
-    let mut payload = Payload::default();
+    payload: &mut Payload,
+) -> Result<u32, Box<dyn Error>> {
+    let left_child_ptr = read_u32_be(cell_bytes(cell_buf, 0..4)?)?;
     let varint_len: usize;
     (payload.size, varint_len) = decode_be(&cell_buf[4..]).map_err(|e| e.to_string())?;
 
-    if payload.size > cell.size as u64 {
-        let overflow: [u8; 4] = cell_buf[cell_buf.len() - 4..].try_into()?;
-        payload.overflow = Some(overflow);
+    payload.read_local(db, pg, &cell_buf[4 + varint_len..])?;
+    Ok(left_child_ptr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::FieldData;
+    use crate::test_util::Fixture;
+
+    // The first cell of table `t`, whose only row is a single blob
+    fn blob_row(len: usize) -> (Fixture, Vec<u8>) {
+        let blob = (0..len).map(|i| i as u8).collect::<Vec<u8>>();
+        let fixture = Fixture::build(4096, |conn| {
+            conn.execute("CREATE TABLE t (b BLOB)", []).unwrap();
+            conn.execute("INSERT INTO t VALUES (?1)", [&blob]).unwrap();
+        });
+        (fixture, blob)
     }
 
-    payload.payload = match payload.overflow {
-        Some(_) => cell_buf[4 + varint_len..cell_buf.len() - 4].to_vec(),
-        None => cell_buf[4 + varint_len..].to_vec(),
-    };
-    Ok((left_child_ptr, payload))
+    #[test]
+    fn payload_at_local_threshold_is_not_truncated() {
+        // a table leaf of a 4096 byte page keeps up to 4061 payload bytes on the page, and
+        // this record is a 3 byte header followed by a 4058 byte blob
+        let (fixture, blob) = blob_row(4058);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        let content = CellContent::get_cell_data(&leaf, &mut db, leaf.get_page_cells()[0]).unwrap();
+        let CellContent::LeafTable { payload, .. } = content else {
+            panic!("expected a leaf table cell");
+        };
+        assert_eq!(payload.size, 4061);
+        assert_eq!(payload.calculate_spillage(&db, &leaf), 0);
+        assert!(payload.overflow.is_none());
+        assert_eq!(payload.payload.len(), 4061);
+        let record = Record::parse(&payload.payload).unwrap();
+        assert_eq!(record.values(), [FieldData::Blob(blob)]);
+    }
+
+    #[test]
+    fn payload_one_past_threshold_spills() {
+        let (fixture, blob) = blob_row(4059);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        let content = CellContent::get_cell_data(&leaf, &mut db, leaf.get_page_cells()[0]).unwrap();
+        let CellContent::LeafTable { mut payload, .. } = content else {
+            panic!("expected a leaf table cell");
+        };
+        assert!(payload.calculate_spillage(&db, &leaf) > 0);
+        assert!(payload.overflow.is_some());
+        payload.read_overflow(&mut db).unwrap();
+        let record = Record::parse(&payload.payload).unwrap();
+        assert_eq!(record.values(), [FieldData::Blob(blob)]);
+    }
+
+    // The text in the first cell of `table`, read through `db`
+    fn first_text(db: &mut Database, table: &str) -> String {
+        let root = db.table_def(table).unwrap().rootpage;
+        let leaf = BtreePage::at(db, root, None).unwrap();
+        let content = CellContent::get_cell_data(&leaf, db, leaf.get_page_cells()[0]).unwrap();
+        let record = Record::parse(content.get_payload().unwrap()).unwrap();
+        record.values()[0].as_text().unwrap().to_owned()
+    }
+
+    #[test]
+    fn interleaved_reads_through_cloned_handles() {
+        let fixture = Fixture::new(
+            "CREATE TABLE a (s); INSERT INTO a VALUES ('from a');
+            CREATE TABLE b (s); INSERT INTO b VALUES ('from b');",
+        );
+        // the second handle shares the file descriptor, and with it any cursor position
+        let mut first = fixture.open();
+        let mut second = first.reader().unwrap();
+        assert_eq!(first_text(&mut first, "a"), "from a");
+        assert_eq!(first_text(&mut second, "b"), "from b");
+        let (mut first, mut second) = (second.reader().unwrap(), first.reader().unwrap());
+        assert_eq!(first_text(&mut second, "b"), "from b");
+        assert_eq!(first_text(&mut first, "a"), "from a");
+        // positional reads leave the shared cursor where it was
+        use std::io::Seek;
+        assert_eq!(first.file.stream_position().unwrap(), 0);
+    }
+
+    #[test]
+    fn column_count_from_record_header() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (a, b, c);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 50)
+            INSERT INTO t SELECT i, 'x', printf('%0100d', i) FROM n;",
+        );
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let interior = BtreePage::at(&mut db, root, None).unwrap();
+        assert!(matches!(interior.page_type, PageType::InteriorTable));
+        let cell = interior.get_page_cells()[0];
+        let content = CellContent::get_cell_data(&interior, &mut db, cell).unwrap();
+        let err = content.column_count().unwrap_err();
+        assert!(err.to_string().contains("does not have a `payload` field"));
+
+        let leaf_page = content.get_left_child_pointer().unwrap();
+        let leaf = BtreePage::at(&mut db, leaf_page, None).unwrap();
+        let content = CellContent::get_cell_data(&leaf, &mut db, leaf.get_page_cells()[0]).unwrap();
+        assert_eq!(content.column_count().unwrap(), 3);
+    }
+
+    #[test]
+    fn leaf_index_cell_with_spilling_key() {
+        // index cells of 4096 byte pages keep at most 1001 payload bytes locally
+        let long_key = "k".repeat(2000);
+        let fixture = Fixture::new(&format!(
+            "CREATE TABLE t (s TEXT);
+            CREATE INDEX t_s ON t (s);
+            INSERT INTO t VALUES ('{}');",
+            long_key
+        ));
+        let mut db = fixture.open();
+        let root = db
+            .read_schema()
+            .unwrap()
+            .into_iter()
+            .find(|entry| entry.name == "t_s")
+            .unwrap()
+            .rootpage;
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        assert!(matches!(leaf.page_type, PageType::LeafIndex));
+        let content = CellContent::get_cell_data(&leaf, &mut db, leaf.get_page_cells()[0]).unwrap();
+        let CellContent::LeafIndex { mut payload, .. } = content else {
+            panic!("expected a leaf index cell");
+        };
+        let spillage = payload.calculate_spillage(&db, &leaf);
+        assert!(spillage > 0);
+        assert!(payload.overflow.is_some());
+        assert_eq!(payload.payload.len() as u64, payload.size - spillage);
+
+        payload.read_overflow(&mut db).unwrap();
+        let record = Record::parse(&payload.payload).unwrap();
+        assert_eq!(record.values()[0], FieldData::Text(long_key));
+        // the rowid the entry points at
+        assert_eq!(record.values()[1].as_integer(), Some(1));
+    }
+
+    #[test]
+    fn undersized_cell_warns_or_fails_in_strict_mode() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (s TEXT);
+            INSERT INTO t VALUES (printf('%0100d', 1)), (printf('%0100d', 2));",
+        );
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let first = BtreePage::at(&mut db, root, None).unwrap().cell_pointers[0];
+        // point the second cell 10 bytes into the first, leaving the first a 10 byte slot
+        fixture.patch(db.page_start(root) + 10, &(first + 10).to_be_bytes());
+
+        let mut db = fixture.open();
+        let warnings = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let collected = warnings.clone();
+        db.set_warning_callback(Box::new(move |warning| {
+            collected.lock().unwrap().push(warning.clone())
+        }));
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        let cell = leaf.get_page_cells()[0];
+        assert_eq!(cell.size, 10);
+        let content = CellContent::get_cell_data(&leaf, &mut db, cell).unwrap();
+        let record = Record::parse(content.get_payload().unwrap()).unwrap();
+        assert_eq!(
+            record.values[0].as_text(),
+            Some(format!("{:0100}", 1).as_str())
+        );
+        // 2 varints and a 103 byte payload: a 3 byte header and 100 characters
+        let expected = Warning::CellTooSmall {
+            page_no: root,
+            offset: usize::from(first),
+            size: 10,
+            required: 105,
+        };
+        assert_eq!(*warnings.lock().unwrap(), std::slice::from_ref(&expected));
+
+        let mut db = crate::db::DatabaseBuilder::new(&fixture.path)
+            .strict(true)
+            .open()
+            .unwrap();
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        let err = CellContent::get_cell_data(&leaf, &mut db, cell).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RuSqliteError>(),
+            Some(RuSqliteError::Corrupt(details)) if *details == expected.to_string()
+        ));
+    }
+
+    #[test]
+    fn spillage_on_64k_pages_uses_the_decoded_page_size() {
+        let blob = vec![5u8; 60000];
+        let fixture = Fixture::build(65536, |conn| {
+            conn.execute("CREATE TABLE t (b BLOB)", []).unwrap();
+            conn.execute("INSERT INTO t VALUES (?1)", [&blob]).unwrap();
+        });
+        // the header stores 65536 as 1
+        assert_eq!(fixture.bytes()[16..18], [0, 1]);
+        let mut db = fixture.open();
+        assert_eq!(db.page_size, 65536);
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaf = BtreePage::at(&mut db, root, None).unwrap();
+        let content = CellContent::get_cell_data(&leaf, &mut db, leaf.get_page_cells()[0]).unwrap();
+        let CellContent::LeafTable { payload, .. } = content else {
+            panic!("expected a leaf table cell");
+        };
+        assert_eq!(payload.calculate_spillage(&db, &leaf), 0);
+        assert_eq!(
+            Record::parse(&payload.payload).unwrap().values(),
+            [FieldData::Blob(blob)]
+        );
+    }
+
+    #[test]
+    fn cell_payload_borrows_unless_it_spills() {
+        for (len, borrowed) in [(100, true), (4058, true), (5000, false)] {
+            let (fixture, blob) = blob_row(len);
+            let mut db = fixture.open();
+            let root = db.table_def("t").unwrap().rootpage;
+            let leaf = BtreePage::at(&mut db, root, None).unwrap();
+            let cell = leaf.get_page_cells()[0];
+            let page_range = db.cached_page(root).unwrap().as_ptr_range();
+            let payload = db.cell_payload(&leaf, cell).unwrap();
+            match &payload {
+                Cow::Borrowed(bytes) => {
+                    assert!(borrowed, "{} byte blob", len);
+                    assert!(page_range.contains(&bytes.as_ptr()));
+                }
+                Cow::Owned(_) => assert!(!borrowed, "{} byte blob", len),
+            }
+            assert_eq!(
+                Record::parse(&payload).unwrap().values(),
+                [FieldData::Blob(blob)]
+            );
+        }
+    }
+
+    #[test]
+    fn overflow_chain_must_end_with_the_payload() {
+        let read_blob = |fixture: &Fixture| {
+            let mut db = fixture.open();
+            let root = db.table_def("t").unwrap().rootpage;
+            let leaf = BtreePage::at(&mut db, root, None).unwrap();
+            db.cell_payload(&leaf, leaf.get_page_cells()[0])
+                .map(|payload| payload.len())
+        };
+        let corrupt = |fixture: &Fixture| match read_blob(fixture) {
+            Err(err) => match err.downcast_ref::<RuSqliteError>() {
+                Some(RuSqliteError::Corrupt(details)) => details.clone(),
+                _ => panic!("not a corruption error: {}", err),
+            },
+            Ok(len) => panic!("read {} bytes of a damaged chain", len),
+        };
+
+        // a 10004 byte payload keeps 1820 bytes in its cell and spills onto two full pages
+        let (fixture, _) = blob_row(10000);
+        let chain = fixture.open().all_overflow_pages().unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(read_blob(&fixture).unwrap(), 10004);
+
+        let start = |page| fixture.open().page_start(page);
+        fixture.patch(start(chain[1]), &1u32.to_be_bytes());
+        assert_eq!(
+            corrupt(&fixture),
+            format!(
+                "overflow page {} completes a 10004 byte payload but links to page 1",
+                chain[1]
+            )
+        );
+
+        fixture.patch(start(chain[0]), &0u32.to_be_bytes());
+        assert_eq!(
+            corrupt(&fixture),
+            "overflow chain ended after 5912 of 10004 payload bytes"
+        );
+    }
+
+    #[test]
+    fn two_byte_cells_are_errors_in_every_parser() {
+        let fixture = Fixture::new("CREATE TABLE t (a); CREATE INDEX t_a ON t (a);");
+        let mut db = fixture.open();
+        let table_root = db.table_def("t").unwrap().rootpage;
+        let schema = db.read_schema().unwrap();
+        let index_root = schema.iter().find(|e| e.name == "t_a").unwrap().rootpage;
+        let table = BtreePage::at(&mut db, table_root, None).unwrap();
+        let index = BtreePage::at(&mut db, index_root, None).unwrap();
+
+        // a 5 byte payload (and row_id 1) with none of its bytes present
+        let short = || vec![0x05, 0x01];
+        let leaf_table = parse_leaf_table_cell(&db, &table, &mut short(), &mut Payload::default());
+        let leaf_index = parse_leaf_index_cell(&db, &index, &mut short(), &mut Payload::default());
+        let interior_table = parse_interior_table_cell(&mut short());
+        let interior_index =
+            parse_interior_index_cell(&db, &index, &mut short(), &mut Payload::default());
+        for err in [
+            leaf_table.unwrap_err(),
+            leaf_index.unwrap_err(),
+            interior_table.unwrap_err(),
+            interior_index.unwrap_err(),
+        ] {
+            assert!(err.to_string().contains("only"), "{}", err);
+        }
+
+        // a spilling payload cut off in the middle of its overflow page number
+        let mut payload = Payload {
+            size: 5000,
+            ..Payload::default()
+        };
+        let local = 5000 - payload.calculate_spillage(&db, &table) as usize;
+        let err = payload
+            .read_local(&db, &table, &vec![0; local + 2])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("needs {} bytes but only {} are left", local + 4, local + 2)
+        );
+    }
+
+    #[test]
+    fn overflow_on_64k_pages_is_reassembled() {
+        let sizes = [65000, 65600, 131000, 300000];
+        let blobs = sizes
+            .iter()
+            .map(|&len| (0..len).map(|i| (i * 31 % 251) as u8).collect::<Vec<u8>>())
+            .collect::<Vec<_>>();
+        let fixture = Fixture::build(65536, |conn| {
+            conn.execute("CREATE TABLE t (b BLOB)", []).unwrap();
+            for blob in &blobs {
+                conn.execute("INSERT INTO t VALUES (?1)", [blob]).unwrap();
+            }
+        });
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let stored = crate::table::TableIterator::new(&mut db, root)
+            .map(|row| row.unwrap().1.into_values().remove(0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stored,
+            blobs.into_iter().map(FieldData::Blob).collect::<Vec<_>>()
+        );
+
+        // u = 65536, so M = 65524 * 32 / 255 - 23 = 8199 and X = 65536 - 35 = 65501. Each
+        // payload has a 4 byte header, and keeps M + (P - M) % (U - 4) bytes if that is at
+        // most X, M otherwise.
+        let spilled = |p: u64| match 8199 + (p - 8199) % 65532 {
+            _ if p <= 65501 => 0,
+            k if k <= 65501 => p - k,
+            _ => p - 8199,
+        };
+        let expected = sizes.iter().map(|&len| spilled(len + 4)).sum::<u64>();
+        assert_eq!(db.overflow_bytes("t").unwrap(), expected);
+        assert_eq!(spilled(65604), 65604 - 8199);
+        assert_eq!(spilled(300004), 300004 - 37876);
+
+        let first_leaf = db.leaf_pages(root).unwrap()[0];
+        let leaf = BtreePage::at(&mut db, first_leaf, None).unwrap();
+        assert_eq!(leaf.page_type, PageType::LeafTable);
+        for (p, spillage) in [(65501, 0), (65502, 57303), (300004, 262128)] {
+            let payload = Payload {
+                size: p,
+                ..Payload::default()
+            };
+            assert_eq!(payload.calculate_spillage(&db, &leaf), spillage, "{}", p);
+        }
+    }
+
+    #[test]
+    fn try_accessors_are_none_for_fields_a_cell_lacks() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a TEXT);
+            CREATE INDEX t_a ON t (a);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300)
+            INSERT INTO t SELECT i, printf('value-%020d', i) FROM n;",
+        );
+        let mut db = fixture.open();
+        let table_root = db.table_def("t").unwrap().rootpage;
+        let schema = db.read_schema().unwrap();
+        let index_root = schema.iter().find(|e| e.name == "t_a").unwrap().rootpage;
+
+        // the first cell of both roots and of the leftmost leaf below each of them
+        let mut cells = vec![];
+        for root in [table_root, index_root] {
+            let interior = BtreePage::at(&mut db, root, None).unwrap();
+            let cell = interior.get_page_cells()[0];
+            let content = CellContent::get_cell_data(&interior, &mut db, cell).unwrap();
+            let child = content.try_left_child_pointer().unwrap();
+            cells.push(content);
+            let mut page = BtreePage::at(&mut db, child, None).unwrap();
+            while page.rightmost_ptr.is_some() {
+                let first = page.get_page_cells()[0];
+                let content = CellContent::get_cell_data(&page, &mut db, first).unwrap();
+                let child = content.try_left_child_pointer().unwrap();
+                page = BtreePage::at(&mut db, child, None).unwrap();
+            }
+            let cell = page.get_page_cells()[0];
+            cells.push(CellContent::get_cell_data(&page, &mut db, cell).unwrap());
+        }
+
+        let [interior_table, leaf_table, interior_index, leaf_index] = &cells[..] else {
+            panic!("expected four cells");
+        };
+        assert!(matches!(interior_table, CellContent::InteriorTable { .. }));
+        assert!(matches!(leaf_table, CellContent::LeafTable { .. }));
+        assert!(matches!(interior_index, CellContent::InteriorIndex { .. }));
+        assert!(matches!(leaf_index, CellContent::LeafIndex { .. }));
+
+        assert_eq!(leaf_table.try_row_id(), Some(1));
+        for content in [interior_table, interior_index, leaf_index] {
+            assert_eq!(content.try_row_id(), None);
+        }
+        assert_eq!(interior_table.try_payload(), None);
+        for content in [leaf_table, interior_index, leaf_index] {
+            assert_eq!(content.try_payload(), content.get_payload().ok());
+            assert!(content.try_payload().is_some());
+        }
+        for content in [leaf_table, leaf_index] {
+            assert_eq!(content.try_left_child_pointer(), None);
+        }
+        for content in [interior_table, interior_index] {
+            assert!(content.try_left_child_pointer().unwrap() > 1);
+        }
+    }
 }
@@ -0,0 +1,151 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{db::Database, record::FieldData};
+
+// Send and Sync so a database can be handed to another thread, reference counted so every
+// `Database::reader` shares the collations registered on the database it was made from
+pub type Collation = Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync>;
+
+#[derive(Debug)]
+pub struct UnknownCollationError {
+    details: String,
+}
+
+impl UnknownCollationError {
+    fn new(name: &str) -> Self {
+        Self {
+            details: format!("no such collation sequence: {}", name),
+        }
+    }
+}
+
+impl fmt::Display for UnknownCollationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for UnknownCollationError {}
+
+// Text comparison functions referenced by name in `COLLATE` clauses. Names are case-insensitive
+// and the built-in BINARY, NOCASE and RTRIM collations are always registered.
+#[derive(Clone)]
+pub struct CollationRegistry {
+    collations: HashMap<String, Collation>,
+}
+
+impl Default for CollationRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            collations: HashMap::new(),
+        };
+        registry.register("BINARY", Arc::new(|a: &str, b: &str| a.cmp(b)));
+        // SQLite only folds ASCII characters for NOCASE
+        registry.register(
+            "NOCASE",
+            Arc::new(|a: &str, b: &str| {
+                let fold = |s: &str| {
+                    s.bytes()
+                        .map(|c| c.to_ascii_lowercase())
+                        .collect::<Vec<_>>()
+                };
+                fold(a).cmp(&fold(b))
+            }),
+        );
+        registry.register(
+            "RTRIM",
+            Arc::new(|a: &str, b: &str| a.trim_end_matches(' ').cmp(b.trim_end_matches(' '))),
+        );
+        registry
+    }
+}
+
+impl fmt::Debug for CollationRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.collations.keys()).finish()
+    }
+}
+
+impl CollationRegistry {
+    // Register a collation, replacing any existing one (built-ins included) with the same name
+    pub fn register(&mut self, name: &str, collation: Collation) {
+        self.collations.insert(name.to_ascii_uppercase(), collation);
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Collation, UnknownCollationError> {
+        self.collations
+            .get(&name.to_ascii_uppercase())
+            .ok_or_else(|| UnknownCollationError::new(name))
+    }
+}
+
+impl Database {
+    pub fn register_collation(&mut self, name: &str, collation: Collation) {
+        self.collations.register(name, collation);
+    }
+
+    // Compare two values the way index keys and ORDER BY do, using the named collation for text
+    pub fn compare_values(
+        &self,
+        a: &FieldData,
+        b: &FieldData,
+        collation: &str,
+    ) -> Result<Ordering, Box<dyn Error>> {
+        let collation = self.collations.get(collation)?;
+        Ok(a.compare(b, collation.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    fn texts(words: &[&str]) -> Vec<FieldData> {
+        words
+            .iter()
+            .map(|word| FieldData::Text(word.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn sorts_with_a_registered_collation() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        let mut db = fixture.open();
+        db.register_collation("reverse", Arc::new(|a: &str, b: &str| b.cmp(a)));
+
+        let mut values = texts(&["banana", "apple", "cherry"]);
+        values.sort_by(|a, b| db.compare_values(a, b, "REVERSE").unwrap());
+        assert_eq!(values, texts(&["cherry", "banana", "apple"]));
+        // NULLs still sort first whatever the collation
+        assert_eq!(
+            db.compare_values(&FieldData::Null(()), &values[0], "reverse")
+                .unwrap(),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn built_in_collations() {
+        let registry = CollationRegistry::default();
+        let nocase = registry.get("nocase").unwrap();
+        assert_eq!(nocase("ABC", "abc"), Ordering::Equal);
+        let rtrim = registry.get("RTRIM").unwrap();
+        assert_eq!(rtrim("abc  ", "abc"), Ordering::Equal);
+        let binary = registry.get("BINARY").unwrap();
+        assert_eq!(binary("B", "a"), Ordering::Less);
+    }
+
+    #[test]
+    fn unknown_collation_is_an_error() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        let db = fixture.open();
+        let a = FieldData::Text("a".to_owned());
+        let err = db.compare_values(&a, &a, "klingon").unwrap_err();
+        assert_eq!(err.to_string(), "no such collation sequence: klingon");
+    }
+}
@@ -0,0 +1,266 @@
+use std::error::Error;
+
+use crate::{db::Database, record::FieldData, table::TableIterator};
+
+// The values of one column across every row, stored as a typed vector. `nulls[i]` is true
+// where row `i` is NULL, and the matching slot of `values` holds a placeholder (0, 0.0 or
+// empty). Columns mixing storage classes, or holding nothing but NULLs, are kept as `Mixed`.
+#[derive(Debug)]
+pub enum Column {
+    I64 {
+        values: Vec<i64>,
+        nulls: Vec<bool>,
+    },
+    F64 {
+        values: Vec<f64>,
+        nulls: Vec<bool>,
+    },
+    Text {
+        values: Vec<String>,
+        nulls: Vec<bool>,
+    },
+    Blob {
+        values: Vec<Vec<u8>>,
+        nulls: Vec<bool>,
+    },
+    Mixed(Vec<FieldData>),
+}
+
+impl Column {
+    fn from_values(values: Vec<FieldData>) -> Self {
+        let non_null = || {
+            values
+                .iter()
+                .filter(|value| !matches!(value, FieldData::Null(_)))
+        };
+        let nulls = values
+            .iter()
+            .map(|value| matches!(value, FieldData::Null(_)))
+            .collect();
+
+        if non_null().next().is_none() {
+            Column::Mixed(values)
+        } else if non_null().all(|value| value.as_integer().is_some()) {
+            Column::I64 {
+                values: values
+                    .iter()
+                    .map(|value| value.as_integer().unwrap_or_default())
+                    .collect(),
+                nulls,
+            }
+        } else if non_null().all(|value| value.as_real().is_some()) {
+            // reals mixed with integers, e.g. a REAL column where integral values were stored as integers
+            Column::F64 {
+                values: values
+                    .iter()
+                    .map(|value| value.as_real().unwrap_or_default())
+                    .collect(),
+                nulls,
+            }
+        } else if non_null().all(|value| matches!(value, FieldData::Text(_))) {
+            Column::Text {
+                values: values
+                    .into_iter()
+                    .map(|value| match value {
+                        FieldData::Text(text) => text,
+                        _ => String::new(),
+                    })
+                    .collect(),
+                nulls,
+            }
+        } else if non_null().all(|value| matches!(value, FieldData::Blob(_))) {
+            Column::Blob {
+                values: values
+                    .into_iter()
+                    .map(|value| match value {
+                        FieldData::Blob(blob) => blob,
+                        _ => vec![],
+                    })
+                    .collect(),
+                nulls,
+            }
+        } else {
+            Column::Mixed(values)
+        }
+    }
+}
+
+// A storage class SQLite can give a non-NULL value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageClass {
+    Integer,
+    Real,
+    Text,
+    Blob,
+}
+
+// What a column's stored values say about its type, regardless of the declared type. `storage`
+// is the most common class among non-NULL values (None if every sampled value is NULL) and
+// `mixed` is set when more than one class occurs. Integers alongside reals count as Real.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InferredType {
+    pub storage: Option<StorageClass>,
+    pub nullable: bool,
+    pub mixed: bool,
+}
+
+impl InferredType {
+    fn from_counts(counts: &[usize; 4], nulls: usize) -> Self {
+        let [integers, reals, texts, blobs] = *counts;
+        // an integer in a column that otherwise holds reals is a real with an integral value
+        let numeric = if reals > 0 {
+            (StorageClass::Real, integers + reals)
+        } else {
+            (StorageClass::Integer, integers)
+        };
+        let classes = [
+            numeric,
+            (StorageClass::Text, texts),
+            (StorageClass::Blob, blobs),
+        ];
+        let present = classes.iter().filter(|(_, count)| *count > 0);
+
+        InferredType {
+            storage: present
+                .clone()
+                .max_by_key(|(_, count)| *count)
+                .map(|(class, _)| *class),
+            nullable: nulls > 0,
+            mixed: present.count() > 1,
+        }
+    }
+}
+
+impl Database {
+    // Read `columns` of every row of `table` as one typed vector per column, in the order given
+    pub fn select_columnar(
+        &mut self,
+        table: &str,
+        columns: &[&str],
+    ) -> Result<Vec<Column>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+        let indices = columns
+            .iter()
+            .map(|column| {
+                table_def
+                    .column_index(column)
+                    .ok_or_else(|| format!("no such column: {}", column))
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+
+        let mut column_values = vec![vec![]; indices.len()];
+        for row in TableIterator::new(self, table_def.rootpage) {
+            let (row_id, mut record) = row?;
+            table_def.complete_record(row_id, &mut record);
+            for (values, idx) in column_values.iter_mut().zip(&indices) {
+                values.push(record.values[*idx].clone());
+            }
+        }
+        Ok(column_values.into_iter().map(Column::from_values).collect())
+    }
+
+    // Infer the type of every column of `table` from the values of its first `sample` rows
+    pub fn infer_column_types(
+        &mut self,
+        table: &str,
+        sample: usize,
+    ) -> Result<Vec<InferredType>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+
+        let column_count = table_def.columns.len();
+        let mut counts = vec![[0usize; 4]; column_count];
+        let mut nulls = vec![0usize; column_count];
+        for row in TableIterator::new(self, table_def.rootpage).take(sample) {
+            let (row_id, mut record) = row?;
+            table_def.complete_record(row_id, &mut record);
+            for (idx, value) in record.values.iter().take(column_count).enumerate() {
+                let class = match value {
+                    FieldData::Null(_) => {
+                        nulls[idx] += 1;
+                        continue;
+                    }
+                    FieldData::Real(_) => 1,
+                    FieldData::Text(_) => 2,
+                    FieldData::Blob(_) => 3,
+                    _ => 0,
+                };
+                counts[idx][class] += 1;
+            }
+        }
+        Ok(counts
+            .iter()
+            .zip(nulls)
+            .map(|(counts, nulls)| InferredType::from_counts(counts, nulls))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn typed_vectors_per_column() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER, x REAL, s TEXT, b BLOB, m, z);
+            INSERT INTO t VALUES (1, 10, 1.5, 'a', X'01', 1, NULL);
+            INSERT INTO t VALUES (2, NULL, NULL, NULL, NULL, 'one', NULL);
+            INSERT INTO t VALUES (3, -3, 2, 'c', X'0203', 2.5, NULL);",
+        );
+        let columns = fixture
+            .open()
+            .select_columnar("t", &["x", "n", "s", "b", "m", "z", "id"])
+            .unwrap();
+        assert_eq!(columns.len(), 7);
+
+        assert!(matches!(&columns[0], Column::F64 { values, nulls }
+            if *values == [1.5, 0.0, 2.0] && *nulls == [false, true, false]));
+        assert!(matches!(&columns[1], Column::I64 { values, nulls }
+            if *values == [10, 0, -3] && *nulls == [false, true, false]));
+        assert!(matches!(&columns[2], Column::Text { values, nulls }
+            if *values == ["a", "", "c"] && *nulls == [false, true, false]));
+        assert!(matches!(&columns[3], Column::Blob { values, nulls }
+            if *values == [vec![1], vec![], vec![2, 3]] && *nulls == [false, true, false]));
+        assert!(matches!(&columns[4], Column::Mixed(values) if values.len() == 3));
+        // nothing but NULLs says nothing about the type
+        assert!(matches!(&columns[5], Column::Mixed(values)
+            if values.iter().all(|value| *value == FieldData::Null(()))));
+        assert!(matches!(&columns[6], Column::I64 { values, .. } if *values == [1, 2, 3]));
+
+        assert!(fixture.open().select_columnar("t", &["nope"]).is_err());
+    }
+
+    #[test]
+    fn inferred_types_from_sampled_rows() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (a INTEGER, b REAL, c TEXT, d, e);
+            INSERT INTO t VALUES (1, 1.5, 'x', 'text', NULL);
+            INSERT INTO t VALUES (2, 2, NULL, 7, NULL);
+            INSERT INTO t VALUES (3, 3.5, 'z', 'more', NULL);
+            INSERT INTO t VALUES ('four', 4.5, 'w', X'00', X'01');",
+        );
+        let mut db = fixture.open();
+        let inferred = |storage, nullable, mixed| InferredType {
+            storage,
+            nullable,
+            mixed,
+        };
+        // the fourth row, the only one with a mismatched `a` or a non-NULL `e`, isn't sampled
+        assert_eq!(
+            db.infer_column_types("t", 3).unwrap(),
+            [
+                inferred(Some(StorageClass::Integer), false, false),
+                inferred(Some(StorageClass::Real), false, false),
+                inferred(Some(StorageClass::Text), true, false),
+                inferred(Some(StorageClass::Text), false, true),
+                inferred(None, true, false),
+            ]
+        );
+        let all = db.infer_column_types("t", usize::MAX).unwrap();
+        assert_eq!(all[0], inferred(Some(StorageClass::Integer), false, true));
+        assert_eq!(all[4], inferred(Some(StorageClass::Blob), true, false));
+    }
+}
@@ -0,0 +1,241 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+
+use crate::{db::Database, record::FieldData, table::TableIterator};
+
+#[derive(Debug)]
+pub struct CsvFormatError {
+    details: String,
+}
+
+impl CsvFormatError {
+    fn new(line: usize, reason: &str) -> Self {
+        Self {
+            details: format!("invalid CSV on line {}: {}", line, reason),
+        }
+    }
+}
+
+impl fmt::Display for CsvFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for CsvFormatError {}
+
+// A difference between a table and the CSV it is validated against
+#[derive(Debug, PartialEq)]
+pub enum Discrepancy {
+    // the row exists in both but a column's value differs
+    Mismatch {
+        row_id: i64,
+        column: String,
+        table_value: String,
+        csv_value: String,
+    },
+    // the table row has no counterpart because the CSV ran out of rows
+    MissingFromCsv {
+        row_id: i64,
+    },
+    // the CSV row on `line` has no counterpart because the table ran out of rows
+    ExtraInCsv {
+        line: usize,
+    },
+}
+
+// Reads RFC 4180 records one at a time: fields are comma-separated, may be quoted with `"`,
+// and quoted fields may contain commas, line breaks and `""` escaped quotes
+struct CsvReader<R> {
+    reader: R,
+    line: usize, // line on which the next record starts
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn next_record(&mut self) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+        let start_line = self.line + 1;
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut buf = String::new();
+
+        loop {
+            buf.clear();
+            if self.reader.read_line(&mut buf)? == 0 {
+                if in_quotes {
+                    return Err(CsvFormatError::new(start_line, "unterminated quoted field").into());
+                }
+                if fields.is_empty() && field.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            }
+            self.line += 1;
+
+            let mut chars = buf.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (in_quotes, c) {
+                    (true, '"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    (true, '"') => in_quotes = false,
+                    (true, c) => field.push(c),
+                    (false, '"') if field.is_empty() => in_quotes = true,
+                    (false, ',') => fields.push(std::mem::take(&mut field)),
+                    (false, '\r' | '\n') => {}
+                    (false, c) => field.push(c),
+                }
+            }
+            if !in_quotes {
+                break;
+            }
+        }
+
+        fields.push(field);
+        Ok(Some(fields))
+    }
+}
+
+// How a value appears in a CSV export: NULL is an empty field and blobs are written as text
+fn csv_text(value: &FieldData) -> String {
+    match value {
+        FieldData::Null(_) => String::new(),
+        FieldData::Text(text) => text.clone(),
+        FieldData::Blob(blob) => String::from_utf8_lossy(blob).into_owned(),
+        // keep the decimal point on integral reals, e.g. `1.0` rather than `1`
+        FieldData::Real(value) => format!("{:?}", value),
+        value => value.as_integer().unwrap_or_default().to_string(),
+    }
+}
+
+impl Database {
+    // Compare `table` with a CSV export of it, streaming both side by side. The CSV must start
+    // with a header row naming table columns, and both are assumed to be in row_id order.
+    pub fn validate_against_csv(
+        &mut self,
+        table: &str,
+        csv: &mut impl Read,
+    ) -> Result<Vec<Discrepancy>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+
+        let mut csv_reader = CsvReader {
+            reader: BufReader::new(csv),
+            line: 0,
+        };
+        let header = csv_reader
+            .next_record()?
+            .ok_or_else(|| CsvFormatError::new(1, "missing header row"))?;
+        let columns = header
+            .iter()
+            .map(|name| {
+                table_def
+                    .column_index(name.trim())
+                    .ok_or_else(|| format!("table {} has no column named {}", table, name))
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+
+        let mut discrepancies = vec![];
+        let mut rows = TableIterator::new(self, table_def.rootpage);
+        loop {
+            let csv_line = csv_reader.line + 1;
+            match (rows.next().transpose()?, csv_reader.next_record()?) {
+                (None, None) => break,
+                (Some((row_id, _)), None) => {
+                    discrepancies.push(Discrepancy::MissingFromCsv { row_id })
+                }
+                (None, Some(_)) => discrepancies.push(Discrepancy::ExtraInCsv { line: csv_line }),
+                (Some((row_id, mut record)), Some(csv_record)) => {
+                    table_def.complete_record(row_id, &mut record);
+                    for (csv_idx, column_idx) in columns.iter().enumerate() {
+                        let table_value = record
+                            .values
+                            .get(*column_idx)
+                            .map(csv_text)
+                            .unwrap_or_default();
+                        let csv_value = csv_record.get(csv_idx).cloned().unwrap_or_default();
+                        if table_value != csv_value {
+                            discrepancies.push(Discrepancy::Mismatch {
+                                row_id,
+                                column: table_def.columns[*column_idx].name.clone(),
+                                table_value,
+                                csv_value,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        Ok(discrepancies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    fn people() -> Fixture {
+        Fixture::new(
+            "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT, score REAL);
+            INSERT INTO people VALUES (1, 'Ada', 9.5), (2, 'Lin, Mei', NULL), (3, 'Bo', 7.0);",
+        )
+    }
+
+    #[test]
+    fn matching_csv_has_no_discrepancies() {
+        let fixture = people();
+        let mut csv = "id,name,score\n1,Ada,9.5\n2,\"Lin, Mei\",\n3,Bo,7.0\n".as_bytes();
+        let discrepancies = fixture
+            .open()
+            .validate_against_csv("people", &mut csv)
+            .unwrap();
+        assert_eq!(discrepancies, []);
+    }
+
+    #[test]
+    fn reports_mismatched_and_missing_rows() {
+        let fixture = people();
+        let mut csv = "name,id\nAda,1\nLin,2\n".as_bytes();
+        let discrepancies = fixture
+            .open()
+            .validate_against_csv("people", &mut csv)
+            .unwrap();
+        assert_eq!(
+            discrepancies,
+            [
+                Discrepancy::Mismatch {
+                    row_id: 2,
+                    column: "name".to_owned(),
+                    table_value: "Lin, Mei".to_owned(),
+                    csv_value: "Lin".to_owned(),
+                },
+                Discrepancy::MissingFromCsv { row_id: 3 },
+            ]
+        );
+
+        let mut csv = "id\n1\n2\n3\n4\n".as_bytes();
+        let discrepancies = fixture
+            .open()
+            .validate_against_csv("people", &mut csv)
+            .unwrap();
+        assert_eq!(discrepancies, [Discrepancy::ExtraInCsv { line: 5 }]);
+    }
+
+    #[test]
+    fn quoted_fields_span_lines() {
+        let mut reader = CsvReader {
+            reader: "a,\"b\nc\",\"d \"\"e\"\"\"\nf\n".as_bytes(),
+            line: 0,
+        };
+        assert_eq!(
+            reader.next_record().unwrap().unwrap(),
+            ["a", "b\nc", "d \"e\""]
+        );
+        assert_eq!(reader.line, 2);
+        assert_eq!(reader.next_record().unwrap().unwrap(), ["f"]);
+        assert!(reader.next_record().unwrap().is_none());
+    }
+}
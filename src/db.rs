@@ -4,47 +4,126 @@ use std::env::current_dir;
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::buffer_pool::BufferPool;
+use crate::collation::CollationRegistry;
+use crate::db_header::{DbHeader, JournalMode, DB_HEADER_SIZE};
+use crate::error::RuSqliteError;
+use crate::helpers::{read_u16_be, read_u32_be};
+use crate::page_cache::{PageCache, DEFAULT_CACHE_PAGES};
+use crate::progress::ProgressReporter;
+use crate::record::DEFAULT_MAX_COLUMNS;
+use crate::warning::WarningReporter;
 
-const DB_HEADER_SIZE: usize = 100;
-const HEADER_STRING_ARR: [u8; 16] = [
-    0x53, 0x51, 0x4c, 0x69, 0x74, 0x65, 0x20, 0x66, 0x6f, 0x72, 0x6d, 0x61, 0x74, 0x20, 0x33, 0x00,
-];
 // description (offset, size) per SQLite database header format
 const HEADER_STR_SZ: (usize, usize) = (0, 16);
 const PG_SIZE: (usize, usize) = (16, 2);
 const PG_COUNT: (usize, usize) = (28, 4);
 const RESERVED_SPACE: (usize, usize) = (20, 1);
+const FREELIST_TRUNK: (usize, usize) = (32, 4);
+const FREELIST_COUNT: (usize, usize) = (36, 4);
+const RESERVED_FOR_EXPANSION: (usize, usize) = (72, 20);
+// page 1 is always the root of the `sqlite_master` schema table
+const SCHEMA_ROOT_PAGE: u32 = 1;
+// Smallest page size less reserved space SQLite accepts
+pub const MIN_USABLE_SIZE: u32 = 480;
+// SQLite uses the bytes from this offset for file locking and never stores data on the page
+// holding them
+const LOCK_BYTE_OFFSET: u64 = 1 << 30;
+
+#[derive(Debug)]
+pub enum OpenError {
+    BadMagic, // the file doesn't start with the `SQLite format 3\0` header string
+    BadPageSize(u16),
+    PageSizeMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpenError::BadMagic => write!(f, "file is not a valid database"),
+            OpenError::BadPageSize(value) => write!(
+                f,
+                "invalid page size `{}` in database header - must be a power of two \
+                between 512 and 32768, or 1 for 65536",
+                value
+            ),
+            OpenError::PageSizeMismatch { expected, actual } => write!(
+                f,
+                "expected a page size of {} but the database header says {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for OpenError {}
 
 #[derive(Debug)]
-struct InvalidDBFileError {
+struct InvalidSchemaPageError {
     details: String,
 }
 
-impl InvalidDBFileError {
-    fn new() -> Self {
+impl InvalidSchemaPageError {
+    fn new(flag: u8) -> Self {
         Self {
-            details: "file is not a valid database".to_owned(),
+            details: format!(
+                "page 1 is not a table b-tree page (type byte 0x{:02x}) - \
+                file might not be a valid database or is corrupt",
+                flag
+            ),
         }
     }
 }
 
-impl fmt::Display for InvalidDBFileError {
+impl fmt::Display for InvalidSchemaPageError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.details)
     }
 }
 
-impl Error for InvalidDBFileError {}
+impl Error for InvalidSchemaPageError {}
+
+// How a database file is kept consistent, from the journal mode in its header and the
+// sidecar files SQLite keeps next to it. An embedded database (one opened at an offset into a
+// larger file) has no sidecars of its own, so only its header is considered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageMode {
+    // a non-empty `-wal` file may hold committed changes not yet copied into the database
+    Wal { wal_present: bool },
+    // a non-empty `-journal` file is left behind by an interrupted write and must be rolled
+    // back before the file is consistent
+    Rollback { journal_present: bool },
+    Unknown { write_version: u8, read_version: u8 },
+}
 
 #[derive(Debug)]
 pub struct Database {
     pub file: File,
-    pub header: [u8; DB_HEADER_SIZE],
-    pub page_size: u16,
+    pub path: PathBuf, // absolute path of the database file
+    pub header: DbHeader,
+    pub page_size: u32, // decoded size in bytes, the stored value 1 is normalized to 65536
     pub page_count: u32,
     pub reserved_space: u8,
+    pub freelist_trunk: u32, // first freelist trunk page, 0 if there are no free pages
+    pub freelist_count: u32,
+    page_cache: PageCache, // the pages this handle read last, see `shared_cache`
+    // pages read through every handle made by `reader`, so one handle reading a page saves the
+    // others the read. The contents are shared with each handle's own `page_cache`.
+    shared_cache: Arc<Mutex<PageCache>>,
+    prefetch: u32,     // number of consecutive pages read into the cache on a miss
+    file_reads: u64,   // reads issued to the file for pages, see `file_reads`
+    header_only: bool, // opened with `open_header_only`, pages can't be read
+    strict: bool,      // reject well-formedness violations SQLite itself tolerates
+    pub collations: CollationRegistry,
+    pub max_columns: usize, // records with more columns than this are rejected as corrupt
+    base_offset: u64,       // position of the database header in the file
+    pub progress: ProgressReporter,
+    pub warnings: WarningReporter,
+    pub buffers: BufferPool, // scratch buffers reused by cell parsing
 }
 
 impl Database {
@@ -52,16 +131,58 @@ impl Database {
     where
         P: AsRef<Path>,
     {
-        let mut path = db_file.as_ref().to_path_buf();
+        Database::open_file(db_file.as_ref(), false, None, 0)
+    }
+
+    // Open a database stored `base_offset` bytes into a larger file, e.g. one appended to an
+    // executable. The database header must start exactly at `base_offset`.
+    pub fn open_at_offset<P>(db_file: P, base_offset: u64) -> Result<Self, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        Database::open_file(db_file.as_ref(), false, None, base_offset)
+    }
+
+    // Open a database reading and validating only its 100-byte header, for scanning metadata of
+    // many files cheaply. Nothing beyond the header needs to exist, and any b-tree operation on
+    // the returned database errors instead of reading pages.
+    pub fn open_header_only<P>(db_file: P) -> Result<Self, Box<dyn Error>>
+    where
+        P: AsRef<Path>,
+    {
+        Database::open_file(db_file.as_ref(), true, None, 0)
+    }
+
+    // With an `expected_page_size`, the header and the rest of page 1 are read together and
+    // page 1 starts out cached
+    fn open_file(
+        db_file: &Path,
+        header_only: bool,
+        expected_page_size: Option<u32>,
+        base_offset: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut path = db_file.to_path_buf();
         if !path.is_absolute() {
             let cwd = current_dir()?;
             path = cwd.join(path);
         }
 
-        let mut file = File::open(&path).map_err(|e| e.to_string())?;
-        let mut header = [0; DB_HEADER_SIZE];
-        file.read_exact(&mut header)
+        let file = File::open(&path).map_err(RuSqliteError::Io)?;
+        let first_read_size = match expected_page_size {
+            Some(expected) => {
+                let file_size = file.metadata().map_err(RuSqliteError::Io)?.len();
+                let file_size = file_size.saturating_sub(base_offset);
+                u64::from(expected)
+                    .min(file_size)
+                    .max(DB_HEADER_SIZE as u64) as usize
+            }
+            None => DB_HEADER_SIZE,
+        };
+        let mut first_page = vec![0; first_read_size];
+        read_exact_at(&file, &mut first_page, base_offset)
             .map_err(|e| e.to_string() + " - database header might be invalid or corrupt")?;
+        let mut header = [0; DB_HEADER_SIZE];
+        header.copy_from_slice(&first_page[..DB_HEADER_SIZE]);
 
         let header_str_arr: [u8; 16] = header
             [(HEADER_STR_SZ.0)..(HEADER_STR_SZ.0 + HEADER_STR_SZ.1)]
@@ -69,43 +190,931 @@ impl Database {
             .map_err(|e: std::array::TryFromSliceError| {
                 "error reading header: ".to_owned() + &e.to_string()
             })?;
-        validate_db_file(header_str_arr).map_err(|e| e.to_string())?;
+        DbHeader::validate_magic(&header_str_arr)?;
 
-        let pg_size_arr = header[(PG_SIZE.0)..(PG_SIZE.0 + PG_SIZE.1)]
-            .try_into()
-            .map_err(|e: std::array::TryFromSliceError| {
-                "error reading header: ".to_owned() + &e.to_string()
-            })?;
-        let page_size = u16::from_be_bytes(pg_size_arr);
+        // the schema page's b-tree header immediately follows the database header
+        if !header_only {
+            let schema_page_type = match first_page.get(DB_HEADER_SIZE) {
+                Some(page_type) => *page_type,
+                None => {
+                    let mut page_type = [0u8; 1];
+                    let offset = base_offset + DB_HEADER_SIZE as u64;
+                    read_exact_at(&file, &mut page_type, offset).map_err(|e| {
+                        e.to_string() + " - schema page might be missing or corrupt"
+                    })?;
+                    page_type[0]
+                }
+            };
+            validate_schema_page(schema_page_type).map_err(|e| e.to_string())?;
+        }
 
-        let pg_count_arr = header[(PG_COUNT.0)..(PG_COUNT.0 + PG_COUNT.1)]
-            .try_into()
-            .map_err(|e: std::array::TryFromSliceError| {
-                "error reading header: ".to_owned() + &e.to_string()
-            })?;
-        let page_count = u32::from_be_bytes(pg_count_arr);
+        let raw_page_size = read_u16_be(&header[(PG_SIZE.0)..(PG_SIZE.0 + PG_SIZE.1)])
+            .map_err(|e| "error reading header: ".to_owned() + &e.to_string())?;
+        let page_size = Database::parse_page_size(raw_page_size)?;
+        let mut page_cache = PageCache::default();
+        let mut shared_cache = PageCache::default();
+        if let Some(expected) = expected_page_size {
+            if expected != page_size {
+                return Err(OpenError::PageSizeMismatch {
+                    expected,
+                    actual: page_size,
+                }
+                .into());
+            }
+            if !header_only && first_page.len() == page_size as usize {
+                let first_page = Arc::<[u8]>::from(first_page);
+                shared_cache.insert(SCHEMA_ROOT_PAGE, first_page.clone());
+                page_cache.insert(SCHEMA_ROOT_PAGE, first_page);
+            }
+        }
 
-        let reserved_space_arr = header[(RESERVED_SPACE.0)..(RESERVED_SPACE.0 + RESERVED_SPACE.1)]
-            .try_into()
-            .map_err(|e: std::array::TryFromSliceError| {
-                "error reading header: ".to_owned() + &e.to_string()
-            })?;
-        let reserved_space = u8::from_be_bytes(reserved_space_arr);
+        // a database file is made of whole pages, anything else means the page size is wrong or
+        // the file was cut short. An embedded database may be followed by unrelated data.
+        if !header_only && base_offset == 0 {
+            let file_size = file.metadata().map_err(RuSqliteError::Io)?.len();
+            if file_size % u64::from(page_size) != 0 {
+                return Err(RuSqliteError::Corrupt(format!(
+                    "file length not a multiple of page size: {} bytes with {} byte pages",
+                    file_size, page_size
+                ))
+                .into());
+            }
+        }
 
-        Ok(Self {
+        let mut db = Self {
             file,
-            header,
+            path,
+            header: DbHeader::new(header),
             page_size,
-            page_count,
-            reserved_space,
+            page_count: 0,
+            reserved_space: 0,
+            freelist_trunk: 0,
+            freelist_count: 0,
+            shared_cache: Arc::new(Mutex::new(shared_cache)),
+            page_cache,
+            prefetch: 1,
+            file_reads: 0,
+            header_only,
+            strict: false,
+            collations: CollationRegistry::default(),
+            max_columns: DEFAULT_MAX_COLUMNS,
+            base_offset,
+            progress: ProgressReporter::default(),
+            warnings: WarningReporter::default(),
+            buffers: BufferPool::default(),
+        };
+        db.load_header_fields()?;
+        Ok(db)
+    }
+
+    // Decode the header fields kept on the database from its raw header bytes
+    fn load_header_fields(&mut self) -> Result<(), Box<dyn Error>> {
+        let header = *self.header.as_bytes();
+        let raw_page_size = read_u16_be(&header[(PG_SIZE.0)..(PG_SIZE.0 + PG_SIZE.1)])
+            .map_err(|e| "error reading header: ".to_owned() + &e.to_string())?;
+        self.page_size = Database::parse_page_size(raw_page_size)?;
+        self.page_count = read_u32_be(&header[(PG_COUNT.0)..(PG_COUNT.0 + PG_COUNT.1)])
+            .map_err(|e| "error reading header: ".to_owned() + &e.to_string())?;
+        self.reserved_space = header[RESERVED_SPACE.0];
+        // the overflow thresholds in `calculate_spillage` underflow below SQLite's minimum
+        let usable_size = self.page_size - u32::from(self.reserved_space);
+        if usable_size < MIN_USABLE_SIZE {
+            return Err(RuSqliteError::Corrupt(format!(
+                "{} reserved bytes leave {} usable bytes per {} byte page, at least {} are needed",
+                self.reserved_space, usable_size, self.page_size, MIN_USABLE_SIZE
+            ))
+            .into());
+        }
+        self.freelist_trunk =
+            read_u32_be(&header[(FREELIST_TRUNK.0)..(FREELIST_TRUNK.0 + FREELIST_TRUNK.1)])
+                .map_err(|e| "error reading header: ".to_owned() + &e.to_string())?;
+        self.freelist_count =
+            read_u32_be(&header[(FREELIST_COUNT.0)..(FREELIST_COUNT.0 + FREELIST_COUNT.1)])
+                .map_err(|e| "error reading header: ".to_owned() + &e.to_string())?;
+        Ok(())
+    }
+
+    pub fn change_counter(&self) -> u32 {
+        self.header.change_counter()
+    }
+
+    // Re-read the header and, if the change counter moved because another connection wrote to
+    // the file, take the new header and drop every cached page. Returns whether it changed.
+    pub fn reopen_if_changed(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut header = [0; DB_HEADER_SIZE];
+        read_exact_at(&self.file, &mut header, self.base_offset).map_err(RuSqliteError::Io)?;
+        let header_str_arr: [u8; 16] =
+            header[(HEADER_STR_SZ.0)..(HEADER_STR_SZ.0 + HEADER_STR_SZ.1)].try_into()?;
+        DbHeader::validate_magic(&header_str_arr)?;
+
+        let header = DbHeader::new(header);
+        if header.change_counter() == self.change_counter() {
+            return Ok(false);
+        }
+        self.header = header;
+        self.load_header_fields()?;
+        self.page_cache.clear();
+        self.shared_cache().clear();
+        Ok(true)
+    }
+
+    // Page number (indexed from one) containing the byte at `offset` in the file. Offsets 0-99
+    // are the database header, which is part of page 1 rather than a page of its own.
+    pub fn page_of_offset(&self, offset: u64) -> u32 {
+        (offset.saturating_sub(self.base_offset) / u64::from(self.page_size)) as u32 + 1
+    }
+
+    // Byte offset in the file where `page_no` starts. For page 1 this is the start of the
+    // database header; its b-tree header starts 100 bytes later.
+    pub fn page_start(&self, page_no: u32) -> u64 {
+        self.base_offset + u64::from(page_no.saturating_sub(1)) * u64::from(self.page_size)
+    }
+
+    // Decode the 2-byte page size header field. Sizes are powers of two from 512 to 32768, and
+    // 65536 doesn't fit in the field so it is stored as 1.
+    pub fn parse_page_size(raw: u16) -> Result<u32, OpenError> {
+        match raw {
+            1 => Ok(65536),
+            512..=32768 if raw.is_power_of_two() => Ok(u32::from(raw)),
+            _ => Err(OpenError::BadPageSize(raw)),
+        }
+    }
+
+    // Bytes 72-91 of the header are reserved for expansion and must be zero. SQLite doesn't
+    // check them, so this only runs in strict mode.
+    fn check_reserved_for_expansion(&self) -> Result<(), RuSqliteError> {
+        let reserved_start = RESERVED_FOR_EXPANSION.0;
+        let reserved =
+            &self.header.as_bytes()[reserved_start..reserved_start + RESERVED_FOR_EXPANSION.1];
+        match reserved.iter().position(|byte| *byte != 0) {
+            Some(idx) => Err(RuSqliteError::Corrupt(format!(
+                "reserved header byte at offset {} is 0x{:02x}, expected zero",
+                reserved_start + idx,
+                reserved[idx]
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // Any payload fractions other than 64/32/32 mean the header is damaged, and the overflow
+    // thresholds computed by `calculate_spillage` wouldn't match the ones the file was written with
+    fn check_payload_fractions(&self) -> Result<(), RuSqliteError> {
+        let fractions = (
+            self.header.max_payload_fraction(),
+            self.header.min_payload_fraction(),
+            self.header.leaf_payload_fraction(),
+        );
+        if fractions != (64, 32, 32) {
+            return Err(RuSqliteError::Corrupt(format!(
+                "payload fractions are {}/{}/{}, expected 64/32/32",
+                fractions.0, fractions.1, fractions.2
+            )));
+        }
+        Ok(())
+    }
+
+    // Return the full contents of a page, reading it (and the following `prefetch - 1` pages)
+    // from the file in a single call if it is not cached yet. The read goes straight into a
+    // buffer sized to the whole batch rather than through a fixed-capacity `BufReader`, so a page
+    // costs one underlying read whatever the page size (up to 64 KiB).
+    pub fn read_page(&mut self, page: u32) -> Result<&[u8], Box<dyn Error>> {
+        if self.header_only {
+            return Err("database was opened header-only - pages can't be read".into());
+        }
+        if page == 0 || page > self.page_count {
+            return Err(format!(
+                "page {} is out of range for a database of {} pages",
+                page, self.page_count
+            )
+            .into());
+        }
+
+        if !self.page_cache.contains(page) {
+            let shared = self.shared_cache().get_shared(page).cloned();
+            if let Some(data) = shared {
+                self.page_cache.insert(page, data);
+            }
+        }
+        if !self.page_cache.contains(page) {
+            let page_size = self.page_size as usize;
+            // prefetching more pages than the cache holds would evict the first ones again
+            let batch = (self.prefetch as usize)
+                .min(self.page_cache.capacity())
+                .min((self.page_count - page + 1) as usize);
+            let mut buf = vec![0u8; batch * page_size];
+            self.file_reads += 1;
+            if let Err(e) = read_exact_at(&self.file, &mut buf, self.page_start(page)) {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    return Err(RuSqliteError::Io(e).into());
+                }
+                // a prefetched page may be the one past the end, the requested page can still
+                // be read on its own if the file holds all of it
+                let file_len = self.file.metadata().map_err(RuSqliteError::Io)?.len();
+                let page_end = self.page_start(page) + page_size as u64;
+                if page_end > file_len {
+                    let expected =
+                        self.page_start(1) + u64::from(self.page_count) * page_size as u64;
+                    return Err(RuSqliteError::Corrupt(format!(
+                        "page {} beyond end of file, file truncated? expected {} bytes but \
+                        the file has {}",
+                        page, expected, file_len
+                    ))
+                    .into());
+                }
+                buf.truncate(page_size);
+                self.file_reads += 1;
+                read_exact_at(&self.file, &mut buf, self.page_start(page))
+                    .map_err(RuSqliteError::Io)?;
+            }
+
+            // the requested page goes in last so it is the most recently used
+            let mut shared = self.shared_cache.lock().unwrap_or_else(|e| e.into_inner());
+            for (i, page_data) in buf.chunks_exact(page_size).enumerate().skip(1) {
+                let page_data = Arc::<[u8]>::from(page_data);
+                shared.insert(page + i as u32, page_data.clone());
+                self.page_cache.insert(page + i as u32, page_data);
+            }
+            buf.truncate(page_size);
+            let page_data = Arc::<[u8]>::from(buf);
+            shared.insert(page, page_data.clone());
+            self.page_cache.insert(page, page_data);
+        }
+
+        self.page_cache
+            .get(page)
+            .ok_or_else(|| format!("page {} is not cached", page).into())
+    }
+
+    // Number of reads issued to the file for pages since the database was opened. A cache miss
+    // costs one read for the page and any prefetched after it.
+    pub fn file_reads(&self) -> u64 {
+        self.file_reads
+    }
+
+    // The cache shared with the handles made by `reader`. Pages are only inserted whole, so the
+    // cache is still usable after a panic on another thread while it was locked.
+    fn shared_cache(&self) -> MutexGuard<'_, PageCache> {
+        self.shared_cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // A page already in the cache, without reading the file. Lets callers borrow page contents
+    // from `&self` after `read_page` has loaded them.
+    pub fn cached_page(&self, page: u32) -> Option<&[u8]> {
+        self.page_cache.peek(page)
+    }
+
+    pub fn schema_root(&self) -> u32 {
+        SCHEMA_ROOT_PAGE
+    }
+
+    pub fn storage_mode(&self) -> StorageMode {
+        let sidecar_present = |suffix: &str| {
+            if self.base_offset != 0 {
+                return false;
+            }
+            let mut sidecar = self.path.clone().into_os_string();
+            sidecar.push(suffix);
+            std::fs::metadata(sidecar).is_ok_and(|metadata| metadata.len() > 0)
+        };
+        match self.header.journal_mode() {
+            JournalMode::Wal => StorageMode::Wal {
+                wal_present: sidecar_present("-wal"),
+            },
+            JournalMode::Legacy => StorageMode::Rollback {
+                journal_present: sidecar_present("-journal"),
+            },
+            JournalMode::Unknown {
+                write_version,
+                read_version,
+            } => StorageMode::Unknown {
+                write_version,
+                read_version,
+            },
+        }
+    }
+
+    // True when the database was modified by a program that didn't update the SQLite version
+    // number and page count in the header (e.g. a legacy writer), so neither can be trusted
+    pub fn is_version_stale(&self) -> bool {
+        self.header.version_valid_for() != self.header.change_counter()
+    }
+
+    // The page holding the lock bytes at 1 GiB into the database, if the database reaches it.
+    // It counts towards the page count but is never part of a b-tree or the freelist.
+    pub fn lock_byte_page(&self) -> Option<u32> {
+        let page = (LOCK_BYTE_OFFSET / u64::from(self.page_size)) as u32 + 1;
+        (page <= self.page_count).then_some(page)
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    // Another handle on the same file with the same settings and collations, e.g. for a scan
+    // on another thread. Its own cache starts out empty but pages already read by this handle
+    // (or any other made by `reader`) come from the cache they share. It has no progress or
+    // warning callback.
+    pub fn reader(&self) -> Result<Database, Box<dyn Error>> {
+        Ok(Self {
+            file: self.file.try_clone().map_err(RuSqliteError::Io)?,
+            path: self.path.clone(),
+            header: self.header.clone(),
+            page_size: self.page_size,
+            page_count: self.page_count,
+            reserved_space: self.reserved_space,
+            freelist_trunk: self.freelist_trunk,
+            freelist_count: self.freelist_count,
+            page_cache: PageCache::new(self.page_cache.capacity()),
+            shared_cache: Arc::clone(&self.shared_cache),
+            prefetch: self.prefetch,
+            file_reads: 0,
+            header_only: self.header_only,
+            strict: self.strict,
+            collations: self.collations.clone(),
+            max_columns: self.max_columns,
+            base_offset: self.base_offset,
+            progress: ProgressReporter::default(),
+            warnings: WarningReporter::default(),
+            buffers: BufferPool::default(),
         })
     }
 }
 
-fn validate_db_file(header_str_arr: [u8; 16]) -> Result<(), InvalidDBFileError> {
-    if header_str_arr == HEADER_STRING_ARR {
-        Ok(())
-    } else {
-        Err(InvalidDBFileError::new())
+#[derive(Debug)]
+pub struct DatabaseBuilder {
+    path: PathBuf,
+    prefetch: u32,
+    cache_pages: usize,
+    strict: bool,
+    max_columns: usize,
+    expected_page_size: Option<u32>,
+}
+
+impl DatabaseBuilder {
+    pub fn new<P>(db_file: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            path: db_file.as_ref().to_path_buf(),
+            prefetch: 1,
+            cache_pages: DEFAULT_CACHE_PAGES,
+            strict: false,
+            max_columns: DEFAULT_MAX_COLUMNS,
+            expected_page_size: None,
+        }
+    }
+
+    // Read `n` consecutive pages per file read when a page misses the cache. Table scans visit
+    // leaf pages mostly in file order, so this cuts the number of reads on large tables.
+    pub fn prefetch(mut self, n: u32) -> Self {
+        self.prefetch = n.max(1);
+        self
+    }
+
+    // Keep at most `n` pages (at least one) in memory, evicting the least recently used page
+    // when the cache is full. Defaults to DEFAULT_CACHE_PAGES.
+    pub fn cache_pages(mut self, n: usize) -> Self {
+        self.cache_pages = n.max(1);
+        self
+    }
+
+    // Strict mode rejects files that violate the file format in ways SQLite tolerates, such as
+    // nonzero reserved header bytes. Lenient by default.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    // Upper bound on the number of columns read from a record header, guarding against corrupt
+    // headers that would otherwise claim an enormous number of columns
+    pub fn max_columns(mut self, n: usize) -> Self {
+        self.max_columns = n;
+        self
+    }
+
+    // Fail to open unless the database uses pages of `page_size` bytes. Knowing the size up
+    // front lets the header and page 1 be fetched in a single read, which saves a round-trip
+    // on backends where each read is expensive.
+    pub fn expected_page_size(mut self, page_size: u32) -> Self {
+        self.expected_page_size = Some(page_size);
+        self
+    }
+
+    pub fn open(self) -> Result<Database, Box<dyn Error>> {
+        let mut db = Database::open_file(&self.path, false, self.expected_page_size, 0)?;
+        db.prefetch = self.prefetch;
+        db.page_cache.set_capacity(self.cache_pages);
+        db.shared_cache().set_capacity(self.cache_pages);
+        db.strict = self.strict;
+        db.max_columns = self.max_columns;
+        if db.strict {
+            db.check_reserved_for_expansion()?;
+            db.check_payload_fractions()?;
+        }
+        Ok(db)
+    }
+}
+
+// Positional reads never move the file's cursor, so the handle can be shared between readers
+// without one read disturbing another
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            }
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn validate_schema_page(flag: u8) -> Result<(), InvalidSchemaPageError> {
+    match flag {
+        0x05 | 0x0d => Ok(()),
+        _ => Err(InvalidSchemaPageError::new(flag)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn schema_root_is_page_one() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        assert_eq!(fixture.open().schema_root(), 1);
+    }
+
+    #[test]
+    fn invalid_schema_page_type_is_rejected() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        fixture.patch(DB_HEADER_SIZE as u64, &[0x00]);
+        let err = Database::new(&fixture.path).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("page 1 is not a table b-tree page"));
+    }
+
+    // A table of 512 byte pages spread over at least ten leaves
+    fn many_leaves() -> Fixture {
+        Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 60)
+            INSERT INTO t SELECT i, printf('%0100d', i) FROM n;",
+        )
+    }
+
+    fn scan_reads(fixture: &Fixture, prefetch: u32) -> u64 {
+        let mut db = DatabaseBuilder::new(&fixture.path)
+            .prefetch(prefetch)
+            .open()
+            .unwrap();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert!(db.leaf_pages(root).unwrap().len() >= 10);
+        let before = db.file_reads();
+        let rows = crate::table::TableIterator::new(&mut db, root).count();
+        assert_eq!(rows, 60);
+        db.file_reads() - before
+    }
+
+    #[test]
+    fn prefetch_cuts_reads_of_a_full_scan() {
+        let fixture = many_leaves();
+        let unbatched = scan_reads(&fixture, 1);
+        let batched = scan_reads(&fixture, 8);
+        assert!(
+            batched * 4 <= unbatched,
+            "{} reads with prefetch 8, {} without",
+            batched,
+            unbatched
+        );
+    }
+
+    #[test]
+    fn page_cache_stays_within_capacity() {
+        let fixture = many_leaves();
+        let mut db = DatabaseBuilder::new(&fixture.path)
+            .cache_pages(3)
+            .prefetch(8)
+            .open()
+            .unwrap();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert_eq!(crate::table::TableIterator::new(&mut db, root).count(), 60);
+        assert!(db.page_cache.len() <= 3);
+        assert_eq!(db.page_cache.capacity(), 3);
+    }
+
+    #[test]
+    fn large_page_is_one_read() {
+        let fixture = Fixture::with_page_size(
+            32768,
+            "CREATE TABLE t (a); INSERT INTO t VALUES (zeroblob(20000));",
+        );
+        let mut db = fixture.open();
+        assert_eq!(db.page_size, 32768);
+        let before = db.file_reads();
+        assert_eq!(db.read_page(2).unwrap().len(), 32768);
+        assert_eq!(db.file_reads() - before, 1);
+        // served from the cache the second time
+        db.read_page(2).unwrap();
+        assert_eq!(db.file_reads() - before, 1);
+    }
+
+    #[test]
+    fn page_size_field_is_decoded() {
+        assert_eq!(Database::parse_page_size(512).unwrap(), 512);
+        assert_eq!(Database::parse_page_size(32768).unwrap(), 32768);
+        // 65536 doesn't fit in the field and is stored as 1
+        assert_eq!(Database::parse_page_size(1).unwrap(), 65536);
+        assert!(matches!(
+            Database::parse_page_size(1000),
+            Err(OpenError::BadPageSize(1000))
+        ));
+        assert!(matches!(
+            Database::parse_page_size(300),
+            Err(OpenError::BadPageSize(300))
+        ));
+    }
+
+    #[test]
+    fn bad_page_size_fails_open() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        fixture.patch(PG_SIZE.0 as u64, &1000u16.to_be_bytes());
+        let err = Database::new(&fixture.path).unwrap_err();
+        assert!(err.to_string().contains("invalid page size `1000`"));
+    }
+
+    #[test]
+    fn offsets_map_to_pages() {
+        let fixture = Fixture::new("CREATE TABLE t (a); CREATE TABLE u (b);");
+        let db = fixture.open();
+        // the database header belongs to page 1
+        assert_eq!(db.page_of_offset(0), 1);
+        assert_eq!(db.page_of_offset(99), 1);
+        assert_eq!(db.page_of_offset(4095), 1);
+        assert_eq!(db.page_of_offset(2 * 4096), 3);
+        assert_eq!(db.page_of_offset(3 * 4096 - 1), 3);
+        assert_eq!(db.page_start(1), 0);
+        assert_eq!(db.page_start(3), 2 * 4096);
+        assert_eq!(db.page_of_offset(db.page_start(3)), 3);
+    }
+
+    #[test]
+    fn header_only_open_of_a_bare_header() {
+        let fixture = Fixture::new("CREATE TABLE t (a); CREATE TABLE u (b);");
+        let header = Fixture::from_bytes(&fixture.bytes()[..DB_HEADER_SIZE]);
+        assert!(Database::new(&header.path).is_err());
+
+        let mut db = Database::open_header_only(&header.path).unwrap();
+        assert_eq!(db.page_size, 4096);
+        assert_eq!(db.page_count, 3);
+        let err = db.read_page(1).unwrap_err();
+        assert!(err.to_string().contains("header-only"));
+        assert!(db.read_schema().is_err());
+    }
+
+    #[test]
+    fn strict_mode_rejects_nonzero_reserved_bytes() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        fixture.patch(80, &[0x01]);
+        assert!(DatabaseBuilder::new(&fixture.path).open().is_ok());
+        let err = DatabaseBuilder::new(&fixture.path)
+            .strict(true)
+            .open()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RuSqliteError>(),
+            Some(RuSqliteError::Corrupt(details))
+                if details == "reserved header byte at offset 80 is 0x01, expected zero"
+        ));
+    }
+
+    #[test]
+    fn strict_mode_accepts_a_clean_file() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        let db = DatabaseBuilder::new(&fixture.path)
+            .strict(true)
+            .open()
+            .unwrap();
+        assert!(db.is_strict());
+    }
+
+    #[test]
+    fn max_columns_applies_to_row_reads() {
+        let fixture = Fixture::new("CREATE TABLE t (a, b, c); INSERT INTO t VALUES (1, 2, 3);");
+        assert!(fixture.open().lookup_rowid("t", 1).unwrap().is_some());
+        let mut db = DatabaseBuilder::new(&fixture.path)
+            .max_columns(2)
+            .open()
+            .unwrap();
+        let err = db.lookup_rowid("t", 1).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RuSqliteError>(),
+            Some(RuSqliteError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn expected_page_size_reads_page_one_with_the_header() {
+        let fixture = Fixture::with_page_size(1024, "CREATE TABLE t (a);");
+        let mut db = DatabaseBuilder::new(&fixture.path)
+            .expected_page_size(1024)
+            .open()
+            .unwrap();
+        assert!(db.cached_page(1).is_some());
+        db.read_page(1).unwrap();
+        assert_eq!(db.file_reads(), 0);
+        assert_eq!(db.read_schema().unwrap().len(), 1);
+
+        let err = DatabaseBuilder::new(&fixture.path)
+            .expected_page_size(4096)
+            .open()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<OpenError>(),
+            Some(OpenError::PageSizeMismatch {
+                expected: 4096,
+                actual: 1024
+            })
+        ));
+    }
+
+    #[test]
+    fn database_embedded_after_a_prefix() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 50)
+            INSERT INTO t SELECT i, printf('%0100d', i) FROM n;",
+        );
+        let mut bytes = vec![0xee; 777];
+        bytes.extend(fixture.bytes());
+        let embedded = Fixture::from_bytes(&bytes);
+
+        assert!(Database::new(&embedded.path).is_err());
+        let mut db = Database::open_at_offset(&embedded.path, 777).unwrap();
+        assert_eq!(db.page_start(1), 777);
+        assert_eq!(db.page_start(3), 777 + 2 * 512);
+        assert_eq!(db.page_of_offset(777 + 512), 2);
+        let row = db.lookup_rowid("t", 50).unwrap().unwrap();
+        assert_eq!(
+            row.values[1].as_text(),
+            Some(format!("{:0100}", 50).as_str())
+        );
+        assert_eq!(db.count_rows("t").unwrap(), 50);
+    }
+
+    #[test]
+    fn reopen_if_changed_drops_stale_pages() {
+        let fixture = Fixture::new("CREATE TABLE t (a); INSERT INTO t VALUES ('before');");
+        let mut db = fixture.open();
+        let before = db.lookup_rowid("t", 1).unwrap().unwrap();
+        assert_eq!(before.values[0].as_text(), Some("before"));
+        assert!(!db.reopen_if_changed().unwrap());
+
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        conn.execute_batch("UPDATE t SET a = 'after'; INSERT INTO t VALUES ('new');")
+            .unwrap();
+        let counter = db.change_counter();
+        assert!(db.reopen_if_changed().unwrap());
+        assert!(db.change_counter() > counter);
+        let after = db.lookup_rowid("t", 1).unwrap().unwrap();
+        assert_eq!(after.values[0].as_text(), Some("after"));
+        assert!(db.lookup_rowid("t", 2).unwrap().is_some());
+        assert!(!db.reopen_if_changed().unwrap());
+    }
+
+    #[test]
+    fn strict_mode_checks_payload_fractions() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        let db = fixture.open();
+        assert_eq!(
+            (
+                db.header.max_payload_fraction(),
+                db.header.min_payload_fraction(),
+                db.header.leaf_payload_fraction()
+            ),
+            (64, 32, 32)
+        );
+
+        fixture.patch(21, &[100]);
+        assert_eq!(fixture.open().header.max_payload_fraction(), 100);
+        let err = DatabaseBuilder::new(&fixture.path)
+            .strict(true)
+            .open()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RuSqliteError>(),
+            Some(RuSqliteError::Corrupt(details))
+                if details == "payload fractions are 100/32/32, expected 64/32/32"
+        ));
+    }
+
+    #[test]
+    fn storage_mode_of_a_wal_file_with_its_wal() {
+        let fixture = Fixture::new("PRAGMA journal_mode = WAL; CREATE TABLE t (a);");
+        // closing the last connection checkpoints and removes the -wal file
+        assert_eq!(
+            fixture.open().storage_mode(),
+            StorageMode::Wal { wal_present: false }
+        );
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        conn.execute_batch("INSERT INTO t VALUES (1);").unwrap();
+        assert_eq!(
+            fixture.open().storage_mode(),
+            StorageMode::Wal { wal_present: true }
+        );
+        drop(conn);
+    }
+
+    #[test]
+    fn storage_mode_of_a_rollback_file() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        assert_eq!(
+            fixture.open().storage_mode(),
+            StorageMode::Rollback {
+                journal_present: false
+            }
+        );
+        let mut journal = fixture.path.clone().into_os_string();
+        journal.push("-journal");
+        // an empty journal is what SQLite leaves after a commit with journal_mode=TRUNCATE
+        std::fs::write(&journal, b"").unwrap();
+        assert_eq!(
+            fixture.open().storage_mode(),
+            StorageMode::Rollback {
+                journal_present: false
+            }
+        );
+        std::fs::write(&journal, [0xd9; 512]).unwrap();
+        assert_eq!(
+            fixture.open().storage_mode(),
+            StorageMode::Rollback {
+                journal_present: true
+            }
+        );
+    }
+
+    #[test]
+    fn storage_mode_of_unknown_versions() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        // file format write and read versions
+        fixture.patch(18, &[3, 4]);
+        assert_eq!(
+            fixture.open().storage_mode(),
+            StorageMode::Unknown {
+                write_version: 3,
+                read_version: 4
+            }
+        );
+    }
+
+    #[test]
+    fn reserved_space_must_leave_480_usable_bytes() {
+        let fixture = Fixture::with_page_size(512, "CREATE TABLE t (a);");
+        // the reserved space byte of the header
+        fixture.patch(20, &[255]);
+        let err = Database::new(&fixture.path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            RuSqliteError::Corrupt(
+                "255 reserved bytes leave 257 usable bytes per 512 byte page, at least 480 are \
+                 needed"
+                    .to_owned()
+            )
+            .to_string()
+        );
+
+        fixture.patch(20, &[33]);
+        assert!(Database::new(&fixture.path).is_err());
+        fixture.patch(20, &[32]);
+        assert_eq!(Database::new(&fixture.path).unwrap().reserved_space, 32);
+    }
+
+    #[test]
+    fn database_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Database>();
+    }
+
+    #[test]
+    fn readers_share_the_page_cache() {
+        let fixture = many_leaves();
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let mut reader = db.reader().unwrap();
+        let leaves = reader.leaf_pages(root).unwrap();
+        assert!(reader.file_reads() > 10);
+
+        // the pages the reader read come from the shared cache, for the database it was made
+        // from as well as for readers of that reader
+        let before = db.file_reads();
+        let mut other = reader.reader().unwrap();
+        assert_eq!(db.leaf_pages(root).unwrap(), leaves);
+        assert_eq!(other.leaf_pages(root).unwrap(), leaves);
+        for &page in &leaves {
+            assert_eq!(
+                db.read_page(page).unwrap(),
+                reader.cached_page(page).unwrap()
+            );
+        }
+        assert_eq!(db.file_reads(), before);
+        assert_eq!(other.file_reads(), 0);
+    }
+
+    #[test]
+    fn readers_keep_registered_collations() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        let mut db = fixture.open();
+        db.register_collation("reverse", Arc::new(|a: &str, b: &str| b.cmp(a)));
+        let reader = db.reader().unwrap();
+        let (a, b) = (
+            crate::record::FieldData::Text("a".to_owned()),
+            crate::record::FieldData::Text("b".to_owned()),
+        );
+        assert_eq!(
+            reader.compare_values(&a, &b, "reverse").unwrap(),
+            std::cmp::Ordering::Greater
+        );
+        assert!(reader.compare_values(&a, &b, "missing").is_err());
+    }
+
+    #[test]
+    fn pages_past_the_end_of_a_truncated_file() {
+        let fixture = many_leaves();
+        let page_count = fixture.open().page_count;
+        // whole pages missing fail the file length check only when cut mid-page, so cut the
+        // file after opening it, half way into its second to last page
+        let mut db = DatabaseBuilder::new(&fixture.path)
+            .prefetch(4)
+            .open()
+            .unwrap();
+        let file_len = 512 * u64::from(page_count - 2) + 256;
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&fixture.path)
+            .unwrap();
+        file.set_len(file_len).unwrap();
+
+        for page in [page_count - 1, page_count] {
+            let err = db.read_page(page).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                RuSqliteError::Corrupt(format!(
+                    "page {} beyond end of file, file truncated? expected {} bytes but the file \
+                     has {}",
+                    page,
+                    512 * u64::from(page_count),
+                    file_len
+                ))
+                .to_string()
+            );
+        }
+        // a prefetch running past the end still reads the whole pages before it
+        let page = page_count - 3;
+        assert_eq!(db.read_page(page).unwrap().len(), 512);
+        assert_eq!(db.read_page(page - 1).unwrap().len(), 512);
+    }
+
+    #[test]
+    fn padded_file_is_not_a_whole_number_of_pages() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        let len = fixture.bytes().len();
+        fixture.append(&[0; 3]);
+        let err = Database::new(&fixture.path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            RuSqliteError::Corrupt(format!(
+                "file length not a multiple of page size: {} bytes with 4096 byte pages",
+                len + 3
+            ))
+            .to_string()
+        );
+        // only the header is read, and an embedded database may be followed by anything
+        assert!(Database::open_header_only(&fixture.path).is_ok());
+        let mut embedded = vec![0xee; 10];
+        embedded.extend(fixture.bytes());
+        let embedded = Fixture::from_bytes(&embedded);
+        assert!(Database::open_at_offset(&embedded.path, 10).is_ok());
     }
 }
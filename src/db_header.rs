@@ -0,0 +1,227 @@
+#![allow(dead_code)]
+
+use crate::{db::OpenError, helpers::read_u32_be};
+
+pub const DB_HEADER_SIZE: usize = 100;
+// the header string every database file starts with, the trailing NUL included
+pub const SQLITE_MAGIC: [u8; 16] = *b"SQLite format 3\0";
+// description (offset, size) per SQLite database header format
+const WRITE_VERSION: (usize, usize) = (18, 1);
+const READ_VERSION: (usize, usize) = (19, 1);
+const MAX_PAYLOAD_FRACTION: (usize, usize) = (21, 1);
+const MIN_PAYLOAD_FRACTION: (usize, usize) = (22, 1);
+const LEAF_PAYLOAD_FRACTION: (usize, usize) = (23, 1);
+const CHANGE_COUNTER: (usize, usize) = (24, 4);
+const DEFAULT_CACHE_SIZE: (usize, usize) = (48, 4);
+const LARGEST_ROOT_PAGE: (usize, usize) = (52, 4);
+const INCREMENTAL_VACUUM: (usize, usize) = (64, 4);
+const VERSION_VALID_FOR: (usize, usize) = (92, 4);
+
+// Journaling mode recorded in the file format write and read version fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    Legacy, // rollback journal, both versions are 1
+    Wal,    // write-ahead log, both versions are 2, changes may be pending in a `-wal` file
+    Unknown { write_version: u8, read_version: u8 },
+}
+
+// The 100-byte header at the start of the database file, with accessors for fields that aren't
+// needed to open the database
+#[derive(Debug, Clone)]
+pub struct DbHeader {
+    bytes: [u8; DB_HEADER_SIZE],
+}
+
+impl DbHeader {
+    pub fn new(bytes: [u8; DB_HEADER_SIZE]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; DB_HEADER_SIZE] {
+        &self.bytes
+    }
+
+    // Check the first 16 bytes of a database header, wherever in the file it starts
+    pub fn validate_magic(bytes: &[u8; 16]) -> Result<(), OpenError> {
+        if *bytes == SQLITE_MAGIC {
+            Ok(())
+        } else {
+            Err(OpenError::BadMagic)
+        }
+    }
+
+    pub fn journal_mode(&self) -> JournalMode {
+        match (self.bytes[WRITE_VERSION.0], self.bytes[READ_VERSION.0]) {
+            (1, 1) => JournalMode::Legacy,
+            (2, 2) => JournalMode::Wal,
+            (write_version, read_version) => JournalMode::Unknown {
+                write_version,
+                read_version,
+            },
+        }
+    }
+
+    // Payload fractions, out of 255, from which the overflow thresholds were once derived. The
+    // file format fixes them at 64, 32 and 32, which `calculate_spillage` assumes.
+    pub fn max_payload_fraction(&self) -> u8 {
+        self.bytes[MAX_PAYLOAD_FRACTION.0]
+    }
+
+    pub fn min_payload_fraction(&self) -> u8 {
+        self.bytes[MIN_PAYLOAD_FRACTION.0]
+    }
+
+    pub fn leaf_payload_fraction(&self) -> u8 {
+        self.bytes[LEAF_PAYLOAD_FRACTION.0]
+    }
+
+    // Incremented on every write transaction that modifies the database
+    pub fn change_counter(&self) -> u32 {
+        self.read_u32(CHANGE_COUNTER)
+    }
+
+    // Value of the change counter when the SQLite version number (offset 96) and in-header
+    // page count were last written
+    pub fn version_valid_for(&self) -> u32 {
+        self.read_u32(VERSION_VALID_FOR)
+    }
+
+    // Page number of the largest root b-tree page. Only set when auto_vacuum or
+    // incremental_vacuum is enabled (0 otherwise), pages past it are candidates for truncation.
+    pub fn largest_root_page(&self) -> u32 {
+        self.read_u32(LARGEST_ROOT_PAGE)
+    }
+
+    // Suggested page cache size set by `PRAGMA default_cache_size`, 0 if it was never set. A
+    // positive value is a number of pages and a negative one a size in KiB, although SQLite
+    // itself stores the magnitude of a negative setting.
+    pub fn default_cache_size(&self) -> i32 {
+        self.read_u32(DEFAULT_CACHE_SIZE) as i32
+    }
+
+    // Whether auto-vacuum runs incrementally (`PRAGMA auto_vacuum = incremental`), only
+    // meaningful when `largest_root_page` is set
+    pub fn incremental_vacuum(&self) -> bool {
+        self.read_u32(INCREMENTAL_VACUUM) != 0
+    }
+
+    // field offsets are constants inside the header so the read can't run out of bytes
+    fn read_u32(&self, field: (usize, usize)) -> u32 {
+        read_u32_be(&self.bytes[field.0..field.0 + field.1]).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, OpenError};
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn journal_mode_from_version_bytes() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        assert_eq!(fixture.open().header.journal_mode(), JournalMode::Legacy);
+
+        let fixture = Fixture::new("PRAGMA journal_mode = WAL; CREATE TABLE t (a);");
+        assert_eq!(fixture.open().header.journal_mode(), JournalMode::Wal);
+
+        fixture.patch(WRITE_VERSION.0 as u64, &[3, 1]);
+        assert_eq!(
+            fixture.open().header.journal_mode(),
+            JournalMode::Unknown {
+                write_version: 3,
+                read_version: 1
+            }
+        );
+    }
+
+    #[test]
+    fn largest_root_page_only_with_auto_vacuum() {
+        let fixture = Fixture::new(
+            "PRAGMA auto_vacuum = FULL;
+            CREATE TABLE t (a);
+            CREATE TABLE u (b);",
+        );
+        let db = fixture.open();
+        // page 2 is the pointer map, so the tables are rooted at pages 3 and 4
+        assert_eq!(db.header.largest_root_page(), 4);
+
+        let fixture = Fixture::new("CREATE TABLE t (a); CREATE TABLE u (b);");
+        assert_eq!(fixture.open().header.largest_root_page(), 0);
+    }
+
+    #[test]
+    fn version_stale_when_change_counter_moves_on() {
+        let fixture = Fixture::new("CREATE TABLE t (a); INSERT INTO t VALUES (1);");
+        let db = fixture.open();
+        assert!(db.header.change_counter() > 0);
+        assert_eq!(db.header.version_valid_for(), db.header.change_counter());
+        assert!(!db.is_version_stale());
+
+        // a legacy writer bumps the change counter alone
+        let counter = db.header.change_counter() + 1;
+        fixture.patch(CHANGE_COUNTER.0 as u64, &counter.to_be_bytes());
+        let db = fixture.open();
+        assert_eq!(db.header.change_counter(), counter);
+        assert!(db.is_version_stale());
+    }
+
+    #[test]
+    fn default_cache_size_and_incremental_vacuum() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        let db = fixture.open();
+        assert_eq!(db.header.default_cache_size(), 0);
+        assert!(!db.header.incremental_vacuum());
+
+        let fixture = Fixture::new(
+            "PRAGMA auto_vacuum = INCREMENTAL;
+            CREATE TABLE t (a);
+            PRAGMA default_cache_size = 500;",
+        );
+        let db = fixture.open();
+        assert_eq!(db.header.default_cache_size(), 500);
+        assert!(db.header.incremental_vacuum());
+
+        // SQLite keeps only the magnitude of a negative setting, a negative field is still
+        // read as one
+        let fixture = Fixture::new("CREATE TABLE t (a); PRAGMA default_cache_size = -4000;");
+        assert_eq!(fixture.open().header.default_cache_size(), 4000);
+        fixture.patch(DEFAULT_CACHE_SIZE.0 as u64, &(-2000i32).to_be_bytes());
+        assert_eq!(fixture.open().header.default_cache_size(), -2000);
+    }
+
+    #[test]
+    fn magic_must_match_exactly() {
+        assert!(DbHeader::validate_magic(&SQLITE_MAGIC).is_ok());
+        let mut no_nul = SQLITE_MAGIC;
+        no_nul[15] = b' ';
+        let mut lowercase = SQLITE_MAGIC;
+        lowercase[0] = b's';
+        for magic in [no_nul, lowercase, [0; 16]] {
+            let err = DbHeader::validate_magic(&magic).unwrap_err();
+            assert!(matches!(err, OpenError::BadMagic));
+        }
+
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        fixture.patch(15, b"4");
+        for err in [
+            Database::new(&fixture.path).unwrap_err(),
+            Database::open_header_only(&fixture.path).unwrap_err(),
+        ] {
+            assert_eq!(err.to_string(), "file is not a valid database");
+        }
+    }
+
+    #[test]
+    fn magic_of_an_embedded_database_at_its_offset() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        let mut bytes = b"prefix".to_vec();
+        bytes.extend(fixture.bytes());
+        let embedded = Fixture::from_bytes(&bytes);
+        assert!(Database::open_at_offset(&embedded.path, 6).is_ok());
+        for offset in [0, 5, 7] {
+            let err = Database::open_at_offset(&embedded.path, offset).unwrap_err();
+            assert_eq!(err.to_string(), "file is not a valid database");
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::io::Write;
+
+use crate::{db::Database, record::FieldData, table::TableIterator};
+
+// Write `value` as an SQL literal that reads back as the same value
+fn write_sql_literal(out: &mut impl Write, value: &FieldData) -> Result<(), Box<dyn Error>> {
+    match value {
+        FieldData::Null(_) => write!(out, "NULL")?,
+        // SQLite reads an out-of-range literal back as infinity, as `sqlite3 .dump` relies on
+        FieldData::Real(value) if value.is_infinite() => {
+            write!(out, "{}1e999", if *value < 0.0 { "-" } else { "" })?
+        }
+        // `{:?}` is the shortest form that parses back to the same f64
+        FieldData::Real(value) => write!(out, "{:?}", value)?,
+        FieldData::Text(text) => write!(out, "{}", quote(text, '\''))?,
+        FieldData::Blob(blob) => {
+            write!(out, "x'")?;
+            for byte in blob {
+                write!(out, "{:02x}", byte)?;
+            }
+            write!(out, "'")?;
+        }
+        value => write!(out, "{}", value.as_integer().unwrap_or_default())?,
+    }
+    Ok(())
+}
+
+// Wrap `text` in `quote` characters, doubling any it contains
+fn quote(text: &str, quote: char) -> String {
+    let doubled = format!("{}{}", quote, quote);
+    format!("{}{}{}", quote, text.replace(quote, &doubled), quote)
+}
+
+impl Database {
+    // Write one `INSERT INTO "table" VALUES(...);` line per row of `table`, like the row output
+    // of `sqlite3 .dump`
+    pub fn dump_inserts(
+        &mut self,
+        table: &str,
+        out: &mut impl Write,
+    ) -> Result<(), Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+        let insert = format!("INSERT INTO {} VALUES(", quote(&table_def.name, '"'));
+
+        for row in TableIterator::new(self, table_def.rootpage) {
+            let (row_id, mut record) = row?;
+            table_def.complete_record(row_id, &mut record);
+
+            write!(out, "{}", insert)?;
+            for (idx, value) in record.values.iter().enumerate() {
+                if idx > 0 {
+                    write!(out, ",")?;
+                }
+                write_sql_literal(out, value)?;
+            }
+            writeln!(out, ");")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::{types::Value, Connection};
+
+    use crate::test_util::Fixture;
+
+    const CREATE: &str = "CREATE TABLE \"it's \"\"odd\"\"\" (id INTEGER PRIMARY KEY, a, r REAL);";
+
+    fn rows(conn: &Connection) -> Vec<Vec<Value>> {
+        let mut stmt = conn
+            .prepare("SELECT id, a, r, typeof(a) FROM \"it's \"\"odd\"\"\" ORDER BY id")
+            .unwrap();
+        stmt.query_map([], |row| (0..4).map(|i| row.get(i)).collect())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn dumped_inserts_replay_to_the_same_rows() {
+        let fixture = Fixture::new(&format!(
+            "{}
+            INSERT INTO \"it's \"\"odd\"\"\" VALUES
+                (1, 'it''s', 7.0), (2, x'00ff10', 0.1), (3, NULL, 1e300), (4, -42, -1e999),
+                (5, 0.30000000000000004, 1e999), (-9, '', NULL), (6, 9223372036854775807, -0.5),
+                (7, 'line
+break \"quoted\"', 123456789.125);",
+            CREATE
+        ));
+        let mut out = vec![];
+        fixture
+            .open()
+            .dump_inserts("it's \"odd\"", &mut out)
+            .unwrap();
+        let sql = String::from_utf8(out).unwrap();
+        assert_eq!(sql.matches("INSERT INTO").count(), 8);
+        assert!(sql.contains("INSERT INTO \"it's \"\"odd\"\"\" VALUES(1,'it''s',7.0);\n"));
+        assert!(sql.contains("VALUES(2,x'00ff10',0.1);"));
+        assert!(sql.contains("VALUES(4,-42,-1e999);"));
+
+        let replayed = Connection::open_in_memory().unwrap();
+        replayed.execute_batch(CREATE).unwrap();
+        replayed.execute_batch(&sql).unwrap();
+        let original = Connection::open(&fixture.path).unwrap();
+        assert_eq!(rows(&replayed), rows(&original));
+    }
+}
@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// Lets callers tell a damaged file apart from a valid file using a feature this crate can't
+// read yet, and both apart from a failing read of the underlying file
+#[derive(Debug)]
+pub enum RuSqliteError {
+    Io(io::Error),
+    Corrupt(String),
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for RuSqliteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuSqliteError::Io(e) => write!(f, "I/O error: {}", e),
+            RuSqliteError::Corrupt(details) => {
+                write!(f, "database disk image is malformed: {}", details)
+            }
+            RuSqliteError::Unsupported(feature) => write!(f, "not supported yet: {}", feature),
+        }
+    }
+}
+
+impl Error for RuSqliteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RuSqliteError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RuSqliteError {
+    fn from(e: io::Error) -> Self {
+        RuSqliteError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::test_util::Fixture;
+
+    fn kind(err: Box<dyn Error>) -> Option<RuSqliteError> {
+        err.downcast::<RuSqliteError>().ok().map(|e| *e)
+    }
+
+    #[test]
+    fn without_rowid_lookups_are_unsupported() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (k TEXT PRIMARY KEY, v) WITHOUT ROWID;
+            CREATE INDEX t_v ON t (v);
+            INSERT INTO t VALUES ('a', 1);",
+        );
+        let mut db = fixture.open();
+        let err = db.lookup_rowid("t", 1).unwrap_err();
+        assert!(matches!(
+            kind(err),
+            Some(RuSqliteError::Unsupported("WITHOUT ROWID tables"))
+        ));
+        let err = db.count_rows("t").unwrap_err();
+        assert!(matches!(kind(err), Some(RuSqliteError::Unsupported(_))));
+        let err = db.index_entries("t_v").unwrap_err();
+        assert!(matches!(
+            kind(err),
+            Some(RuSqliteError::Unsupported(
+                "indexes of WITHOUT ROWID tables"
+            ))
+        ));
+    }
+
+    #[test]
+    fn missing_file_is_io_and_damage_is_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = Database::new(dir.path().join("missing.db")).unwrap_err();
+        assert!(matches!(kind(err), Some(RuSqliteError::Io(_))));
+
+        // table `t` is rooted at page 2, past the end of a database claiming a single page
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        fixture.patch(28, &1u32.to_be_bytes());
+        let mut db = fixture.open();
+        let err = db.read_schema().unwrap_err();
+        assert!(matches!(kind(err), Some(RuSqliteError::Corrupt(_))));
+    }
+}
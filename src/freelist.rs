@@ -0,0 +1,98 @@
+use std::error::Error;
+
+use crate::{db::Database, error::RuSqliteError, helpers::read_u32_be};
+
+impl Database {
+    // Every page on the freelist, trunk pages included. Each trunk page holds the next trunk
+    // page number, the number of leaf page numbers stored on it and then those leaf pages.
+    pub fn freelist_pages(&mut self) -> Result<Vec<u32>, Box<dyn Error>> {
+        if (self.freelist_trunk == 0) != (self.freelist_count == 0) {
+            return Err(RuSqliteError::Corrupt(format!(
+                "freelist trunk page is {} but freelist count is {}",
+                self.freelist_trunk, self.freelist_count
+            ))
+            .into());
+        }
+
+        let expected = self.freelist_count as usize;
+        let mut pages = vec![];
+        let mut trunk = self.freelist_trunk;
+        while trunk != 0 {
+            // stop early on trunk cycles or counts that would run past the header's total
+            if pages.len() >= expected {
+                break;
+            }
+            pages.push(trunk);
+            let page_data = self.read_page(trunk).map_err(|e| e.to_string())?;
+            let next_trunk = read_u32_be(page_data)?;
+            let num_leaves = read_u32_be(&page_data[4..])? as usize;
+            let leaves = page_data
+                .get(8..8 + num_leaves * 4)
+                .ok_or_else(|| {
+                    RuSqliteError::Corrupt(format!(
+                        "freelist trunk page {} claims {} leaf pages",
+                        trunk, num_leaves
+                    ))
+                })?
+                .chunks_exact(4)
+                .map(read_u32_be)
+                .collect::<Result<Vec<u32>, _>>()?;
+            pages.extend(leaves);
+            trunk = next_trunk;
+        }
+
+        if pages.len() != expected {
+            return Err(RuSqliteError::Corrupt(format!(
+                "freelist count is {} but the freelist chain holds {} pages",
+                expected,
+                pages.len()
+            ))
+            .into());
+        }
+        Ok(pages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::Fixture;
+
+    // a dropped table leaves its pages on the freelist
+    fn with_free_pages() -> Fixture {
+        Fixture::with_page_size(
+            512,
+            "CREATE TABLE keep (a);
+            CREATE TABLE t (a);
+            INSERT INTO t VALUES (zeroblob(3000));
+            DROP TABLE t;",
+        )
+    }
+
+    #[test]
+    fn lists_free_pages() {
+        let fixture = with_free_pages();
+        let mut db = fixture.open();
+        let pages = db.freelist_pages().unwrap();
+        assert_eq!(pages.len(), db.freelist_count as usize);
+        assert!(!pages.is_empty());
+        assert_eq!(pages[0], db.freelist_trunk);
+    }
+
+    #[test]
+    fn trunk_without_count_is_corrupt() {
+        let fixture = with_free_pages();
+        fixture.patch(36, &0u32.to_be_bytes());
+        let err = fixture.open().freelist_pages().unwrap_err();
+        assert!(err.to_string().contains("but freelist count is 0"));
+    }
+
+    #[test]
+    fn count_without_trunk_is_corrupt() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        fixture.patch(36, &3u32.to_be_bytes());
+        let err = fixture.open().freelist_pages().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("freelist trunk page is 0 but freelist count is 3"));
+    }
+}
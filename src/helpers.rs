@@ -0,0 +1,93 @@
+#![allow(dead_code)]
+
+use std::error::Error;
+use std::fmt;
+
+// SQLite stores every multi-byte integer big-endian on disk regardless of the host, so all
+// fixed-width reads go through these helpers instead of ad hoc `from_be_bytes` calls.
+
+#[derive(Debug)]
+pub struct BufferTooShortError {
+    details: String,
+}
+
+impl BufferTooShortError {
+    fn new(expected: usize, found: usize) -> Self {
+        Self {
+            details: format!(
+                "buffer too short: expected at least {} bytes but found {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl fmt::Display for BufferTooShortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for BufferTooShortError {}
+
+fn read_array<const N: usize>(buf: &[u8]) -> Result<[u8; N], BufferTooShortError> {
+    buf.get(..N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| BufferTooShortError::new(N, buf.len()))
+}
+
+// Read a big-endian u16 from the first 2 bytes of `buf`
+pub fn read_u16_be(buf: &[u8]) -> Result<u16, BufferTooShortError> {
+    Ok(u16::from_be_bytes(read_array(buf)?))
+}
+
+// Read a big-endian u32 from the first 4 bytes of `buf`
+pub fn read_u32_be(buf: &[u8]) -> Result<u32, BufferTooShortError> {
+    Ok(u32::from_be_bytes(read_array(buf)?))
+}
+
+// Read a big-endian 48-bit unsigned integer from the first 6 bytes of `buf`
+pub fn read_u48_be(buf: &[u8]) -> Result<u64, BufferTooShortError> {
+    let bytes: [u8; 6] = read_array(buf)?;
+    let mut padded = [0u8; 8];
+    padded[2..].copy_from_slice(&bytes);
+    Ok(u64::from_be_bytes(padded))
+}
+
+// Read a big-endian u64 from the first 8 bytes of `buf`
+pub fn read_u64_be(buf: &[u8]) -> Result<u64, BufferTooShortError> {
+    Ok(u64::from_be_bytes(read_array(buf)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BYTES: [u8; 9] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09];
+
+    #[test]
+    fn reads_each_width_big_endian() {
+        assert_eq!(read_u16_be(&BYTES).unwrap(), 0x0102);
+        assert_eq!(read_u32_be(&BYTES).unwrap(), 0x0102_0304);
+        assert_eq!(read_u48_be(&BYTES).unwrap(), 0x0102_0304_0506);
+        assert_eq!(read_u64_be(&BYTES).unwrap(), 0x0102_0304_0506_0708);
+    }
+
+    #[test]
+    fn u48_keeps_the_top_bit() {
+        let bytes = [0xff, 0xff, 0xff, 0xff, 0xff, 0xfe];
+        assert_eq!(read_u48_be(&bytes).unwrap(), 0xffff_ffff_fffe);
+    }
+
+    #[test]
+    fn short_buffers_are_errors() {
+        assert!(read_u16_be(&BYTES[..1]).is_err());
+        assert!(read_u32_be(&BYTES[..3]).is_err());
+        assert!(read_u48_be(&BYTES[..5]).is_err());
+        let err = read_u64_be(&BYTES[..7]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "buffer too short: expected at least 8 bytes but found 7"
+        );
+    }
+}
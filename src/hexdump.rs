@@ -0,0 +1,173 @@
+use std::error::Error;
+use std::io::Write;
+
+use crate::{btree_page::BtreePage, db::Database, db_header::DB_HEADER_SIZE};
+
+// Bytes shown per line of a dump
+const BYTES_PER_LINE: usize = 16;
+
+// Where the structures of a b-tree page start, as (offset, description) in offset order
+fn page_regions(btree_pg: &BtreePage) -> Vec<(usize, String)> {
+    let mut regions = vec![];
+    let header_start = if btree_pg.page_num == 1 {
+        regions.push((0, "database header".to_owned()));
+        DB_HEADER_SIZE
+    } else {
+        0
+    };
+    let ptr_array_start = header_start + usize::from(btree_pg.header_size);
+    regions.push((
+        header_start,
+        format!("b-tree header ({:?})", btree_pg.page_type),
+    ));
+    if btree_pg.num_cells > 0 {
+        let pointers = btree_pg
+            .cell_pointers
+            .iter()
+            .map(|ptr| format!("{:04x}", ptr))
+            .collect::<Vec<_>>();
+        regions.push((
+            ptr_array_start,
+            format!("cell pointer array: {}", pointers.join(" ")),
+        ));
+    }
+    let unallocated = btree_pg.unallocated_region();
+    if !unallocated.is_empty() {
+        regions.push((unallocated.start as usize, "unallocated space".to_owned()));
+    }
+    regions.push((
+        btree_pg.content_area_start() as usize,
+        "cell content area".to_owned(),
+    ));
+    regions
+}
+
+impl Database {
+    // Write the bytes of `page_no` as a classic hex dump: the offset within the page, 16 bytes
+    // in hex and the same bytes as ASCII. Lines on which a part of a b-tree page starts (the
+    // headers, the cell pointer array and the content area) are annotated with it, and runs
+    // of identical unannotated lines are collapsed into a single `*`.
+    pub fn hexdump_page(
+        &mut self,
+        page_no: u32,
+        out: &mut impl Write,
+    ) -> Result<(), Box<dyn Error>> {
+        // pages that aren't b-tree pages (overflow, freelist, ...) are dumped unannotated
+        let regions = match BtreePage::at(self, page_no, None) {
+            Ok(btree_pg) => page_regions(&btree_pg),
+            Err(_) => vec![],
+        };
+        let data = self.read_page(page_no)?;
+
+        let mut previous: Option<&[u8]> = None;
+        let mut collapsed = false;
+        for (line_no, line) in data.chunks(BYTES_PER_LINE).enumerate() {
+            let offset = line_no * BYTES_PER_LINE;
+            let notes = regions
+                .iter()
+                .filter(|(start, _)| (offset..offset + BYTES_PER_LINE).contains(start))
+                .map(|(start, description)| format!("{:04x}: {}", start, description))
+                .collect::<Vec<_>>();
+            if notes.is_empty() && previous == Some(line) {
+                if !collapsed {
+                    writeln!(out, "*")?;
+                    collapsed = true;
+                }
+                continue;
+            }
+            previous = Some(line);
+            collapsed = false;
+
+            write!(out, "{:04x}  ", offset)?;
+            for (idx, byte) in line.iter().enumerate() {
+                let separator = if idx == BYTES_PER_LINE / 2 { " " } else { "" };
+                write!(out, "{}{:02x} ", separator, byte)?;
+            }
+            let ascii = line
+                .iter()
+                .map(|byte| match byte {
+                    0x20..=0x7e => *byte as char,
+                    _ => '.',
+                })
+                .collect::<String>();
+            write!(out, " |{}|", ascii)?;
+            if !notes.is_empty() {
+                write!(out, "  {}", notes.join("; "))?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out, "{:04x}", data.len())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    fn dump_page(db: &mut Database, page_no: u32) -> String {
+        let mut out = vec![];
+        db.hexdump_page(page_no, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn dump_lines_show_offset_hex_and_ascii() {
+        let fixture = Fixture::with_page_size(512, "CREATE TABLE t (a);");
+        let mut db = fixture.open();
+        let dump = dump_page(&mut db, 1);
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "0000  53 51 4c 69 74 65 20 66  6f 72 6d 61 74 20 33 00  |SQLite format 3.|  \
+             0000: database header"
+        );
+        // the page size (512), the format versions and the payload fractions, shown as ASCII
+        // where they are printable
+        assert!(lines[1].starts_with("0010  02 00 01 01 00 40 20 20  "));
+        assert!(lines[1].contains("  |.....@  "));
+        // the zeros between the pointer array and the cell content are collapsed
+        assert!(lines.contains(&"*"));
+        assert_eq!(lines.last(), Some(&"0200"));
+        let hex_lines = lines.iter().filter(|line| line.len() > 4).count();
+        assert!(hex_lines < 512 / BYTES_PER_LINE);
+    }
+
+    #[test]
+    fn btree_structures_are_annotated() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (a);
+            INSERT INTO t VALUES ('hello'), (zeroblob(2000));",
+        );
+        let mut db = fixture.open();
+        let btree_pg = BtreePage::at(&mut db, 2, None).unwrap();
+        let pointers = btree_pg
+            .cell_pointers
+            .iter()
+            .map(|ptr| format!("{:04x}", ptr))
+            .collect::<Vec<_>>();
+        let dump = dump_page(&mut db, 2);
+        let first = dump.lines().next().unwrap();
+        assert!(first.starts_with("0000  0d 00 00 00 02 "));
+        assert!(first.ends_with(&format!(
+            "0000: b-tree header (LeafTable); 0008: cell pointer array: {}; \
+             000c: unallocated space",
+            pointers.join(" ")
+        )));
+        let content = format!("{:04x}: cell content area", btree_pg.content_area_start());
+        let content_line = dump.lines().find(|line| line.contains(&content)).unwrap();
+        assert!(content_line.starts_with(&format!(
+            "{:04x}  ",
+            btree_pg.content_area_start() as usize / BYTES_PER_LINE * BYTES_PER_LINE
+        )));
+        assert!(dump.contains("hello|"));
+
+        // page 3 is the overflow page of the blob, dumped without any annotations
+        let overflow = dump_page(&mut db, 3);
+        assert!(overflow.starts_with("0000  00 00 00 04 00 00 00 00  "));
+        assert!(!overflow.contains(": "));
+        assert!(db.hexdump_page(9, &mut vec![]).is_err());
+    }
+}
@@ -0,0 +1,587 @@
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::error::Error;
+
+use crate::{
+    btree_page::{BtreePage, PageType},
+    cell::{Cell, CellContent},
+    db::Database,
+    error::RuSqliteError,
+    record::{FieldData, Record},
+    schema::SchemaEntry,
+    table_def::{index_columns, TableDef},
+};
+
+// An entry of an index on a rowid table: the values of the indexed columns followed, as the
+// last column of the record, by the row_id of the table row they belong to
+#[derive(Debug)]
+pub struct IndexEntry {
+    pub key: Vec<FieldData>,
+    pub rowid: i64,
+}
+
+impl IndexEntry {
+    pub fn from_record(mut record: Record) -> Result<Self, Box<dyn Error>> {
+        let rowid = record
+            .values
+            .pop()
+            .and_then(|value| value.as_integer())
+            .ok_or_else(|| {
+                RuSqliteError::Corrupt("index entry doesn't end with a rowid".to_owned())
+            })?;
+        Ok(Self {
+            key: record.values,
+            rowid,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum IndexStep {
+    Page(u32),
+    Entry(Record),
+}
+
+// Iterates the entries of an index b-tree in key order. Interior index pages hold entries too,
+// each one ordered between the subtree of its left child and the subtree that follows it.
+#[derive(Debug)]
+pub struct IndexIterator<'a> {
+    db: &'a mut Database,
+    root_page: u32,
+    pending: Vec<IndexStep>, // steps left to take, the next one is at the end
+    visited: HashSet<u32>,   // pages loaded so far, to stop at the first loop in the b-tree
+}
+
+impl<'a> IndexIterator<'a> {
+    pub fn new(db: &'a mut Database, root_page: u32) -> Self {
+        Self {
+            db,
+            root_page,
+            pending: vec![IndexStep::Page(root_page)],
+            visited: HashSet::new(),
+        }
+    }
+
+    fn load_page(&mut self, page: u32) -> Result<(), Box<dyn Error>> {
+        if !self.visited.insert(page) {
+            // nothing past a loop can be trusted, so the iteration ends here
+            self.pending.clear();
+            return Err(RuSqliteError::Corrupt(format!(
+                "page {} is referenced more than once in the b-tree rooted at page {}",
+                page, self.root_page
+            ))
+            .into());
+        }
+        let btree_pg = BtreePage::at(self.db, page, None)?;
+        let mut steps = vec![];
+        match btree_pg.page_type {
+            PageType::LeafIndex => {
+                for cell in btree_pg.get_page_cells() {
+                    steps.push(IndexStep::Entry(self.read_entry(&btree_pg, cell)?));
+                }
+            }
+            PageType::InteriorIndex => {
+                for cell in btree_pg.get_page_cells() {
+                    let left_child = CellContent::get_cell_data(&btree_pg, self.db, cell)?
+                        .get_left_child_pointer()?;
+                    steps.push(IndexStep::Page(left_child));
+                    steps.push(IndexStep::Entry(self.read_entry(&btree_pg, cell)?));
+                }
+                steps.extend(btree_pg.rightmost_ptr.map(IndexStep::Page));
+            }
+            _ => return Err(format!("page {} is not an index b-tree page", page).into()),
+        }
+        self.pending.extend(steps.into_iter().rev());
+        Ok(())
+    }
+
+    fn read_entry(&mut self, btree_pg: &BtreePage, cell: Cell) -> Result<Record, Box<dyn Error>> {
+        read_index_record(self.db, btree_pg, cell)
+    }
+}
+
+// Record of an index cell, overflow included. Keys that spill from an interior or leaf cell
+// are always completed here, so seeks never compare against the truncated local bytes.
+fn read_index_record(
+    db: &mut Database,
+    btree_pg: &BtreePage,
+    cell: Cell,
+) -> Result<Record, Box<dyn Error>> {
+    match CellContent::get_cell_data(btree_pg, db, cell)? {
+        CellContent::LeafIndex { mut payload, .. }
+        | CellContent::InteriorIndex { mut payload, .. } => {
+            payload.read_overflow(db)?;
+            let record = Record::parse_with_limit(&payload.payload, db.max_columns);
+            db.buffers.give_back(payload.payload);
+            record
+        }
+        _ => Err(format!("page {} is not an index b-tree page", btree_pg.page_num).into()),
+    }
+}
+
+impl<'a> IndexIterator<'a> {
+    // Yield the records of the entries as stored, without splitting off a trailing rowid. The
+    // rows of a WITHOUT ROWID table are the entries of its index b-tree.
+    pub fn records(mut self) -> impl Iterator<Item = Result<Record, Box<dyn Error>>> + 'a {
+        std::iter::from_fn(move || self.next_record())
+    }
+
+    fn next_record(&mut self) -> Option<Result<Record, Box<dyn Error>>> {
+        loop {
+            match self.pending.pop()? {
+                IndexStep::Entry(record) => return Some(Ok(record)),
+                IndexStep::Page(page) => {
+                    if let Err(e) = self.load_page(page) {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for IndexIterator<'_> {
+    type Item = Result<IndexEntry, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.next_record()?;
+        Some(record.and_then(IndexEntry::from_record))
+    }
+}
+
+impl Database {
+    // Iterate the entries of the index named `index`. Indexes of WITHOUT ROWID tables end with
+    // the table's primary key instead of a rowid and aren't supported.
+    pub fn index_entries(&mut self, index: &str) -> Result<IndexIterator<'_>, Box<dyn Error>> {
+        let (entry, _) = self.index_schema(index)?;
+        Ok(IndexIterator::new(self, entry.rootpage))
+    }
+
+    // Answer a query for `columns` from the entries of `index` alone, without reading the
+    // table. Every column must be one of the indexed columns or the table's rowid alias.
+    pub fn index_scan(
+        &mut self,
+        index: &str,
+        columns: &[&str],
+    ) -> Result<Vec<Vec<FieldData>>, Box<dyn Error>> {
+        let (entry, table_def) = self.index_schema(index)?;
+        let key_columns = entry
+            .sql
+            .as_deref()
+            .and_then(index_columns)
+            .ok_or_else(|| format!("can't determine the columns of index {}", index))?;
+
+        // position of each requested column in the index key, None for the rowid
+        let projection = columns
+            .iter()
+            .map(|column| {
+                let table_column = table_def.column_index(column);
+                match key_columns
+                    .iter()
+                    .position(|key| key.name.eq_ignore_ascii_case(column))
+                {
+                    Some(position) => Ok(Some(position)),
+                    None if table_column.is_some() && table_column == table_def.rowid_alias => {
+                        Ok(None)
+                    }
+                    None => Err(format!(
+                        "column {} is not covered by index {}",
+                        column, index
+                    )),
+                }
+            })
+            .collect::<Result<Vec<Option<usize>>, String>>()?;
+
+        IndexIterator::new(self, entry.rootpage)
+            .map(|index_entry| {
+                let index_entry = index_entry?;
+                Ok(projection
+                    .iter()
+                    .map(|position| match position {
+                        Some(position) => index_entry
+                            .key
+                            .get(*position)
+                            .cloned()
+                            .unwrap_or(FieldData::Null(())),
+                        None => FieldData::Integer(index_entry.rowid),
+                    })
+                    .collect())
+            })
+            .collect()
+    }
+
+    // Find the table row whose leading index columns equal `key` by seeking `index`, then
+    // fetching the row by its rowid. Meant for unique indexes: with duplicate keys, any one of
+    // the matching rows is returned.
+    pub fn lookup_by_index(
+        &mut self,
+        index: &str,
+        key: &[FieldData],
+    ) -> Result<Option<Record>, Box<dyn Error>> {
+        let (entry, table_def) = self.index_schema(index)?;
+        let key_columns = entry
+            .sql
+            .as_deref()
+            .and_then(index_columns)
+            .ok_or_else(|| format!("can't determine the columns of index {}", index))?;
+        if key.len() > key_columns.len() {
+            return Err(format!("index {} has only {} columns", index, key_columns.len()).into());
+        }
+
+        // (collation, descending) of each key column, collations default to the table's
+        let ordering = key_columns
+            .iter()
+            .take(key.len())
+            .map(|column| {
+                let collation = column.collation.clone().or_else(|| {
+                    let table_column = &table_def.columns[table_def.column_index(&column.name)?];
+                    table_column.collation().map(str::to_owned)
+                });
+                (
+                    collation.unwrap_or_else(|| "BINARY".to_owned()),
+                    column.descending,
+                )
+            })
+            .collect::<Vec<_>>();
+        // search values are converted to the affinity of the columns they are compared with,
+        // so e.g. '42' finds the integer 42 in an INTEGER column
+        let key = key_columns
+            .iter()
+            .zip(key)
+            .map(
+                |(column, value)| match table_def.column_index(&column.name) {
+                    Some(idx) => value
+                        .clone()
+                        .apply_affinity(table_def.columns[idx].affinity()),
+                    None => value.clone(),
+                },
+            )
+            .collect::<Vec<_>>();
+        let compare_key = |db: &Database, values: &[FieldData]| {
+            for ((value, wanted), (collation, descending)) in values.iter().zip(&key).zip(&ordering)
+            {
+                let order = db.compare_values(value, wanted, collation)?;
+                if order.is_ne() {
+                    return Ok(if *descending { order.reverse() } else { order });
+                }
+            }
+            Ok::<Ordering, Box<dyn Error>>(Ordering::Equal)
+        };
+
+        let mut page = entry.rootpage;
+        for _ in 0..self.page_count {
+            let btree_pg = BtreePage::at(self, page, None)?;
+            let cells = btree_pg.get_page_cells();
+
+            // first cell whose key is not ordered before `key`, and whether it matches
+            let (mut low, mut high) = (0, cells.len());
+            let mut found = None;
+            while low < high {
+                let mid = low + (high - low) / 2;
+                let record = read_index_record(self, &btree_pg, cells[mid])?;
+                match compare_key(self, &record.values)? {
+                    Ordering::Less => low = mid + 1,
+                    Ordering::Equal => {
+                        found = Some(record);
+                        break;
+                    }
+                    Ordering::Greater => high = mid,
+                }
+            }
+
+            // entries of interior pages are index entries too
+            if let Some(record) = found {
+                let rowid = IndexEntry::from_record(record)?.rowid;
+                return self.lookup_rowid(&table_def.name, rowid);
+            }
+            match btree_pg.page_type {
+                PageType::LeafIndex => return Ok(None),
+                PageType::InteriorIndex => {
+                    page = match cells.get(low) {
+                        Some(cell) => CellContent::get_cell_data(&btree_pg, self, *cell)?
+                            .get_left_child_pointer()?,
+                        None => btree_pg.rightmost_ptr.unwrap_or_default(),
+                    };
+                }
+                _ => return Err(format!("page {} is not an index b-tree page", page).into()),
+            }
+        }
+        Err(RuSqliteError::Corrupt(format!(
+            "b-tree of index {} is deeper than the database",
+            index
+        ))
+        .into())
+    }
+
+    // Schema entry of the index named `index` and the definition of the table it indexes
+    fn index_schema(&mut self, index: &str) -> Result<(SchemaEntry, TableDef), Box<dyn Error>> {
+        let schema = self.read_schema()?;
+        let entry = schema
+            .iter()
+            .find(|entry| entry.entry_type == "index" && entry.name.eq_ignore_ascii_case(index))
+            .ok_or_else(|| format!("no such index: {}", index))?;
+        let table_def = TableDef::from_schema(&schema, &entry.tbl_name)?;
+        if table_def.without_rowid {
+            return Err(RuSqliteError::Unsupported("indexes of WITHOUT ROWID tables").into());
+        }
+        Ok((entry.clone(), table_def))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_util::Fixture;
+
+    // `t (id INTEGER PRIMARY KEY, name TEXT, n INTEGER)` on 512 byte pages, indexed on name
+    // by `t_name` with duplicate names, deep enough for interior index pages
+    fn named_rows() -> Fixture {
+        Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, n INTEGER);
+            CREATE INDEX t_name ON t (name);
+            WITH RECURSIVE c(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM c WHERE i < 500)
+            INSERT INTO t SELECT i * 3, printf('name-%040d', (i * 7919) % 200), i FROM c;",
+        )
+    }
+
+    #[test]
+    fn entries_in_index_order() {
+        let fixture = named_rows();
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        let expected = conn
+            .prepare("SELECT name, id FROM t ORDER BY name, id")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let mut db = fixture.open();
+        let (entry, _) = db.index_schema("t_name").unwrap();
+        assert!(db.btree_depth(entry.rootpage).unwrap() > 1);
+        let entries = db
+            .index_entries("t_name")
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                assert_eq!(entry.key.len(), 1);
+                (entry.key[0].as_text().unwrap().to_owned(), entry.rowid)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 500);
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn index_scan_projects_key_columns_and_rowid() {
+        let fixture = named_rows();
+        let mut db = fixture.open();
+        let rows = db.index_scan("t_name", &["id", "name"]).unwrap();
+        assert_eq!(rows.len(), 500);
+        let entries = db
+            .index_entries("t_name")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        for (row, entry) in rows.iter().zip(entries) {
+            assert_eq!(row[0], FieldData::Integer(entry.rowid));
+            assert_eq!(row[1], entry.key[0]);
+        }
+
+        let err = db.index_scan("t_name", &["n"]).unwrap_err();
+        assert_eq!(err.to_string(), "column n is not covered by index t_name");
+        assert!(db.index_scan("no_such_index", &["name"]).is_err());
+    }
+
+    #[test]
+    fn lookup_by_unique_email_index() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT COLLATE NOCASE, age INTEGER);
+            CREATE UNIQUE INDEX users_email ON users (email);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300)
+            INSERT INTO users SELECT i * 1000, printf('user%03d@example.com', i), i FROM n;
+            INSERT INTO users VALUES (-42, 'negative@example.com', 99),
+                (9000000000000, 'big@example.com', 7);",
+        );
+        let mut db = fixture.open();
+        let (entry, _) = db.index_schema("users_email").unwrap();
+        assert!(db.btree_depth(entry.rootpage).unwrap() > 1);
+
+        for (email, id, age) in [
+            ("user001@example.com", 1000, 1),
+            ("user150@example.com", 150_000, 150),
+            ("USER300@EXAMPLE.COM", 300_000, 300),
+            ("negative@example.com", -42, 99),
+            ("big@example.com", 9_000_000_000_000, 7),
+        ] {
+            let row = db
+                .lookup_by_index("users_email", &[FieldData::Text(email.to_owned())])
+                .unwrap()
+                .unwrap_or_else(|| panic!("{} not found", email));
+            assert_eq!(row.values[0], FieldData::Integer(id));
+            assert_eq!(row.values[2].as_integer(), Some(age));
+        }
+        let missing = [FieldData::Text("nobody@example.com".to_owned())];
+        assert!(db
+            .lookup_by_index("users_email", &missing)
+            .unwrap()
+            .is_none());
+
+        let too_long = [FieldData::Null(()), FieldData::Null(())];
+        assert!(db.lookup_by_index("users_email", &too_long).is_err());
+    }
+
+    #[test]
+    fn long_keys_spilling_from_index_cells() {
+        // on 512 byte pages at most 102 bytes of an index key stay in its cell, and these keys
+        // share far longer prefixes of zeros, only telling apart by their spilled tail
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, k TEXT);
+            CREATE UNIQUE INDEX t_k ON t (k);
+            WITH RECURSIVE c(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM c WHERE i < 399)
+            INSERT INTO t SELECT i, printf('%0*d', 300 + (i % 4) * 500, (i * 7919) % 400)
+            FROM c;",
+        );
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        let expected = conn
+            .prepare("SELECT k, id FROM t ORDER BY k")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        drop(conn);
+
+        let mut db = fixture.open();
+        let (entry, _) = db.index_schema("t_k").unwrap();
+        assert!(db.btree_depth(entry.rootpage).unwrap() > 2);
+        let entries = db
+            .index_entries("t_k")
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.key[0].as_text().unwrap().to_owned(), entry.rowid)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(entries, expected);
+
+        for (key, id) in &expected {
+            let row = db
+                .lookup_by_index("t_k", &[FieldData::Text(key.clone())])
+                .unwrap()
+                .unwrap_or_else(|| panic!("key of row {} not found", id));
+            assert_eq!(row.values[0], FieldData::Integer(*id));
+        }
+        // same length and prefix as the stored keys, but a tail none of them has
+        for missing in [format!("{:0300}", 401), format!("{:01800}", 999)] {
+            assert!(db
+                .lookup_by_index("t_k", &[FieldData::Text(missing)])
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn seek_keys_take_the_affinity_of_their_column() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER, r REAL, s TEXT, u);
+            CREATE INDEX t_n ON t (n);
+            CREATE INDEX t_r ON t (r);
+            CREATE INDEX t_s ON t (s);
+            CREATE INDEX t_u ON t (u);
+            INSERT INTO t VALUES (1, 42, 2.5, '42', 42), (2, 420, 3, 'x', 'y');",
+        );
+        let mut db = fixture.open();
+        let text = |value: &str| FieldData::Text(value.to_owned());
+        for (index, key, id) in [
+            ("t_n", text("42"), 1),
+            ("t_n", text(" 420 "), 2),
+            ("t_n", FieldData::Real(42.0), 1),
+            ("t_r", text("2.5"), 1),
+            ("t_r", text("3"), 2),
+            ("t_r", FieldData::Integer(3), 2),
+            ("t_s", FieldData::Integer(42), 1),
+            ("t_u", FieldData::Integer(42), 1),
+        ] {
+            let row = db
+                .lookup_by_index(index, std::slice::from_ref(&key))
+                .unwrap()
+                .unwrap_or_else(|| panic!("{:?} not found in {}", key, index));
+            assert_eq!(row.values[0], FieldData::Integer(id));
+        }
+        // columns without a declared type compare values as they are
+        assert!(db.lookup_by_index("t_u", &[text("42")]).unwrap().is_none());
+        assert!(db.lookup_by_index("t_n", &[text("42x")]).unwrap().is_none());
+    }
+
+    #[test]
+    fn lookup_compares_keys_with_a_registered_collation() {
+        let reverse = |a: &str, b: &str| b.cmp(a);
+        let fixture = Fixture::build(512, |conn| {
+            conn.create_collation("reverse", reverse).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT);
+                CREATE INDEX t_name ON t (name COLLATE reverse);
+                WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 300)
+                INSERT INTO t SELECT i, printf('name-%05d', i) FROM n;",
+            )
+            .unwrap();
+        });
+        let key = |i: i64| [FieldData::Text(format!("name-{:05}", i))];
+
+        let mut db = fixture.open();
+        let err = db.lookup_by_index("t_name", &key(1)).unwrap_err();
+        assert_eq!(err.to_string(), "no such collation sequence: reverse");
+
+        db.register_collation("reverse", Arc::new(reverse));
+        let (entry, _) = db.index_schema("t_name").unwrap();
+        assert!(db.btree_depth(entry.rootpage).unwrap() > 1);
+        for i in [1, 150, 300] {
+            let row = db
+                .lookup_by_index("t_name", &key(i))
+                .unwrap()
+                .unwrap_or_else(|| panic!("name {} not found", i));
+            assert_eq!(row.values[0], FieldData::Integer(i));
+        }
+        assert!(db.lookup_by_index("t_name", &key(301)).unwrap().is_none());
+        // the entries are stored in descending name order
+        let first = db.index_entries("t_name").unwrap().next().unwrap().unwrap();
+        assert_eq!(first.rowid, 300);
+    }
+
+    #[test]
+    fn looping_index_btree_ends_with_one_error() {
+        let fixture = named_rows();
+        let mut db = fixture.open();
+        let root = db.index_schema("t_name").unwrap().0.rootpage;
+        assert!(db.btree_depth(root).unwrap() > 1);
+        // point the right-most pointer of the root back at the root
+        fixture.patch(db.page_start(root) + 8, &root.to_be_bytes());
+
+        let mut db = fixture.open();
+        let entries = db.index_entries("t_name").unwrap().collect::<Vec<_>>();
+        let errors = entries.iter().filter(|entry| entry.is_err()).count();
+        assert_eq!(errors, 1);
+        let err = entries.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "database disk image is malformed: page {} is referenced more than once in the \
+                 b-tree rooted at page {}",
+                root, root
+            )
+        );
+        assert!(db.index_scan("t_name", &["name"]).is_err());
+    }
+}
@@ -1,6 +1,31 @@
 pub mod btree_page;
+pub mod buffer_pool;
 pub mod cell;
+pub mod collation;
+pub mod columnar;
+pub mod csv;
 pub mod db;
+pub mod db_header;
 pub mod dbinfo;
+pub mod dump;
+pub mod error;
+pub mod freelist;
+#[cfg(feature = "hash")]
+pub mod hash;
+pub mod helpers;
+pub mod hexdump;
+pub mod index;
+pub mod json;
+pub mod page_cache;
+pub mod page_walk;
+pub mod predicate;
+pub mod progress;
 pub mod record;
+pub mod schema;
+pub mod stats;
+pub mod table;
+pub mod table_def;
+#[cfg(test)]
+mod test_util;
 pub mod varint;
+pub mod warning;
@@ -47,6 +47,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                 "{:24}{:<1}\n{:24}{:<1}",
                 "database page size:", db.page_size, "database page count:", db.page_count
             );
+            println!(
+                "{:24}{:<1}\n{:24}{:<1}",
+                "default cache size:",
+                db.header.default_cache_size(),
+                "incremental vacuum:",
+                u8::from(db.header.incremental_vacuum())
+            );
         }
         _ => {
             eprintln!("{}", CMDError::InvalidCommand(command.clone()));
@@ -0,0 +1,138 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+// Pages kept in memory by default, SQLite's own default cache holds about 2000 KiB worth
+pub const DEFAULT_CACHE_PAGES: usize = 2000;
+
+// Pages read from the file, holding at most `capacity` of them. Once full, the page used least
+// recently makes room for the next one. Contents are reference counted so a page can be held in
+// the cache shared by several handles and in each handle's own cache without being copied.
+#[derive(Debug)]
+pub struct PageCache {
+    capacity: usize,
+    pages: HashMap<u32, (Arc<[u8]>, u64)>, // contents and last use of each cached page
+    by_last_use: BTreeMap<u64, u32>,       // the page numbers ordered by their last use
+    clock: u64,
+}
+
+impl Default for PageCache {
+    fn default() -> Self {
+        PageCache::new(DEFAULT_CACHE_PAGES)
+    }
+}
+
+impl PageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            pages: HashMap::new(),
+            by_last_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Change how many pages are kept, evicting the least recently used ones above `capacity`
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        while self.pages.len() > self.capacity {
+            if let Some((_, evicted)) = self.by_last_use.pop_first() {
+                self.pages.remove(&evicted);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn contains(&self, page: u32) -> bool {
+        self.pages.contains_key(&page)
+    }
+
+    // The contents of `page`, marking it as the most recently used
+    pub fn get(&mut self, page: u32) -> Option<&[u8]> {
+        self.get_shared(page).map(|data| &data[..])
+    }
+
+    // Like `get`, returning the reference counted contents so they outlive the borrow of the
+    // cache
+    pub fn get_shared(&mut self, page: u32) -> Option<&Arc<[u8]>> {
+        self.clock += 1;
+        let (data, last_use) = self.pages.get_mut(&page)?;
+        self.by_last_use.remove(last_use);
+        *last_use = self.clock;
+        self.by_last_use.insert(self.clock, page);
+        Some(data)
+    }
+
+    // Like `get` without counting as a use, so it works from a shared reference
+    pub fn peek(&self, page: u32) -> Option<&[u8]> {
+        self.pages.get(&page).map(|(data, _)| &data[..])
+    }
+
+    // Cache `data` as the contents of `page`, evicting the least recently used page if the
+    // cache is full. A page that is already cached keeps its contents.
+    pub fn insert(&mut self, page: u32, data: impl Into<Arc<[u8]>>) {
+        if self.get(page).is_some() {
+            return;
+        }
+        if self.pages.len() == self.capacity {
+            if let Some((_, evicted)) = self.by_last_use.pop_first() {
+                self.pages.remove(&evicted);
+            }
+        }
+        self.pages.insert(page, (data.into(), self.clock));
+        self.by_last_use.insert(self.clock, page);
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.by_last_use.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_page_is_evicted() {
+        let mut cache = PageCache::new(2);
+        cache.insert(1, vec![1]);
+        cache.insert(2, vec![2]);
+        // page 1 is now used more recently than page 2
+        assert_eq!(cache.get(1), Some([1].as_slice()));
+        cache.insert(3, vec![3]);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(1));
+        assert!(!cache.contains(2));
+        assert!(cache.contains(3));
+    }
+
+    #[test]
+    fn reinserting_keeps_contents() {
+        let mut cache = PageCache::new(1);
+        cache.insert(5, vec![5]);
+        cache.insert(5, vec![6]);
+        assert_eq!(cache.peek(5), Some([5].as_slice()));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn shared_contents_outlive_eviction() {
+        let mut cache = PageCache::new(1);
+        cache.insert(1, vec![1, 2]);
+        let page = Arc::clone(cache.get_shared(1).unwrap());
+        cache.insert(2, vec![3]);
+        assert!(!cache.contains(1));
+        assert_eq!(&page[..], [1, 2]);
+    }
+}
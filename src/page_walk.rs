@@ -0,0 +1,465 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use crate::{
+    btree_page::{BtreePage, PageType},
+    cell::CellContent,
+    db::Database,
+    helpers::read_u32_be,
+};
+
+// Callbacks for `Database::walk_pages`, one per kind of page, each receiving the page number
+// and the full contents of the page. Every method does nothing unless overridden.
+pub trait PageVisitor {
+    fn visit_table_leaf(&mut self, _page_no: u32, _data: &[u8]) {}
+    fn visit_table_interior(&mut self, _page_no: u32, _data: &[u8]) {}
+    fn visit_index_leaf(&mut self, _page_no: u32, _data: &[u8]) {}
+    fn visit_index_interior(&mut self, _page_no: u32, _data: &[u8]) {}
+    fn visit_overflow(&mut self, _page_no: u32, _data: &[u8]) {}
+    fn visit_freelist(&mut self, _page_no: u32, _data: &[u8]) {}
+}
+
+impl Database {
+    // Visit every page reachable from the schema: the pages of each b-tree, the overflow pages
+    // of their cells and then the freelist. Pages reached more than once are visited once, and
+    // pages nothing points to (e.g. pointer map pages) aren't visited at all.
+    pub fn walk_pages(&mut self, visitor: &mut impl PageVisitor) -> Result<(), Box<dyn Error>> {
+        let mut pending_pages = vec![self.schema_root()];
+        pending_pages.extend(
+            self.read_schema()?
+                .iter()
+                .map(|entry| entry.rootpage)
+                .filter(|rootpage| *rootpage != 0),
+        );
+
+        let mut visited = HashSet::new();
+        self.walk_btrees(pending_pages, &mut visited, visitor)?;
+
+        for page in self.freelist_pages()? {
+            if visited.insert(page) {
+                let data = self.read_page(page).map_err(|e| e.to_string())?;
+                visitor.visit_freelist(page, data);
+            }
+        }
+        Ok(())
+    }
+
+    // Visit the b-tree pages reachable from `pending_pages` and the overflow pages of their
+    // cells, skipping (and then adding) the pages in `visited`
+    fn walk_btrees(
+        &mut self,
+        mut pending_pages: Vec<u32>,
+        visited: &mut HashSet<u32>,
+        visitor: &mut impl PageVisitor,
+    ) -> Result<(), Box<dyn Error>> {
+        while let Some(page) = pending_pages.pop() {
+            if !visited.insert(page) {
+                continue;
+            }
+            let btree_pg = BtreePage::at(self, page, None)?;
+            let data = self.read_page(page).map_err(|e| e.to_string())?;
+            match btree_pg.page_type {
+                PageType::LeafTable => visitor.visit_table_leaf(page, data),
+                PageType::InteriorTable => visitor.visit_table_interior(page, data),
+                PageType::LeafIndex => visitor.visit_index_leaf(page, data),
+                PageType::InteriorIndex => visitor.visit_index_interior(page, data),
+            }
+
+            for cell in btree_pg.get_page_cells() {
+                let content = CellContent::get_cell_data(&btree_pg, self, cell)?;
+                let (left_child, overflow) = cell_references(&content);
+                pending_pages.extend(left_child);
+
+                // each overflow page starts with the number of the next one, 0 on the last
+                let mut next_page = overflow.unwrap_or(0);
+                while next_page != 0 && visited.insert(next_page) {
+                    let data = self.read_page(next_page).map_err(|e| e.to_string())?;
+                    visitor.visit_overflow(next_page, data);
+                    next_page = read_u32_be(data)?;
+                }
+            }
+            pending_pages.extend(btree_pg.rightmost_ptr);
+        }
+        Ok(())
+    }
+}
+
+// The pages a b-tree cell points to: the left child of an interior cell and the first overflow
+// page of a payload that spills
+fn cell_references(content: &CellContent) -> (Option<u32>, Option<u32>) {
+    let left_child = content.try_left_child_pointer();
+    let overflow = match content {
+        CellContent::LeafTable { payload, .. }
+        | CellContent::LeafIndex { payload, .. }
+        | CellContent::InteriorIndex { payload, .. } => payload.overflow.map(u32::from_be_bytes),
+        CellContent::InteriorTable { .. } => None,
+    };
+    (left_child, overflow)
+}
+
+// Collects the page numbers of overflow pages as they are visited
+struct OverflowPageCollector(Vec<u32>);
+
+impl PageVisitor for OverflowPageCollector {
+    fn visit_overflow(&mut self, page_no: u32, _data: &[u8]) {
+        self.0.push(page_no);
+    }
+}
+
+// Collects the page numbers of b-tree and overflow pages as they are visited
+struct BtreePageCollector(Vec<u32>);
+
+impl PageVisitor for BtreePageCollector {
+    fn visit_table_leaf(&mut self, page_no: u32, _data: &[u8]) {
+        self.0.push(page_no);
+    }
+    fn visit_table_interior(&mut self, page_no: u32, _data: &[u8]) {
+        self.0.push(page_no);
+    }
+    fn visit_index_leaf(&mut self, page_no: u32, _data: &[u8]) {
+        self.0.push(page_no);
+    }
+    fn visit_index_interior(&mut self, page_no: u32, _data: &[u8]) {
+        self.0.push(page_no);
+    }
+    fn visit_overflow(&mut self, page_no: u32, _data: &[u8]) {
+        self.0.push(page_no);
+    }
+}
+
+impl Database {
+    // Every overflow page used by a table or index, chain by chain in the order the cells
+    // referencing them are stored
+    pub fn all_overflow_pages(&mut self) -> Result<Vec<u32>, Box<dyn Error>> {
+        let mut collector = OverflowPageCollector(vec![]);
+        self.walk_pages(&mut collector)?;
+        Ok(collector.0)
+    }
+
+    // Every page `table` occupies, in ascending order: the interior and leaf pages of its b-tree
+    // and the overflow pages its rows spill onto. Its indexes have b-trees of their own and
+    // aren't included, the b-tree of a WITHOUT ROWID table is an index b-tree and is.
+    pub fn table_pages(&mut self, table: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        let mut collector = BtreePageCollector(vec![]);
+        self.walk_btrees(
+            vec![table_def.rootpage],
+            &mut HashSet::new(),
+            &mut collector,
+        )?;
+        collector.0.sort_unstable();
+        Ok(collector.0)
+    }
+}
+
+impl Database {
+    // Pages referenced more than once (by a parent b-tree page, an overflow pointer, the schema
+    // or the freelist), which means the database is corrupt, and in-use pages that nothing
+    // references at all, which are leaked. The lock-byte page and pointer map pages are never
+    // referenced and aren't reported.
+    pub fn find_multiref_pages(&mut self) -> Result<Vec<u32>, Box<dyn Error>> {
+        let mut ref_counts: HashMap<u32, u32> = HashMap::new();
+        let mut pending_pages = vec![self.schema_root()];
+        pending_pages.extend(
+            self.read_schema()?
+                .iter()
+                .map(|entry| entry.rootpage)
+                .filter(|rootpage| *rootpage != 0),
+        );
+
+        while let Some(page) = pending_pages.pop() {
+            let count = ref_counts.entry(page).or_default();
+            *count += 1;
+            // a page reached again has already been walked, following it twice could loop
+            if *count > 1 {
+                continue;
+            }
+            let btree_pg = BtreePage::at(self, page, None)?;
+            for cell in btree_pg.get_page_cells() {
+                let content = CellContent::get_cell_data(&btree_pg, self, cell)?;
+                let (left_child, overflow) = cell_references(&content);
+                pending_pages.extend(left_child);
+
+                let mut next_page = overflow.unwrap_or(0);
+                while next_page != 0 {
+                    let count = ref_counts.entry(next_page).or_default();
+                    *count += 1;
+                    if *count > 1 {
+                        break;
+                    }
+                    next_page = read_u32_be(self.read_page(next_page).map_err(|e| e.to_string())?)?;
+                }
+            }
+            pending_pages.extend(btree_pg.rightmost_ptr);
+        }
+        for page in self.freelist_pages()? {
+            *ref_counts.entry(page).or_default() += 1;
+        }
+
+        let unreferenced =
+            |page: &u32| self.lock_byte_page() == Some(*page) || self.is_ptrmap_page(*page);
+        Ok((1..=self.page_count)
+            .filter(|page| match ref_counts.get(page) {
+                Some(count) => *count > 1,
+                None => !unreferenced(page),
+            })
+            .collect())
+    }
+
+    // Auto-vacuum databases keep pointer map pages at page 2 and then after every run of pages
+    // they describe, 5 bytes per described page
+    fn is_ptrmap_page(&self, page: u32) -> bool {
+        if self.header.largest_root_page() == 0 || page < 2 {
+            return false;
+        }
+        let entries_per_page = (self.page_size - u32::from(self.reserved_space)) / 5;
+        (page - 2).is_multiple_of(entries_per_page + 1)
+    }
+}
+
+// Page usage of the whole database, like a summary of SQLite's `dbstat`. Pages no walk reaches
+// (pointer map pages, the lock-byte page, leaked pages) are counted in `other_pages`, so the
+// per-type counts add up to `page_count`.
+#[derive(Debug, Default)]
+pub struct SpaceReport {
+    pub page_count: u32,
+    pub table_leaf_pages: u32,
+    pub table_interior_pages: u32,
+    pub index_leaf_pages: u32,
+    pub index_interior_pages: u32,
+    pub overflow_pages: u32,
+    pub freelist_pages: u32,
+    pub other_pages: u32,
+    // what a VACUUM would free at least: the freelist pages, not free space within pages
+    pub reclaimable_bytes: u64,
+}
+
+impl PageVisitor for SpaceReport {
+    fn visit_table_leaf(&mut self, _page_no: u32, _data: &[u8]) {
+        self.table_leaf_pages += 1;
+    }
+    fn visit_table_interior(&mut self, _page_no: u32, _data: &[u8]) {
+        self.table_interior_pages += 1;
+    }
+    fn visit_index_leaf(&mut self, _page_no: u32, _data: &[u8]) {
+        self.index_leaf_pages += 1;
+    }
+    fn visit_index_interior(&mut self, _page_no: u32, _data: &[u8]) {
+        self.index_interior_pages += 1;
+    }
+    fn visit_overflow(&mut self, _page_no: u32, _data: &[u8]) {
+        self.overflow_pages += 1;
+    }
+    fn visit_freelist(&mut self, _page_no: u32, _data: &[u8]) {
+        self.freelist_pages += 1;
+    }
+}
+
+impl Database {
+    pub fn space_report(&mut self) -> Result<SpaceReport, Box<dyn Error>> {
+        let mut report = SpaceReport::default();
+        self.walk_pages(&mut report)?;
+
+        let visited = report.table_leaf_pages
+            + report.table_interior_pages
+            + report.index_leaf_pages
+            + report.index_interior_pages
+            + report.overflow_pages
+            + report.freelist_pages;
+        report.page_count = self.page_count;
+        report.other_pages = self.page_count.saturating_sub(visited);
+        report.reclaimable_bytes = u64::from(report.freelist_pages) * u64::from(self.page_size);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    // On 512 byte pages: a table and an index deep enough for interior pages, one row
+    // spilling onto overflow pages and a dropped table's pages on the freelist
+    fn every_page_kind() -> Fixture {
+        Fixture::build(512, |conn| {
+            conn.execute_batch(
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+                CREATE INDEX t_s ON t (s);
+                WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 60)
+                INSERT INTO t SELECT i, printf('%050d', i) FROM n;
+                CREATE TABLE dropped (a);
+                INSERT INTO dropped SELECT s FROM t;
+                DROP TABLE dropped;
+                CREATE TABLE blobs (b BLOB);",
+            )
+            .unwrap();
+            conn.execute("INSERT INTO blobs VALUES (?1)", [vec![0u8; 2000]])
+                .unwrap();
+        })
+    }
+
+    #[derive(Default)]
+    struct Kinds(HashMap<&'static str, Vec<u32>>);
+
+    impl Kinds {
+        fn add(&mut self, kind: &'static str, page_no: u32) {
+            self.0.entry(kind).or_default().push(page_no);
+        }
+    }
+
+    impl PageVisitor for Kinds {
+        fn visit_table_leaf(&mut self, page_no: u32, _data: &[u8]) {
+            self.add("table leaf", page_no);
+        }
+        fn visit_table_interior(&mut self, page_no: u32, data: &[u8]) {
+            assert_eq!(data[if page_no == 1 { 100 } else { 0 }], 0x05);
+            self.add("table interior", page_no);
+        }
+        fn visit_index_leaf(&mut self, page_no: u32, _data: &[u8]) {
+            self.add("index leaf", page_no);
+        }
+        fn visit_index_interior(&mut self, page_no: u32, data: &[u8]) {
+            assert_eq!(data[0], 0x02);
+            self.add("index interior", page_no);
+        }
+        fn visit_overflow(&mut self, page_no: u32, _data: &[u8]) {
+            self.add("overflow", page_no);
+        }
+        fn visit_freelist(&mut self, page_no: u32, _data: &[u8]) {
+            self.add("freelist", page_no);
+        }
+    }
+
+    #[test]
+    fn walk_visits_every_page_once_by_kind() {
+        let fixture = every_page_kind();
+        let mut db = fixture.open();
+        let mut kinds = Kinds::default();
+        db.walk_pages(&mut kinds).unwrap();
+
+        for kind in [
+            "table leaf",
+            "table interior",
+            "index leaf",
+            "index interior",
+            "overflow",
+            "freelist",
+        ] {
+            assert!(kinds.0.contains_key(kind), "no {} pages visited", kind);
+        }
+        assert_eq!(kinds.0["freelist"].len() as u32, db.freelist_count);
+        // the blob spills 1964 bytes onto overflow pages holding 508 bytes each
+        assert_eq!(kinds.0["overflow"].len(), 4);
+
+        let mut pages = kinds.0.into_values().flatten().collect::<Vec<_>>();
+        pages.sort_unstable();
+        assert_eq!(pages, (1..=db.page_count).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn overflow_pages_in_chain_order() {
+        let fixture = every_page_kind();
+        let mut db = fixture.open();
+        let pages = db.all_overflow_pages().unwrap();
+        assert_eq!(pages.len(), 4);
+        // each page of the chain starts with the number of the next one, 0 on the last
+        for (page, next) in pages.iter().zip(pages.iter().skip(1).chain([&0])) {
+            let data = db.read_page(*page).unwrap();
+            assert_eq!(read_u32_be(data).unwrap(), *next);
+        }
+    }
+
+    #[test]
+    fn multiref_and_leaked_pages() {
+        let fixture = every_page_kind();
+        let mut db = fixture.open();
+        assert!(db.find_multiref_pages().unwrap().is_empty());
+
+        // point the second page of the overflow chain back at the first, so the first page is
+        // referenced twice and the rest of the chain by nothing
+        let chain = db.all_overflow_pages().unwrap();
+        fixture.patch(db.page_start(chain[1]), &chain[0].to_be_bytes());
+        let mut db = fixture.open();
+        let mut expected = vec![chain[0], chain[2], chain[3]];
+        expected.sort_unstable();
+        assert_eq!(db.find_multiref_pages().unwrap(), expected);
+    }
+
+    #[test]
+    fn space_report_adds_up_to_the_page_count() {
+        let fixture = every_page_kind();
+        let mut db = fixture.open();
+        let mut kinds = Kinds::default();
+        db.walk_pages(&mut kinds).unwrap();
+        let count = |kind: &str| kinds.0.get(kind).map_or(0, Vec::len) as u32;
+
+        let report = db.space_report().unwrap();
+        assert_eq!(report.page_count, db.page_count);
+        assert_eq!(report.table_leaf_pages, count("table leaf"));
+        assert_eq!(report.table_interior_pages, count("table interior"));
+        assert_eq!(report.index_leaf_pages, count("index leaf"));
+        assert_eq!(report.index_interior_pages, count("index interior"));
+        assert_eq!(report.overflow_pages, 4);
+        assert_eq!(report.freelist_pages, db.freelist_count);
+        assert_eq!(report.other_pages, 0);
+        assert_eq!(report.reclaimable_bytes, u64::from(db.freelist_count) * 512);
+        assert!(report.reclaimable_bytes > 0);
+    }
+
+    #[test]
+    fn space_report_counts_pointer_map_pages_as_other() {
+        let fixture = Fixture::build(512, |conn| {
+            conn.execute_batch(
+                "PRAGMA auto_vacuum = FULL;
+                CREATE TABLE t (s TEXT);
+                WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 40)
+                INSERT INTO t SELECT printf('%050d', i) FROM n;",
+            )
+            .unwrap();
+        });
+        let mut db = fixture.open();
+        let report = db.space_report().unwrap();
+        // page 2 is the only pointer map page, and nothing is left on the freelist
+        assert_eq!(report.other_pages, 1);
+        assert_eq!(report.freelist_pages, 0);
+        assert_eq!(report.reclaimable_bytes, 0);
+        assert_eq!(
+            report.table_leaf_pages
+                + report.table_interior_pages
+                + report.index_leaf_pages
+                + report.index_interior_pages
+                + report.overflow_pages
+                + report.freelist_pages
+                + report.other_pages,
+            report.page_count
+        );
+    }
+
+    #[test]
+    fn table_pages_match_sqlite_dbstat() {
+        let fixture = every_page_kind();
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE w (k TEXT PRIMARY KEY, v) WITHOUT ROWID;
+            INSERT INTO w SELECT s, id FROM t;",
+        )
+        .unwrap();
+        let dbstat = |name: &str| {
+            conn.prepare("SELECT pageno FROM dbstat WHERE name = ?1 ORDER BY pageno")
+                .unwrap()
+                .query_map([name], |row| row.get::<_, u32>(0))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+
+        let mut db = fixture.open();
+        for table in ["t", "blobs", "w", "sqlite_schema"] {
+            let pages = db.table_pages(table).unwrap();
+            assert_eq!(pages, dbstat(table), "{}", table);
+        }
+        // the leaf holding the blob row and the 4 overflow pages it spills onto
+        assert_eq!(db.table_pages("blobs").unwrap().len(), 5);
+        assert!(db.table_pages("missing").is_err());
+    }
+}
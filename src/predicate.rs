@@ -0,0 +1,248 @@
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use std::error::Error;
+
+use crate::{
+    db::Database,
+    record::{FieldData, Record},
+    table_def::TableDef,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// A WHERE condition on the columns of a table. Evaluation follows SQLite's three-valued logic:
+// a comparison with NULL is neither true nor false but NULL.
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    Compare {
+        column: String,
+        op: CompareOp,
+        value: FieldData,
+    },
+    IsNull(String),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+}
+
+impl Predicate {
+    // Truth value of the predicate for one row, `None` standing for NULL
+    pub fn evaluate(
+        &self,
+        table_def: &TableDef,
+        values: &[FieldData],
+    ) -> Result<Option<bool>, Box<dyn Error>> {
+        let column_value = |column: &str| {
+            table_def
+                .column_index(column)
+                .map(|idx| values.get(idx).unwrap_or(&FieldData::Null(())))
+                .ok_or_else(|| format!("no such column: {}", column))
+        };
+
+        match self {
+            Predicate::Compare { column, op, value } => {
+                let lhs = column_value(column)?;
+                if matches!(lhs, FieldData::Null(_)) || matches!(value, FieldData::Null(_)) {
+                    return Ok(None);
+                }
+                let ordering = lhs.compare(value, &|a: &str, b: &str| a.cmp(b));
+                Ok(Some(match op {
+                    CompareOp::Eq => ordering == Ordering::Equal,
+                    CompareOp::Ne => ordering != Ordering::Equal,
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::Le => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    CompareOp::Ge => ordering != Ordering::Less,
+                }))
+            }
+            Predicate::IsNull(column) => {
+                Ok(Some(matches!(column_value(column)?, FieldData::Null(_))))
+            }
+            // false wins over NULL in AND, true wins over NULL in OR
+            Predicate::And(lhs, rhs) => {
+                match (
+                    lhs.evaluate(table_def, values)?,
+                    rhs.evaluate(table_def, values)?,
+                ) {
+                    (Some(false), _) | (_, Some(false)) => Ok(Some(false)),
+                    (Some(true), Some(true)) => Ok(Some(true)),
+                    _ => Ok(None),
+                }
+            }
+            Predicate::Or(lhs, rhs) => {
+                match (
+                    lhs.evaluate(table_def, values)?,
+                    rhs.evaluate(table_def, values)?,
+                ) {
+                    (Some(true), _) | (_, Some(true)) => Ok(Some(true)),
+                    (Some(false), Some(false)) => Ok(Some(false)),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+}
+
+impl Database {
+    // Rows of `table` for which `predicate` is true, in row_id order (primary key order for
+    // WITHOUT ROWID tables). Rows where it is false or NULL are skipped, like in a WHERE clause.
+    pub fn select(
+        &mut self,
+        table: &str,
+        predicate: &Predicate,
+    ) -> Result<Vec<Record>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+
+        let mut records = vec![];
+        for record in self.table_records(&table_def) {
+            let record = record?;
+            if predicate.evaluate(&table_def, &record.values)? == Some(true) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    fn eq(column: &str, value: i64) -> Predicate {
+        Predicate::Compare {
+            column: column.to_owned(),
+            op: CompareOp::Eq,
+            value: FieldData::Integer(value),
+        }
+    }
+
+    #[test]
+    fn and_or_with_null() {
+        let table_def = TableDef::parse("t", 2, "CREATE TABLE t (a, b)").unwrap();
+        let both = |lhs: Predicate, rhs: Predicate| {
+            let and = Predicate::And(Box::new(lhs.clone()), Box::new(rhs.clone()));
+            let or = Predicate::Or(Box::new(lhs), Box::new(rhs));
+            (and, or)
+        };
+        // (a, b) = (1, NULL): a = 1 is true, b = 1 is NULL, a = 0 is false
+        let values = [FieldData::Integer(1), FieldData::Null(())];
+        let evaluate = |p: &Predicate| p.evaluate(&table_def, &values).unwrap();
+
+        assert_eq!(evaluate(&eq("b", 1)), None);
+        let (and, or) = both(eq("a", 1), eq("b", 1));
+        assert_eq!((evaluate(&and), evaluate(&or)), (None, Some(true)));
+        let (and, or) = both(eq("a", 0), eq("b", 1));
+        assert_eq!((evaluate(&and), evaluate(&or)), (Some(false), None));
+        let (and, or) = both(eq("b", 1), eq("b", 0));
+        assert_eq!((evaluate(&and), evaluate(&or)), (None, None));
+        assert_eq!(evaluate(&Predicate::IsNull("b".to_owned())), Some(true));
+        assert!(eq("c", 1).evaluate(&table_def, &values).is_err());
+    }
+
+    #[test]
+    fn select_matches_sqlite_where() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a, b);
+            INSERT INTO t (a, b) SELECT x.v, y.v
+                FROM (SELECT NULL AS v UNION ALL SELECT 0 UNION ALL SELECT 1) AS x,
+                     (SELECT NULL AS v UNION ALL SELECT 0 UNION ALL SELECT 1) AS y;",
+        );
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        let mut db = fixture.open();
+        let cases = [
+            (
+                "a = 1 AND b = 1",
+                Predicate::And(Box::new(eq("a", 1)), Box::new(eq("b", 1))),
+            ),
+            (
+                "a = 1 OR b = 1",
+                Predicate::Or(Box::new(eq("a", 1)), Box::new(eq("b", 1))),
+            ),
+            (
+                "a = 0 OR b IS NULL",
+                Predicate::Or(
+                    Box::new(eq("a", 0)),
+                    Box::new(Predicate::IsNull("b".to_owned())),
+                ),
+            ),
+            (
+                "a > 0 AND b <> 1",
+                Predicate::And(
+                    Box::new(Predicate::Compare {
+                        column: "a".to_owned(),
+                        op: CompareOp::Gt,
+                        value: FieldData::Integer(0),
+                    }),
+                    Box::new(Predicate::Compare {
+                        column: "b".to_owned(),
+                        op: CompareOp::Ne,
+                        value: FieldData::Integer(1),
+                    }),
+                ),
+            ),
+        ];
+        for (condition, predicate) in cases {
+            let expected = conn
+                .prepare(&format!("SELECT id FROM t WHERE {} ORDER BY id", condition))
+                .unwrap()
+                .query_map([], |row| row.get::<_, i64>(0))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let selected = db
+                .select("t", &predicate)
+                .unwrap()
+                .iter()
+                .map(|record| record.values[0].as_integer().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(selected, expected, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn select_from_a_without_rowid_table() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE w (a TEXT, b INTEGER, c TEXT, PRIMARY KEY (c, a)) WITHOUT ROWID;
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1000)
+            INSERT INTO w SELECT printf('a%d', i), i % 97, printf('c%03d', (i * 7919) % 300)
+            FROM n;",
+        );
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        let expected = conn
+            .prepare("SELECT c, a FROM w WHERE b < 10 ORDER BY c, a")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(String, String)>, _>>()
+            .unwrap();
+
+        let mut db = fixture.open();
+        let predicate = Predicate::Compare {
+            column: "b".to_owned(),
+            op: CompareOp::Lt,
+            value: FieldData::Integer(10),
+        };
+        let selected = db
+            .select("w", &predicate)
+            .unwrap()
+            .into_iter()
+            .map(|record| {
+                assert!(record.values[1].as_integer().unwrap() < 10);
+                let text = |idx: usize| record.values[idx].as_text().unwrap().to_owned();
+                (text(2), text(0))
+            })
+            .collect::<Vec<_>>();
+        assert!(!expected.is_empty());
+        assert_eq!(selected, expected);
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+
+use crate::db::Database;
+
+// Number of b-tree pages visited between two progress events
+pub const PROGRESS_INTERVAL: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressEvent {
+    // total number of b-tree pages visited so far
+    PageVisited(u64),
+}
+
+// Send and Sync so a database can be shared between threads
+pub type ProgressCallback = Box<dyn FnMut(ProgressEvent) + Send + Sync>;
+
+// Counts the b-tree pages read by scans and lookups, and hands every PROGRESS_INTERVAL-th
+// visit to the registered callback. Without a callback visits aren't even counted.
+#[derive(Default)]
+pub struct ProgressReporter {
+    callback: Option<ProgressCallback>,
+    pages_visited: u64,
+}
+
+impl fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("enabled", &self.callback.is_some())
+            .field("pages_visited", &self.pages_visited)
+            .finish()
+    }
+}
+
+impl ProgressReporter {
+    pub fn page_visited(&mut self) {
+        let Some(callback) = self.callback.as_mut() else {
+            return;
+        };
+        self.pages_visited += 1;
+        if self.pages_visited.is_multiple_of(PROGRESS_INTERVAL) {
+            callback(ProgressEvent::PageVisited(self.pages_visited));
+        }
+    }
+}
+
+impl Database {
+    // Call `f` periodically while pages are read, e.g. to draw a progress bar during long scans.
+    // Replaces any earlier callback and restarts the page count.
+    pub fn set_progress(&mut self, f: ProgressCallback) {
+        self.progress = ProgressReporter {
+            callback: Some(f),
+            pages_visited: 0,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn reports_every_sixteenth_page_visit() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 200)
+            INSERT INTO t SELECT i, printf('%0100d', i) FROM n;",
+        );
+        let mut db = fixture.open();
+        let table_pages = db.table_pages("t").unwrap().len() as u64;
+        assert!(table_pages > 2 * PROGRESS_INTERVAL);
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let collected = events.clone();
+        db.set_progress(Box::new(move |event| collected.lock().unwrap().push(event)));
+        assert_eq!(db.count_rows("t").unwrap(), 200);
+
+        let events = events.lock().unwrap();
+        let expected = (1..=events.len() as u64)
+            .map(|n| ProgressEvent::PageVisited(n * PROGRESS_INTERVAL))
+            .collect::<Vec<_>>();
+        assert_eq!(*events, expected);
+        // every page of the table was visited at least once
+        assert!((events.len() as u64 + 1) * PROGRESS_INTERVAL > table_pages);
+    }
+}
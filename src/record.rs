@@ -1,8 +1,18 @@
 use std::fmt;
-use std::{cmp::min, error::Error};
+use std::{
+    cmp::{min, Ordering},
+    error::Error,
+    ops::Range,
+};
 
 use crate::cell::CellContent;
-use crate::varint::{decode_be, MaxBytesExceededError};
+use crate::error::RuSqliteError;
+use crate::helpers::{read_u48_be, read_u64_be};
+use crate::varint::{decode_be, encode_be};
+
+// Records claiming more columns than this are treated as corrupt. SQLite itself allows at most
+// 32767 columns per table.
+pub const DEFAULT_MAX_COLUMNS: usize = 65536;
 
 #[derive(Debug)]
 pub struct ParseError {
@@ -36,7 +46,87 @@ pub enum DataType {
     Blob,
 }
 
-#[derive(Debug)]
+// How a value is stored, as given by its serial type in the record header. Integers are
+// stored big-endian in the width named, `Zero` and `One` are the integer constants that take
+// no body bytes, and blobs and text carry their length in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialType {
+    Null,
+    I8,
+    I16,
+    I24,
+    I32,
+    I48,
+    I64,
+    F64,
+    Zero,
+    One,
+    Blob(usize),
+    Text(usize),
+}
+
+impl SerialType {
+    pub fn from_code(code: u64) -> Result<Self, RuSqliteError> {
+        let serial_type = match code {
+            0 => SerialType::Null,
+            1 => SerialType::I8,
+            2 => SerialType::I16,
+            3 => SerialType::I24,
+            4 => SerialType::I32,
+            5 => SerialType::I48,
+            6 => SerialType::I64,
+            7 => SerialType::F64,
+            8 => SerialType::Zero,
+            9 => SerialType::One,
+            // Technically, 10 and 11 have variable sizes but are reserved for internal SQLite
+            // use and should never appear in database files.
+            10 | 11 => {
+                return Err(RuSqliteError::Corrupt(format!(
+                    "reserved serial type {} in record header",
+                    code
+                )))
+            }
+            // Serial types >= 12: even values are BLOBs of (N-12)/2 bytes and odd values are
+            // TEXT of (N-13)/2 bytes, so 12 and 13 are zero-length values.
+            n if n.is_multiple_of(2) => SerialType::Blob(((n - 12) / 2) as usize),
+            n => SerialType::Text(((n - 13) / 2) as usize),
+        };
+        Ok(serial_type)
+    }
+
+    // Number of body bytes taken by a value of this type
+    pub fn size(&self) -> usize {
+        match self {
+            SerialType::Null | SerialType::Zero | SerialType::One => 0,
+            SerialType::I8 => 1,
+            SerialType::I16 => 2,
+            SerialType::I24 => 3,
+            SerialType::I32 => 4,
+            SerialType::I48 => 6,
+            SerialType::I64 | SerialType::F64 => 8,
+            SerialType::Blob(len) | SerialType::Text(len) => *len,
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            SerialType::Null => DataType::Null,
+            SerialType::I8
+            | SerialType::I16
+            | SerialType::I24
+            | SerialType::I32
+            | SerialType::I48
+            | SerialType::I64 => DataType::Integer,
+            SerialType::F64 => DataType::Real,
+            SerialType::Zero => DataType::BooleanFalse,
+            SerialType::One => DataType::BooleanTrue,
+            SerialType::Blob(_) => DataType::Blob,
+            SerialType::Text(_) => DataType::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum FieldData {
     Null(()),
     BooleanFalse(u8),
@@ -82,20 +172,11 @@ impl FieldData {
                     }
                     4 => i32::from_be_bytes([data[0], data[1], data[2], data[3]]) as i64,
                     6 => {
-                        // Adjust for 48-bit data
-                        if data[0] <= 0x7F {
-                            i64::from_be_bytes([
-                                0, 0, data[0], data[1], data[2], data[3], data[4], data[5],
-                            ])
-                        } else {
-                            i64::from_be_bytes([
-                                0xFF, 0xFF, data[0], data[1], data[2], data[3], data[4], data[5],
-                            ])
-                        }
+                        // Sign-extend the 48-bit value from its top bit
+                        let value = read_u48_be(data).map_err(|_| ParseError::new("INTEGER"))?;
+                        ((value << 16) as i64) >> 16
                     }
-                    8 => i64::from_be_bytes([
-                        data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-                    ]),
+                    8 => read_u64_be(data).map_err(|_| ParseError::new("INTEGER"))? as i64,
                     _ => {
                         return Err(ParseError::new("INTEGER"));
                     }
@@ -106,9 +187,7 @@ impl FieldData {
                 if data.len() != 8 {
                     return Err(ParseError::new("REAL"));
                 }
-                let value = f64::from_be_bytes([
-                    data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
-                ]);
+                let value = f64::from_bits(read_u64_be(data).map_err(|_| ParseError::new("REAL"))?);
                 Ok(FieldData::Real(value))
             }
             DataType::Text => {
@@ -123,6 +202,210 @@ impl FieldData {
     }
 }
 
+impl FieldData {
+    // Integer value of the field, including the constant 0 and 1 serial types
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            FieldData::Integer(value) => Some(*value),
+            FieldData::BooleanFalse(value) | FieldData::BooleanTrue(value) => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    // Order two values like SQLite: NULL < INTEGER and REAL (compared numerically) < TEXT
+    // (compared with `collation`) < BLOB (compared bytewise)
+    pub fn compare(
+        &self,
+        other: &FieldData,
+        collation: &dyn Fn(&str, &str) -> Ordering,
+    ) -> Ordering {
+        let class = |value: &FieldData| match value {
+            FieldData::Null(_) => 0,
+            FieldData::BooleanFalse(_)
+            | FieldData::BooleanTrue(_)
+            | FieldData::Integer(_)
+            | FieldData::Real(_) => 1,
+            FieldData::Text(_) => 2,
+            FieldData::Blob(_) => 3,
+        };
+
+        match (self, other) {
+            (FieldData::Text(a), FieldData::Text(b)) => collation(a, b),
+            (FieldData::Blob(a), FieldData::Blob(b)) => a.cmp(b),
+            (FieldData::Real(a), b) if class(b) == 1 => {
+                a.total_cmp(&b.as_real().unwrap_or_default())
+            }
+            (a, FieldData::Real(b)) if class(a) == 1 => {
+                a.as_real().unwrap_or_default().total_cmp(b)
+            }
+            (a, b) if class(a) == 1 && class(b) == 1 => a.as_integer().cmp(&b.as_integer()),
+            (a, b) => class(a).cmp(&class(b)),
+        }
+    }
+
+    pub fn as_real(&self) -> Option<f64> {
+        match self {
+            FieldData::Real(value) => Some(*value),
+            _ => self.as_integer().map(|value| value as f64),
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            FieldData::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    // Guess what a BLOB holds from its leading magic bytes, falling back to "utf8-text" for
+    // printable UTF-8. None for other values and unrecognized content.
+    pub fn sniff_blob(&self) -> Option<&'static str> {
+        let FieldData::Blob(blob) = self else {
+            return None;
+        };
+        let kind = match blob.as_slice() {
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => "png",
+            [0xff, 0xd8, 0xff, ..] => "jpeg",
+            [b'G', b'I', b'F', b'8', ..] => "gif",
+            [0x1f, 0x8b, ..] => "gzip",
+            [b'P', b'K', 0x03, 0x04, ..] => "zip",
+            [b'%', b'P', b'D', b'F', b'-', ..] => "pdf",
+            bytes if bytes.starts_with(b"SQLite format 3\0") => "sqlite",
+            bytes => match std::str::from_utf8(bytes) {
+                Ok(text)
+                    if !text.is_empty()
+                        && !text.chars().any(|c| c.is_control() && !c.is_whitespace()) =>
+                {
+                    "utf8-text"
+                }
+                _ => return None,
+            },
+        };
+        Some(kind)
+    }
+}
+
+// How a column converts the values stored in or compared against it, see
+// https://www.sqlite.org/datatype3.html#type_affinity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob, // no conversion at all, also the affinity of columns without a declared type
+}
+
+impl Affinity {
+    // Affinity of a column declared with `type_name`, from the first matching rule: INT, then
+    // CHAR, CLOB or TEXT, then BLOB or no type, then REAL, FLOA or DOUB, otherwise NUMERIC
+    pub fn from_type_name(type_name: &str) -> Self {
+        let type_name = type_name.to_ascii_uppercase();
+        let has = |words: &[&str]| words.iter().any(|word| type_name.contains(word));
+        if has(&["INT"]) {
+            Affinity::Integer
+        } else if has(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if type_name.is_empty() || has(&["BLOB"]) {
+            Affinity::Blob
+        } else if has(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+}
+
+// The number `text` spells out, surrounding whitespace allowed. Only decimal integer and real
+// literals count, unlike Rust's parser SQLite doesn't read "inf" or "NaN" as numbers.
+fn parse_numeric_text(text: &str) -> Option<FieldData> {
+    let text = text.trim();
+    if text.is_empty()
+        || !text
+            .chars()
+            .all(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+    {
+        return None;
+    }
+    if let Ok(value) = text.parse::<i64>() {
+        return Some(FieldData::Integer(value));
+    }
+    text.parse::<f64>().ok().map(FieldData::Real)
+}
+
+impl FieldData {
+    // Convert the value the way a column with `affinity` would before storing or comparing it:
+    // text that looks like a number becomes one in numeric columns, numbers become text in
+    // TEXT columns, and reals without a fractional part become integers in NUMERIC and
+    // INTEGER columns. Everything else, NULL and blobs included, is left as it is.
+    pub fn apply_affinity(self, affinity: Affinity) -> FieldData {
+        match (affinity, self) {
+            (Affinity::Blob, value) => value,
+            (Affinity::Text, FieldData::Real(value)) => FieldData::Text(format!("{:?}", value)),
+            (Affinity::Text, value @ (FieldData::Integer(_) | FieldData::BooleanFalse(_))) => {
+                FieldData::Text(value.as_integer().unwrap_or_default().to_string())
+            }
+            (Affinity::Text, FieldData::BooleanTrue(_)) => FieldData::Text("1".to_owned()),
+            (Affinity::Text, value) => value,
+            (affinity, FieldData::Text(text)) => match parse_numeric_text(&text) {
+                Some(number) => number.apply_affinity(affinity),
+                None => FieldData::Text(text),
+            },
+            (Affinity::Real, FieldData::Integer(value)) => FieldData::Real(value as f64),
+            // only reals that convert to an integer and back without loss
+            (Affinity::Numeric | Affinity::Integer, FieldData::Real(value))
+                if value.fract() == 0.0 && value.abs() < 9.2e18 =>
+            {
+                FieldData::Integer(value as i64)
+            }
+            (_, value) => value,
+        }
+    }
+}
+
+impl FieldData {
+    // The serial type SQLite would store this value with: integers take the narrowest width
+    // that holds them, with 0 and 1 stored as the body-less constants 8 and 9. Those two only
+    // exist from schema format 4 (the default since SQLite 3.7.10), and they parse back as
+    // `BooleanFalse` and `BooleanTrue` rather than `Integer`.
+    pub fn serial_type(&self) -> u64 {
+        match self {
+            FieldData::Null(_) => 0,
+            FieldData::BooleanFalse(_) => 8,
+            FieldData::BooleanTrue(_) => 9,
+            FieldData::Integer(0) => 8,
+            FieldData::Integer(1) => 9,
+            FieldData::Integer(value) => match value {
+                -0x80..=0x7f => 1,
+                -0x8000..=0x7fff => 2,
+                -0x80_0000..=0x7f_ffff => 3,
+                -0x8000_0000..=0x7fff_ffff => 4,
+                -0x8000_0000_0000..=0x7fff_ffff_ffff => 5,
+                _ => 6,
+            },
+            FieldData::Real(_) => 7,
+            FieldData::Text(text) => text.len() as u64 * 2 + 13,
+            FieldData::Blob(blob) => blob.len() as u64 * 2 + 12,
+        }
+    }
+
+    // The bytes stored for this value in the body of a record, to go with `serial_type`
+    pub fn encode_body(&self) -> Vec<u8> {
+        match self {
+            FieldData::Integer(value) => {
+                let width = SerialType::from_code(self.serial_type())
+                    .map(|serial_type| serial_type.size())
+                    .unwrap_or_default();
+                value.to_be_bytes()[8 - width..].to_vec()
+            }
+            FieldData::Real(value) => value.to_bits().to_be_bytes().to_vec(),
+            FieldData::Text(text) => text.as_bytes().to_vec(),
+            FieldData::Blob(blob) => blob.clone(),
+            FieldData::Null(_) | FieldData::BooleanFalse(_) | FieldData::BooleanTrue(_) => vec![],
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Field {
     size: usize,
@@ -143,6 +426,11 @@ impl Default for Field {
 impl Field {
     pub fn read_data(&self, content: &CellContent) -> Result<FieldData, Box<dyn Error>> {
         let payload = content.get_payload()?;
+        self.read_value(payload)
+    }
+
+    // Decode this field from a complete record payload (header included)
+    pub fn read_value(&self, payload: &[u8]) -> Result<FieldData, Box<dyn Error>> {
         let data = &payload[self.offset..self.offset + self.size];
 
         match self.data_type {
@@ -188,6 +476,8 @@ impl Field {
 #[derive(Debug, Default)]
 pub struct Record {
     pub fields: Option<Vec<Field>>,
+    pub values: Vec<FieldData>,
+    serial_types: Vec<SerialType>,
 }
 
 impl Record {
@@ -197,15 +487,112 @@ impl Record {
         }
     }
 
-    pub fn load_fields(&mut self, payload: &[u8]) -> Result<(), MaxBytesExceededError> {
-        // read first varint from payload to determine size
-        let (header_size, mut idx) = decode_be(&payload[..9usize])?;
+    // Load the fields of a complete (overflow included) record payload and decode every value
+    pub fn parse(payload: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Record::parse_with_limit(payload, DEFAULT_MAX_COLUMNS)
+    }
+
+    // Like `parse`, erroring instead of reading the header past `max_columns` serial types
+    pub fn parse_with_limit(payload: &[u8], max_columns: usize) -> Result<Self, Box<dyn Error>> {
+        let mut record = Record::new();
+        record.load_fields(payload, max_columns)?;
+        record.values = record
+            .fields
+            .iter()
+            .flatten()
+            .map(|field| field.read_value(payload))
+            .collect::<Result<Vec<FieldData>, Box<dyn Error>>>()?;
+        Ok(record)
+    }
+
+    // Build the payload SQLite would store for `values`: the header size varint (counting
+    // itself), a serial type varint per value and then the value bodies in order. Parsing it
+    // gives back `values` except that `Integer(0)` and `Integer(1)` come back as
+    // `BooleanFalse(0)` and `BooleanTrue(1)`, see `FieldData::serial_type`.
+    pub fn encode(values: &[FieldData]) -> Vec<u8> {
+        let serial_types = values
+            .iter()
+            .flat_map(|value| encode_be(value.serial_type()).1)
+            .collect::<Vec<u8>>();
+        // the size varint grows with the header it describes, at most by a couple of bytes
+        let mut header_size = serial_types.len() + 1;
+        while encode_be(header_size as u64).0 + serial_types.len() != header_size {
+            header_size = encode_be(header_size as u64).0 + serial_types.len();
+        }
+
+        let mut payload = encode_be(header_size as u64).1;
+        payload.extend(serial_types);
+        for value in values {
+            payload.extend(value.encode_body());
+        }
+        payload
+    }
+
+    // Serial types from the record header, one per column, describing how each value is stored
+    pub fn header(&self) -> &[SerialType] {
+        &self.serial_types
+    }
+
+    pub fn values(&self) -> &[FieldData] {
+        &self.values
+    }
+
+    // Take the decoded values without copying them, e.g. to keep large blobs
+    pub fn into_values(self) -> Vec<FieldData> {
+        self.values
+    }
+
+    // Number of columns in a record, counted from the serial types in its header without
+    // decoding any values
+    pub fn column_count(payload: &[u8]) -> Result<usize, Box<dyn Error>> {
+        if payload.is_empty() {
+            return Ok(0);
+        }
+        let (header_size, mut position) = decode_be(&payload[..min(9usize, payload.len())])?;
+        let header_end = min(header_size as usize, payload.len());
+        let mut count = 0;
+        while position < header_end {
+            let (_, varint_len) = decode_be(&payload[position..min(position + 9, header_end)])?;
+            position += varint_len;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn load_fields(
+        &mut self,
+        payload: &[u8],
+        max_columns: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        // a zero-length payload has no header at all and holds no columns
+        if payload.is_empty() {
+            self.fields = Some(vec![]);
+            return Ok(());
+        }
+
+        // read first varint from payload to determine size. A header-only record (every column
+        // NULL or a 0/1 constant) has an empty body and each field is a zero-length slice at
+        // the end of the payload.
+        let (header_size, mut idx) = decode_be(&payload[..min(9usize, payload.len())])?;
+        // the header size counts its own varint
+        if (header_size as usize) < idx || header_size as usize > payload.len() {
+            return Err(RuSqliteError::Corrupt(format!(
+                "record header of {} bytes in a {} byte payload",
+                header_size,
+                payload.len()
+            ))
+            .into());
+        }
         let mut fields = vec![];
+        let mut serial_types = vec![];
 
-        let mut serial_type: u64;
+        let mut code: u64;
         let mut position = idx;
         let mut field_start = header_size as usize;
         while position < header_size as usize {
+            if fields.len() == max_columns {
+                return Err(RuSqliteError::Corrupt("implausible column count".to_owned()).into());
+            }
             // let mut new_field = Field::default();
             let mut new_field = Field {
                 offset: field_start,
@@ -213,54 +600,515 @@ impl Record {
             };
             // new_field.offset = field_start;
             let end = min(position + 9usize, header_size as usize);
-            (serial_type, idx) = decode_be(&payload[position..end])?;
-            match serial_type {
-                0 => {
-                    new_field.size = 0;
-                    new_field.data_type = DataType::Null;
-                }
-                1..=4 => {
-                    new_field.size = serial_type as usize;
-                    new_field.data_type = DataType::Integer;
-                }
-                s @ 5 | s @ 6 => {
-                    new_field.size = if s == 5 { 6 } else { 8 };
-                    new_field.data_type = DataType::Integer;
-                }
-                7 => {
-                    new_field.size = 8;
-                    new_field.data_type = DataType::Real;
-                }
-                8 => {
-                    new_field.size = 0;
-                    new_field.data_type = DataType::BooleanFalse;
-                }
-                9 => {
-                    new_field.size = 0;
-                    new_field.data_type = DataType::BooleanTrue;
-                }
-                10 | 11 => {
-                    // Technically, 10 and 11 have variable sizes but are reserved for
-                    // internal SQLite use and should never appear in database files.
-                    todo!()
-                }
-                _ => {
-                    if serial_type % 2 == 0 {
-                        new_field.size = ((serial_type - 12) / 2) as usize;
-                        new_field.data_type = DataType::Blob;
-                    } else {
-                        new_field.size = ((serial_type - 13) / 2) as usize;
-                        new_field.data_type = DataType::Text
-                    }
-                }
-            };
+            (code, idx) = decode_be(&payload[position..end])?;
+            let serial_type = SerialType::from_code(code)?;
+            new_field.size = serial_type.size();
+            new_field.data_type = serial_type.data_type();
+            serial_types.push(serial_type);
+            if new_field.size > payload.len() - field_start {
+                return Err(RuSqliteError::Corrupt(format!(
+                    "record fields run past the end of a {} byte payload",
+                    payload.len()
+                ))
+                .into());
+            }
             field_start += new_field.size;
             fields.push(new_field);
             position += idx;
         }
 
         self.fields = Some(fields);
+        self.serial_types = serial_types;
 
         Ok(())
     }
 }
+
+// A record payload whose values are only decoded when asked for. The header is parsed on first
+// use, after which any run of columns can be decoded without touching the others.
+#[derive(Debug)]
+pub struct LazyRecord {
+    payload: Vec<u8>,
+    max_columns: usize,
+    serial_types: Option<Vec<SerialType>>,
+    header_size: usize,
+}
+
+impl LazyRecord {
+    pub fn new(payload: Vec<u8>, max_columns: usize) -> Self {
+        Self {
+            payload,
+            max_columns,
+            serial_types: None,
+            header_size: 0,
+        }
+    }
+
+    pub fn column_count(&mut self) -> Result<usize, Box<dyn Error>> {
+        Ok(self.load_header()?.len())
+    }
+
+    // Decode the columns in `range` only. The body of the first one starts after the bodies of
+    // every column before it, whose lengths come from their serial types alone.
+    pub fn columns(&mut self, range: Range<usize>) -> Result<Vec<FieldData>, Box<dyn Error>> {
+        // the cached header is borrowed alongside the payload, not copied for every call
+        self.load_header()?;
+        let serial_types = self.serial_types.as_deref().unwrap_or_default();
+        if range.start > range.end || range.end > serial_types.len() {
+            return Err(format!(
+                "columns {}..{} are out of range for a record of {} columns",
+                range.start,
+                range.end,
+                serial_types.len()
+            )
+            .into());
+        }
+
+        let mut offset = self.header_size
+            + serial_types[..range.start]
+                .iter()
+                .map(SerialType::size)
+                .sum::<usize>();
+        let mut values = vec![];
+        for serial_type in &serial_types[range] {
+            let data = &self.payload[offset..offset + serial_type.size()];
+            values
+                .push(FieldData::parse(serial_type.data_type(), data).map_err(|e| e.to_string())?);
+            offset += serial_type.size();
+        }
+        Ok(values)
+    }
+
+    // Serial types of the columns, parsing the header the first time
+    fn load_header(&mut self) -> Result<&[SerialType], Box<dyn Error>> {
+        if self.serial_types.is_none() {
+            // `load_fields` checks that every field lies within the payload
+            let mut record = Record::new();
+            record.load_fields(&self.payload, self.max_columns)?;
+            if !self.payload.is_empty() {
+                self.header_size =
+                    decode_be(&self.payload[..min(9, self.payload.len())])?.0 as usize;
+            }
+            self.serial_types = Some(record.serial_types);
+        }
+        Ok(self.serial_types.as_deref().unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn text_and_blob_lengths_from_serial_type() {
+        assert_eq!(SerialType::from_code(12).unwrap(), SerialType::Blob(0));
+        assert_eq!(SerialType::from_code(13).unwrap(), SerialType::Text(0));
+        assert_eq!(SerialType::from_code(14).unwrap(), SerialType::Blob(1));
+        assert_eq!(SerialType::from_code(15).unwrap(), SerialType::Text(1));
+        assert_eq!(SerialType::from_code(212).unwrap(), SerialType::Blob(100));
+        assert_eq!(SerialType::from_code(213).unwrap(), SerialType::Text(100));
+    }
+
+    #[test]
+    fn zero_length_text_and_blob_advance_nothing() {
+        // header: size 6, empty blob, empty text, 1-byte blob, 1-byte text, 8-bit integer
+        let payload = [6, 12, 13, 14, 15, 1, 0xab, b'a', 7];
+        let record = Record::parse(&payload).unwrap();
+        assert_eq!(
+            record.values(),
+            [
+                FieldData::Blob(vec![]),
+                FieldData::Text(String::new()),
+                FieldData::Blob(vec![0xab]),
+                FieldData::Text("a".to_owned()),
+                FieldData::Integer(7),
+            ]
+        );
+    }
+
+    #[test]
+    fn header_only_and_empty_records() {
+        // every column NULL or a 0/1 constant, so there is no body at all
+        let record = Record::parse(&[4, 0, 8, 9]).unwrap();
+        assert_eq!(
+            record.values(),
+            [
+                FieldData::Null(()),
+                FieldData::BooleanFalse(0),
+                FieldData::BooleanTrue(1)
+            ]
+        );
+        assert!(Record::parse(&[]).unwrap().values().is_empty());
+    }
+
+    #[test]
+    fn column_limit_rejects_wider_records() {
+        // three 8-bit integers
+        let payload = [4, 1, 1, 1, 10, 20, 30];
+        assert_eq!(
+            Record::parse_with_limit(&payload, 3)
+                .unwrap()
+                .values()
+                .len(),
+            3
+        );
+        let err = Record::parse_with_limit(&payload, 2).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RuSqliteError>(),
+            Some(RuSqliteError::Corrupt(details)) if details == "implausible column count"
+        ));
+    }
+
+    #[test]
+    fn sniff_blob_magic_bytes() {
+        let sniff = |bytes: &[u8]| FieldData::Blob(bytes.to_vec()).sniff_blob();
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0"), Some("png"));
+        assert_eq!(sniff(&[0xff, 0xd8, 0xff, 0xe0]), Some("jpeg"));
+        assert_eq!(sniff(b"GIF89a"), Some("gif"));
+        assert_eq!(sniff(&[0x1f, 0x8b, 0x08]), Some("gzip"));
+        assert_eq!(sniff(b"PK\x03\x04"), Some("zip"));
+        assert_eq!(sniff(b"%PDF-1.7"), Some("pdf"));
+        assert_eq!(sniff(b"SQLite format 3\0\x10\0"), Some("sqlite"));
+        assert_eq!(sniff(b"plain text\n"), Some("utf8-text"));
+        // too short to match, binary, empty, and not a blob at all
+        assert_eq!(sniff(&[0x89, b'P']), None);
+        assert_eq!(sniff(&[0x00, 0x01, 0x02]), None);
+        assert_eq!(sniff(&[]), None);
+        assert_eq!(FieldData::Text("GIF8".to_owned()).sniff_blob(), None);
+    }
+
+    #[test]
+    fn malformed_headers_are_corrupt() {
+        let corrupt = |payload: &[u8]| {
+            let err = Record::parse(payload).unwrap_err();
+            match err.downcast_ref::<RuSqliteError>() {
+                Some(RuSqliteError::Corrupt(details)) => details.clone(),
+                _ => panic!("{:?} is not a corruption error: {}", payload, err),
+            }
+        };
+        // a header size too small to hold its own varint, and one past the payload
+        assert_eq!(
+            corrupt(&[0, 1, 5]),
+            "record header of 0 bytes in a 3 byte payload"
+        );
+        assert_eq!(
+            corrupt(&[9, 1, 5]),
+            "record header of 9 bytes in a 3 byte payload"
+        );
+        assert_eq!(
+            corrupt(&[2, 10]),
+            "reserved serial type 10 in record header"
+        );
+        // a 4 byte integer with only 2 bytes of body left
+        assert_eq!(
+            corrupt(&[2, 4, 0, 1]),
+            "record fields run past the end of a 4 byte payload"
+        );
+    }
+
+    #[test]
+    fn into_values_moves_the_decoded_values() {
+        // a 3 byte blob and the text "hi"
+        let payload = [3, 18, 17, 1, 2, 3, b'h', b'i'];
+        let record = Record::parse(&payload).unwrap();
+        let blob_ptr = match &record.values()[0] {
+            FieldData::Blob(blob) => blob.as_ptr(),
+            value => panic!("expected a blob, got {:?}", value),
+        };
+        let values = record.into_values();
+        assert_eq!(
+            values,
+            [
+                FieldData::Blob(vec![1, 2, 3]),
+                FieldData::Text("hi".to_owned())
+            ]
+        );
+        // the blob wasn't copied on the way out
+        assert!(matches!(&values[0], FieldData::Blob(blob) if blob.as_ptr() == blob_ptr));
+    }
+
+    #[test]
+    fn header_lists_the_serial_types_sqlite_chose() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (a, b, c, d, e, f, g, h, i, j, k, l);
+            INSERT INTO t VALUES (NULL, 0, 1, 100, 1000, 100000, 100000000, 3000000000,
+                1000000000000000, 1.5, 'ab', X'00');",
+        );
+        let record = fixture.open().lookup_rowid("t", 1).unwrap().unwrap();
+        assert_eq!(
+            record.header(),
+            [
+                SerialType::Null,
+                SerialType::Zero,
+                SerialType::One,
+                SerialType::I8,
+                SerialType::I16,
+                SerialType::I24,
+                SerialType::I32,
+                SerialType::I48,
+                SerialType::I64,
+                SerialType::F64,
+                SerialType::Text(2),
+                SerialType::Blob(1),
+            ]
+        );
+        let sizes = record
+            .header()
+            .iter()
+            .map(SerialType::size)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [0, 0, 0, 1, 2, 3, 4, 6, 8, 8, 2, 1]);
+    }
+
+    #[test]
+    fn serial_type_of_each_value() {
+        let cases = [
+            (FieldData::Null(()), 0, vec![]),
+            (FieldData::Integer(0), 8, vec![]),
+            (FieldData::Integer(1), 9, vec![]),
+            (FieldData::BooleanFalse(0), 8, vec![]),
+            (FieldData::BooleanTrue(1), 9, vec![]),
+            (FieldData::Integer(2), 1, vec![2]),
+            (FieldData::Integer(-1), 1, vec![0xff]),
+            (FieldData::Integer(127), 1, vec![0x7f]),
+            (FieldData::Integer(-128), 1, vec![0x80]),
+            (FieldData::Integer(128), 2, vec![0x00, 0x80]),
+            (FieldData::Integer(-32768), 2, vec![0x80, 0x00]),
+            (FieldData::Integer(32768), 3, vec![0x00, 0x80, 0x00]),
+            (FieldData::Integer(8_388_607), 3, vec![0x7f, 0xff, 0xff]),
+            (
+                FieldData::Integer(8_388_608),
+                4,
+                vec![0x00, 0x80, 0x00, 0x00],
+            ),
+            (FieldData::Integer(-2_147_483_648), 4, vec![0x80, 0, 0, 0]),
+            (
+                FieldData::Integer(2_147_483_648),
+                5,
+                vec![0, 0, 0x80, 0, 0, 0],
+            ),
+            (
+                FieldData::Integer(0x7fff_ffff_ffff),
+                5,
+                vec![0x7f, 0xff, 0xff, 0xff, 0xff, 0xff],
+            ),
+            (
+                FieldData::Integer(0x8000_0000_0000),
+                6,
+                vec![0, 0, 0x80, 0, 0, 0, 0, 0],
+            ),
+            (
+                FieldData::Integer(i64::MIN),
+                6,
+                i64::MIN.to_be_bytes().to_vec(),
+            ),
+            (FieldData::Real(1.5), 7, 1.5f64.to_be_bytes().to_vec()),
+            (FieldData::Blob(vec![]), 12, vec![]),
+            (FieldData::Text(String::new()), 13, vec![]),
+            (FieldData::Blob(vec![0xab; 3]), 18, vec![0xab; 3]),
+            (
+                FieldData::Text("héllo".to_owned()),
+                25,
+                "héllo".as_bytes().to_vec(),
+            ),
+        ];
+        for (value, serial_type, body) in cases {
+            assert_eq!(value.serial_type(), serial_type, "{:?}", value);
+            assert_eq!(value.encode_body(), body, "{:?}", value);
+            // the body is exactly as long as the serial type says
+            let size = SerialType::from_code(serial_type).unwrap().size();
+            assert_eq!(size, body.len(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn serial_types_match_sqlite() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (v);
+            INSERT INTO t VALUES (NULL), (0), (1), (-7), (300), (70000), (3000000000),
+                (140000000000000), (1.25), ('text'), (x'0102');",
+        );
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        for row in crate::table::TableIterator::new(&mut db, root) {
+            let (_, record) = row.unwrap();
+            assert_eq!(record.header().len(), 1);
+            let stored = record.header()[0];
+            assert_eq!(
+                SerialType::from_code(record.values[0].serial_type()).unwrap(),
+                stored
+            );
+        }
+    }
+
+    #[test]
+    fn encoded_records_parse_back() {
+        let records = [
+            vec![],
+            vec![FieldData::Null(()); 5],
+            vec![
+                FieldData::Integer(-3),
+                FieldData::Real(2.5),
+                FieldData::Text("name".to_owned()),
+                FieldData::Blob(vec![0, 1, 2]),
+                FieldData::Null(()),
+                FieldData::Integer(i64::MAX),
+                FieldData::Integer(1 << 40),
+            ],
+            // 100 byte texts take 2 byte serial types, pushing the header past 127 bytes so
+            // its size needs a 2 byte varint too
+            vec![FieldData::Text("x".repeat(100)); 70],
+            vec![FieldData::Blob(vec![9; 20000])],
+        ];
+        for values in records {
+            let payload = Record::encode(&values);
+            assert_eq!(Record::parse(&payload).unwrap().values(), values);
+        }
+        assert_eq!(Record::encode(&[]), [1]);
+    }
+
+    #[test]
+    fn encoded_zero_and_one_parse_back_as_constants() {
+        let values = [
+            FieldData::Integer(0),
+            FieldData::Integer(1),
+            FieldData::Integer(2),
+        ];
+        let payload = Record::encode(&values);
+        // header size, the constants 8 and 9 with no body, then a 1 byte integer
+        assert_eq!(payload, [4, 8, 9, 1, 2]);
+        let record = Record::parse(&payload).unwrap();
+        assert_eq!(
+            record.values(),
+            [
+                FieldData::BooleanFalse(0),
+                FieldData::BooleanTrue(1),
+                FieldData::Integer(2)
+            ]
+        );
+        let integers = record
+            .values()
+            .iter()
+            .map(|value| value.as_integer())
+            .collect::<Vec<_>>();
+        assert_eq!(integers, [Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn encode_matches_the_payloads_sqlite_writes() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (a, b, c, d);
+            INSERT INTO t VALUES (NULL, 0, 'abc', x'ff'), (1, -70000, 2.75, ''),
+                (9000000000, 1, NULL, printf('%0300d', 5));",
+        );
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaf = crate::btree_page::BtreePage::at(&mut db, root, None).unwrap();
+        let mut rows = 0;
+        for cell in leaf.get_page_cells() {
+            let CellContent::LeafTable { payload, .. } =
+                CellContent::get_cell_data(&leaf, &mut db, cell).unwrap()
+            else {
+                panic!("not a table leaf cell");
+            };
+            let record = Record::parse(&payload.payload).unwrap();
+            assert_eq!(Record::encode(record.values()), payload.payload);
+            rows += 1;
+        }
+        assert_eq!(rows, 3);
+    }
+
+    #[test]
+    fn lazy_record_decodes_only_the_requested_columns() {
+        let values = [
+            FieldData::Integer(70000),
+            FieldData::Text("name".to_owned()),
+            FieldData::Null(()),
+            FieldData::Real(0.5),
+            FieldData::Blob(vec![7; 300]),
+            FieldData::Text("wide".repeat(100)),
+        ];
+        let mut payload = Record::encode(&values);
+        let mut lazy = LazyRecord::new(payload.clone(), DEFAULT_MAX_COLUMNS);
+        assert_eq!(lazy.column_count().unwrap(), 6);
+        assert_eq!(lazy.columns(0..2).unwrap(), values[0..2]);
+        assert_eq!(lazy.columns(3..6).unwrap(), values[3..6]);
+        assert_eq!(lazy.columns(2..2).unwrap(), []);
+        let err = lazy.columns(4..7).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "columns 4..7 are out of range for a record of 6 columns"
+        );
+
+        // invalid UTF-8 in the last column only fails when that column is decoded
+        let last = payload.len() - 1;
+        payload[last] = 0xff;
+        let mut lazy = LazyRecord::new(payload.clone(), DEFAULT_MAX_COLUMNS);
+        assert_eq!(lazy.columns(0..2).unwrap(), values[0..2]);
+        assert_eq!(lazy.columns(4..5).unwrap(), values[4..5]);
+        assert!(lazy.columns(5..6).is_err());
+        assert!(Record::parse(&payload).is_err());
+    }
+
+    #[test]
+    fn affinity_from_type_name_and_conversions() {
+        for (type_name, affinity) in [
+            ("INTEGER", Affinity::Integer),
+            ("BIGINT", Affinity::Integer),
+            ("VARCHAR(20)", Affinity::Text),
+            // INT is checked first, just like SQLite does
+            ("CHARINT", Affinity::Integer),
+            ("", Affinity::Blob),
+            ("blob", Affinity::Blob),
+            ("DOUBLE PRECISION", Affinity::Real),
+            ("DECIMAL(10,5)", Affinity::Numeric),
+            // "POINT" holds INT, the example SQLite's documentation gives for this rule
+            ("FLOATING POINT", Affinity::Integer),
+        ] {
+            assert_eq!(
+                Affinity::from_type_name(type_name),
+                affinity,
+                "{}",
+                type_name
+            );
+        }
+
+        let text = |value: &str| FieldData::Text(value.to_owned());
+        for (value, affinity, converted) in [
+            (text(" 42 "), Affinity::Integer, FieldData::Integer(42)),
+            (text("2.0"), Affinity::Numeric, FieldData::Integer(2)),
+            (text("2.5"), Affinity::Integer, FieldData::Real(2.5)),
+            (text("3"), Affinity::Real, FieldData::Real(3.0)),
+            (text("inf"), Affinity::Real, text("inf")),
+            (text("42"), Affinity::Blob, text("42")),
+            (FieldData::Integer(42), Affinity::Text, text("42")),
+            (FieldData::Real(1.5), Affinity::Text, text("1.5")),
+            (
+                FieldData::Real(2.0),
+                Affinity::Integer,
+                FieldData::Integer(2),
+            ),
+            (FieldData::Null(()), Affinity::Integer, FieldData::Null(())),
+        ] {
+            assert_eq!(
+                value.clone().apply_affinity(affinity),
+                converted,
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn lazy_columns_borrow_the_cached_header() {
+        let values = (0..1000).map(FieldData::Integer).collect::<Vec<_>>();
+        let mut lazy = LazyRecord::new(Record::encode(&values), DEFAULT_MAX_COLUMNS);
+        assert_eq!(lazy.column_count().unwrap(), 1000);
+        // once the header is parsed, a column costs the returned Vec and nothing per column of
+        // the record
+        let before = crate::test_util::allocations();
+        assert_eq!(lazy.columns(998..999).unwrap(), [FieldData::Integer(998)]);
+        assert_eq!(crate::test_util::allocations() - before, 1);
+    }
+}
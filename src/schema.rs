@@ -0,0 +1,462 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::{db::Database, error::RuSqliteError, record::Record, table::TableIterator};
+
+#[derive(Debug)]
+pub struct InvalidSchemaEntryError {
+    details: String,
+}
+
+impl InvalidSchemaEntryError {
+    fn new(column: &str) -> Self {
+        Self {
+            details: format!("sqlite_master entry has an invalid `{}` column", column),
+        }
+    }
+}
+
+impl fmt::Display for InvalidSchemaEntryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for InvalidSchemaEntryError {}
+
+// The implicit definition of the schema table, which has no entry of its own. `sqlite_schema`
+// is accepted as another name for it.
+pub const SCHEMA_TABLE: &str = "sqlite_master";
+pub const SCHEMA_TABLE_SQL: &str =
+    "CREATE TABLE sqlite_master(type text, name text, tbl_name text, rootpage integer, sql text)";
+
+// A row of the `sqlite_master` table:
+// (type text, name text, tbl_name text, rootpage integer, sql text)
+#[derive(Debug, Clone)]
+pub struct SchemaEntry {
+    pub entry_type: String, // "table", "index", "view" or "trigger"
+    pub name: String,
+    pub tbl_name: String,
+    pub rootpage: u32,       // 0 for views and triggers, which have no b-tree
+    pub sql: Option<String>, // NULL for automatically created indexes
+}
+
+impl SchemaEntry {
+    pub fn from_record(record: &Record) -> Result<Self, InvalidSchemaEntryError> {
+        let text_column = |idx: usize, column: &str| {
+            record
+                .values
+                .get(idx)
+                .and_then(|value| value.as_text())
+                .map(str::to_owned)
+                .ok_or_else(|| InvalidSchemaEntryError::new(column))
+        };
+
+        Ok(Self {
+            entry_type: text_column(0, "type")?,
+            name: text_column(1, "name")?,
+            tbl_name: text_column(2, "tbl_name")?,
+            rootpage: record
+                .values
+                .get(3)
+                .and_then(|value| value.as_integer())
+                .and_then(|rootpage| u32::try_from(rootpage).ok())
+                .ok_or_else(|| InvalidSchemaEntryError::new("rootpage"))?,
+            sql: text_column(4, "sql").ok(),
+        })
+    }
+
+    // Catch a root page pointer outside the file here rather than during a later traversal.
+    // Only objects without a b-tree, views, triggers and virtual tables, may have rootpage 0.
+    pub fn check_rootpage(&self, page_count: u32) -> Result<(), RuSqliteError> {
+        let has_btree = match self.entry_type.as_str() {
+            "view" | "trigger" => false,
+            "table" => VirtualTable::from_entry(self).is_none(),
+            _ => true,
+        };
+        if !has_btree && self.rootpage == 0 {
+            return Ok(());
+        }
+        if self.rootpage == 0 || self.rootpage > page_count {
+            return Err(RuSqliteError::Corrupt(format!(
+                "{} {} has root page {} but the database has {} pages",
+                self.entry_type, self.name, self.rootpage, page_count
+            )));
+        }
+        Ok(())
+    }
+}
+
+// A `CREATE VIRTUAL TABLE name USING module(args)` entry. Virtual tables have no b-tree of their
+// own, modules such as FTS keep their data in ordinary "shadow" tables named after them.
+#[derive(Debug)]
+pub struct VirtualTable {
+    pub name: String,
+    pub module: String,       // e.g. "fts5" or "rtree"
+    pub args: Option<String>, // the module arguments as written, None without parentheses
+}
+
+impl VirtualTable {
+    fn from_entry(entry: &SchemaEntry) -> Option<Self> {
+        let sql = entry.sql.as_deref()?;
+        let words = sql.split_whitespace().collect::<Vec<_>>();
+        let is_virtual = words.len() > 2
+            && words[0].eq_ignore_ascii_case("CREATE")
+            && words[1].eq_ignore_ascii_case("VIRTUAL")
+            && words[2].eq_ignore_ascii_case("TABLE");
+        if !is_virtual {
+            return None;
+        }
+
+        let using = sql.to_ascii_uppercase().find(" USING ")? + " USING ".len();
+        let module_def = sql[using..].trim();
+        let module_end = module_def
+            .find(|c: char| c == '(' || c.is_whitespace())
+            .unwrap_or(module_def.len());
+        let args = module_def[module_end..]
+            .trim()
+            .strip_prefix('(')
+            .and_then(|args| args.strip_suffix(')'))
+            .map(|args| args.trim().to_owned());
+        Some(Self {
+            name: entry.name.clone(),
+            module: module_def[..module_end].to_owned(),
+            args,
+        })
+    }
+}
+
+// Schema objects that differ between two databases, matched by name. `added` entries only exist
+// in the other database and `removed` entries only exist in this one.
+#[derive(Debug, Default)]
+pub struct SchemaDiff {
+    pub added: Vec<SchemaEntry>,
+    pub removed: Vec<SchemaEntry>,
+    pub changed: Vec<(SchemaEntry, SchemaEntry)>, // (this database's entry, other's entry)
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Collapse whitespace runs so formatting-only differences in the stored DDL are ignored
+fn normalize_sql(sql: &Option<String>) -> Option<String> {
+    sql.as_ref()
+        .map(|sql| sql.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+impl Database {
+    pub fn read_schema(&mut self) -> Result<Vec<SchemaEntry>, Box<dyn Error>> {
+        self.sqlite_master().collect()
+    }
+
+    // Entries of `sqlite_master` read one at a time through the generic table iterator
+    pub fn sqlite_master(
+        &mut self,
+    ) -> impl Iterator<Item = Result<SchemaEntry, Box<dyn Error>>> + '_ {
+        let schema_root = self.schema_root();
+        let page_count = self.page_count;
+        TableIterator::new(self, schema_root).map(move |row| {
+            let (_, record) = row?;
+            let entry = SchemaEntry::from_record(&record)?;
+            entry.check_rootpage(page_count)?;
+            Ok(entry)
+        })
+    }
+
+    // The CREATE statement of the schema object called `name`, stopping at the first match.
+    // None if there is no such object or it has no SQL, like automatic indexes.
+    pub fn object_sql(&mut self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        for entry in self.sqlite_master() {
+            let entry = entry?;
+            if entry.name.eq_ignore_ascii_case(name) {
+                return Ok(entry.sql);
+            }
+        }
+        Ok(None)
+    }
+
+    // (name, sql) of every trigger in the schema
+    pub fn triggers(&mut self) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        self.schema_definitions("trigger")
+    }
+
+    // (name, sql) of every view in the schema
+    pub fn views(&mut self) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        self.schema_definitions("view")
+    }
+
+    // Views and triggers have no b-tree (rootpage 0), so their definitions only come from
+    // `sqlite_master` and are never routed through a table iterator
+    fn schema_definitions(
+        &mut self,
+        entry_type: &str,
+    ) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        Ok(self
+            .read_schema()?
+            .into_iter()
+            .filter(|entry| entry.entry_type == entry_type)
+            .map(|entry| (entry.name, entry.sql.unwrap_or_default()))
+            .collect())
+    }
+
+    pub fn virtual_tables(&mut self) -> Result<Vec<VirtualTable>, Box<dyn Error>> {
+        Ok(self
+            .read_schema()?
+            .iter()
+            .filter(|entry| entry.entry_type == "table")
+            .filter_map(VirtualTable::from_entry)
+            .collect())
+    }
+
+    // Names of the tables a virtual table module created to store its data, which are named
+    // with the virtual table's name as a prefix (e.g. `docs_data` and `docs_idx` for `docs`)
+    pub fn shadow_tables_for(&mut self, vtable: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let schema = self.read_schema()?;
+        let vtable = schema
+            .iter()
+            .filter(|entry| entry.entry_type == "table")
+            .filter_map(VirtualTable::from_entry)
+            .find(|virtual_table| virtual_table.name.eq_ignore_ascii_case(vtable))
+            .ok_or_else(|| format!("no such virtual table: {}", vtable))?;
+
+        let prefix = format!("{}_", vtable.name.to_ascii_lowercase());
+        Ok(schema
+            .into_iter()
+            .filter(|entry| entry.entry_type == "table" && entry.rootpage != 0)
+            .filter(|entry| entry.name.to_ascii_lowercase().starts_with(&prefix))
+            .map(|entry| entry.name)
+            .collect())
+    }
+
+    pub fn schema_diff(&mut self, other: &mut Database) -> Result<SchemaDiff, Box<dyn Error>> {
+        let mut diff = SchemaDiff::default();
+        let mut own_entries: HashMap<String, SchemaEntry> = self
+            .read_schema()?
+            .into_iter()
+            .map(|entry| (entry.name.clone(), entry))
+            .collect();
+
+        for entry in other.read_schema()? {
+            match own_entries.remove(&entry.name) {
+                None => diff.added.push(entry),
+                Some(own_entry) => {
+                    if own_entry.entry_type != entry.entry_type
+                        || normalize_sql(&own_entry.sql) != normalize_sql(&entry.sql)
+                    {
+                        diff.changed.push((own_entry, entry));
+                    }
+                }
+            }
+        }
+
+        diff.removed = own_entries.into_values().collect();
+        diff.removed.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn lists_views_and_triggers() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (a, b);
+            CREATE TABLE log (a);
+            CREATE VIEW v AS SELECT a FROM t;
+            CREATE TRIGGER tr AFTER INSERT ON t BEGIN INSERT INTO log VALUES (new.a); END;",
+        );
+        let mut db = fixture.open();
+        assert_eq!(
+            db.views().unwrap(),
+            [(
+                "v".to_owned(),
+                "CREATE VIEW v AS SELECT a FROM t".to_owned()
+            )]
+        );
+        assert_eq!(
+            db.triggers().unwrap(),
+            [(
+                "tr".to_owned(),
+                "CREATE TRIGGER tr AFTER INSERT ON t BEGIN INSERT INTO log VALUES (new.a); END"
+                    .to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn schema_diff_reports_additions() {
+        let before = Fixture::new("CREATE TABLE t (a, b);");
+        let after = Fixture::new(
+            "CREATE TABLE t (a, b);
+            CREATE TABLE u (c);
+            CREATE INDEX t_b ON t (b);",
+        );
+        let diff = before.open().schema_diff(&mut after.open()).unwrap();
+        let mut added = diff
+            .added
+            .iter()
+            .map(|entry| (entry.entry_type.as_str(), entry.name.as_str()))
+            .collect::<Vec<_>>();
+        added.sort();
+        assert_eq!(added, [("index", "t_b"), ("table", "u")]);
+        assert!(diff.removed.is_empty());
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn schema_diff_ignores_whitespace() {
+        let a = Fixture::new("CREATE TABLE t (a,   b);");
+        let b = Fixture::new("CREATE TABLE t (a, b);");
+        assert!(a.open().schema_diff(&mut b.open()).unwrap().is_empty());
+        let c = Fixture::new("CREATE TABLE t (a, b, c);");
+        let diff = a.open().schema_diff(&mut c.open()).unwrap();
+        assert_eq!(diff.changed.len(), 1);
+    }
+
+    #[test]
+    fn virtual_tables_and_their_shadow_tables() {
+        let fixture = Fixture::new(
+            "CREATE VIRTUAL TABLE docs USING fts5(title, body);
+            CREATE VIRTUAL TABLE boxes USING rtree(id, min_x, max_x);
+            CREATE TABLE docs_notes (a);
+            INSERT INTO docs VALUES ('a', 'b');",
+        );
+        let mut db = fixture.open();
+        let vtables = db
+            .virtual_tables()
+            .unwrap()
+            .into_iter()
+            .map(|vtable| (vtable.name, vtable.module, vtable.args))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vtables,
+            [
+                (
+                    "docs".to_owned(),
+                    "fts5".to_owned(),
+                    Some("title, body".to_owned())
+                ),
+                (
+                    "boxes".to_owned(),
+                    "rtree".to_owned(),
+                    Some("id, min_x, max_x".to_owned())
+                ),
+            ]
+        );
+
+        let mut shadow = db.shadow_tables_for("docs").unwrap();
+        shadow.sort();
+        // an ordinary table sharing the prefix can't be told apart from a shadow table
+        assert_eq!(
+            shadow,
+            [
+                "docs_config",
+                "docs_content",
+                "docs_data",
+                "docs_docsize",
+                "docs_idx",
+                "docs_notes"
+            ]
+        );
+        let mut shadow = db.shadow_tables_for("boxes").unwrap();
+        shadow.sort();
+        assert_eq!(shadow, ["boxes_node", "boxes_parent", "boxes_rowid"]);
+        assert!(db.shadow_tables_for("docs_data").is_err());
+    }
+
+    #[test]
+    fn schema_table_reads_like_any_table() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (a);
+            CREATE INDEX t_a ON t (a);
+            CREATE VIEW v AS SELECT a FROM t;",
+        );
+        let mut db = fixture.open();
+        let names = db
+            .sqlite_master()
+            .map(|entry| entry.map(|entry| (entry.entry_type, entry.name)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            names,
+            [
+                ("table".to_owned(), "t".to_owned()),
+                ("index".to_owned(), "t_a".to_owned()),
+                ("view".to_owned(), "v".to_owned())
+            ]
+        );
+
+        for name in ["sqlite_master", "SQLITE_SCHEMA"] {
+            let table_def = db.table_def(name).unwrap();
+            assert_eq!(table_def.rootpage, 1);
+            assert_eq!(table_def.columns.len(), 5);
+        }
+        let types = db
+            .query_map("sqlite_schema", |values| {
+                Ok(values[0].as_text().unwrap_or_default().to_owned())
+            })
+            .unwrap();
+        assert_eq!(types, ["table", "index", "view"]);
+    }
+
+    #[test]
+    fn object_sql_by_name() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (a TEXT UNIQUE);
+            CREATE INDEX t_a ON t (a);",
+        );
+        let mut db = fixture.open();
+        assert_eq!(
+            db.object_sql("T").unwrap().as_deref(),
+            Some("CREATE TABLE t (a TEXT UNIQUE)")
+        );
+        assert_eq!(
+            db.object_sql("t_a").unwrap().as_deref(),
+            Some("CREATE INDEX t_a ON t (a)")
+        );
+        // the index behind UNIQUE is created automatically and has no SQL
+        assert_eq!(db.object_sql("sqlite_autoindex_t_1").unwrap(), None);
+        assert_eq!(db.object_sql("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn root_pages_are_checked_against_the_page_count() {
+        let schema = "CREATE TABLE t (a); CREATE INDEX t_a ON t (a);
+            CREATE VIEW v AS SELECT a FROM t;
+            CREATE TRIGGER tr AFTER INSERT ON t BEGIN SELECT 1; END;";
+        let fixture = Fixture::new(schema);
+        let mut db = fixture.open();
+        let entries = db.read_schema().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(entries
+            .iter()
+            .all(|e| (e.rootpage == 0) == matches!(e.entry_type.as_str(), "view" | "trigger")));
+
+        let edited = |update: &str| {
+            let fixture = Fixture::new(schema);
+            let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+            conn.execute_batch(&format!("PRAGMA writable_schema = ON; {}", update))
+                .unwrap();
+            drop(conn);
+            let page_count = fixture.open().page_count;
+            let err = fixture.open().read_schema().unwrap_err();
+            (err.to_string(), page_count)
+        };
+        let (err, page_count) = edited("UPDATE sqlite_master SET rootpage = 999 WHERE name = 't';");
+        let expected = crate::error::RuSqliteError::Corrupt(format!(
+            "table t has root page 999 but the database has {} pages",
+            page_count
+        ));
+        assert_eq!(err, expected.to_string());
+        let (err, _) = edited("UPDATE sqlite_master SET rootpage = 0 WHERE name = 't_a';");
+        assert!(err.contains("index t_a has root page 0"), "{}", err);
+    }
+}
@@ -0,0 +1,75 @@
+use std::error::Error;
+
+use crate::db::Database;
+
+const STAT1_TABLE: &str = "sqlite_stat1";
+
+// A row of `sqlite_stat1` as written by ANALYZE. `stat` holds the approximate number of rows in
+// the table or index followed, for indexes, by the average number of rows matching each
+// prefix of the indexed columns. Trailing keywords such as `unordered` or `sz=N` go in `options`.
+#[derive(Debug)]
+pub struct Stat1Row {
+    pub tbl: String,
+    pub idx: Option<String>, // NULL for rows describing the table itself
+    pub stat: Vec<u64>,
+    pub options: Vec<String>,
+}
+
+impl Database {
+    // Rows of `sqlite_stat1`, or none if the database was never analyzed
+    pub fn stat1(&mut self) -> Result<Vec<Stat1Row>, Box<dyn Error>> {
+        if !self
+            .read_schema()?
+            .iter()
+            .any(|entry| entry.entry_type == "table" && entry.name == STAT1_TABLE)
+        {
+            return Ok(vec![]);
+        }
+
+        self.query_map(STAT1_TABLE, |values| {
+            let text_column = |idx: usize| values.get(idx).and_then(|value| value.as_text());
+            let tbl = text_column(0).ok_or("sqlite_stat1 row has no `tbl` value")?;
+            let (stat, options): (Vec<&str>, Vec<&str>) = text_column(2)
+                .unwrap_or_default()
+                .split_whitespace()
+                .partition(|word| word.parse::<u64>().is_ok());
+
+            Ok(Stat1Row {
+                tbl: tbl.to_owned(),
+                idx: text_column(1).map(str::to_owned),
+                stat: stat.iter().filter_map(|n| n.parse().ok()).collect(),
+                options: options.into_iter().map(str::to_owned).collect(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn reads_analyze_statistics() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (a, b);
+            CREATE INDEX t_a ON t (a);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 100)
+            INSERT INTO t SELECT i % 10, i FROM n;
+            ANALYZE;",
+        );
+        let stats = fixture.open().stat1().unwrap();
+        let index_stat = stats
+            .iter()
+            .find(|row| row.idx.as_deref() == Some("t_a"))
+            .unwrap();
+        assert_eq!(index_stat.tbl, "t");
+        // 100 rows, each value of `a` matching 10 of them
+        assert_eq!(index_stat.stat, [100, 10]);
+    }
+
+    #[test]
+    fn no_statistics_without_analyze() {
+        let fixture = Fixture::new("CREATE TABLE t (a);");
+        assert!(fixture.open().stat1().unwrap().is_empty());
+    }
+}
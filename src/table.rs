@@ -0,0 +1,1311 @@
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+use crate::{
+    btree_page::{BtreePage, PageType},
+    cell::{Cell, CellContent},
+    db::Database,
+    error::RuSqliteError,
+    index::IndexIterator,
+    record::{FieldData, LazyRecord, Record},
+    table_def::TableDef,
+    varint::decode_be,
+};
+
+// A row along with where it is stored: `(page_no, cell_index, row_id, record)`
+pub type LocatedRow = (u32, usize, i64, Record);
+
+type LeafCells = std::iter::Enumerate<std::vec::IntoIter<Cell>>;
+
+// A row or page that couldn't be read during a `TableIterator::robust` scan. `cell_index` is
+// None when a whole page failed and none of the rows below it could be reached.
+#[derive(Debug)]
+pub struct RowError {
+    pub page_no: u32,
+    pub cell_index: Option<usize>,
+    pub row_id: Option<i64>, // when the cell is intact enough to tell
+    details: String,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell_index {
+            Some(cell_index) => write!(
+                f,
+                "cell {} of page {}: {}",
+                cell_index, self.page_no, self.details
+            ),
+            None => write!(f, "page {}: {}", self.page_no, self.details),
+        }
+    }
+}
+
+impl Error for RowError {}
+
+// Iterates the rows of a table b-tree in row_id order, yielding `(row_id, record)` pairs. It
+// can also be consumed from the end, the two ends meet without yielding a row twice.
+#[derive(Debug)]
+pub struct TableIterator<'a> {
+    db: &'a mut Database,
+    root_page: u32,
+    // pages left to visit in descending row_id order: the next one going forward is at the
+    // back, the next one going backward at the front
+    pending_pages: VecDeque<u32>,
+    visited: HashSet<u32>, // interior pages loaded so far, to stop at the first loop
+    leaf: BtreePage,
+    leaf_cells: LeafCells,
+    back_leaf: BtreePage, // the leaf rows are read from going backward
+    back_leaf_cells: LeafCells,
+}
+
+impl<'a> TableIterator<'a> {
+    pub fn new(db: &'a mut Database, root_page: u32) -> Self {
+        Self {
+            db,
+            root_page,
+            pending_pages: VecDeque::from([root_page]),
+            visited: HashSet::new(),
+            leaf: BtreePage::default(),
+            leaf_cells: vec![].into_iter().enumerate(),
+            back_leaf: BtreePage::default(),
+            back_leaf_cells: vec![].into_iter().enumerate(),
+        }
+    }
+
+    // Rewind to the first row, keeping the allocated page stack for the next pass
+    pub fn reset(&mut self) {
+        self.pending_pages.clear();
+        self.pending_pages.push_back(self.root_page);
+        self.visited.clear();
+        self.leaf_cells = vec![].into_iter().enumerate();
+        self.back_leaf_cells = vec![].into_iter().enumerate();
+    }
+
+    // Keep only the rows whose record satisfies `pred`. Errors are always passed through so a
+    // failed read isn't silently filtered out.
+    pub fn filter_rows<F>(
+        self,
+        pred: F,
+    ) -> impl Iterator<Item = Result<(i64, Record), Box<dyn Error>>> + 'a
+    where
+        F: Fn(&Record) -> bool + 'a,
+    {
+        self.filter(move |row| match row {
+            Ok((_, record)) => pred(record),
+            Err(_) => true,
+        })
+    }
+
+    // Yield each row with its location, so it can be traced back to the leaf page it was read
+    // from and its position in that page's cell pointer array
+    pub fn with_page_info(
+        mut self,
+    ) -> impl Iterator<Item = Result<LocatedRow, Box<dyn Error>>> + 'a {
+        std::iter::from_fn(move || self.next_located())
+    }
+
+    fn next_located(&mut self) -> Option<Result<LocatedRow, Box<dyn Error>>> {
+        let (cell_index, cell) = match self.next_cell()? {
+            Ok(cell) => cell,
+            Err(e) => return Some(Err(e)),
+        };
+        let page_no = self.leaf.page_num;
+        let row = self.read_row(cell);
+        Some(row.map(|(row_id, record)| (page_no, cell_index, row_id, record)))
+    }
+
+    // The next cell of `leaf` going forward, loading pages until one is found. Once every
+    // page is loaded the last leaf is the backward one, which is swapped in to finish it.
+    fn next_cell(&mut self) -> Option<Result<(usize, Cell), Box<dyn Error>>> {
+        loop {
+            if let Some(cell) = self.leaf_cells.next() {
+                return Some(Ok(cell));
+            }
+            if let Some(page) = self.pending_pages.pop_back() {
+                if let Err(e) = self.load_page(page, false) {
+                    return Some(Err(e));
+                }
+            } else if self.back_leaf_cells.len() > 0 {
+                std::mem::swap(&mut self.leaf, &mut self.back_leaf);
+                std::mem::swap(&mut self.leaf_cells, &mut self.back_leaf_cells);
+            } else {
+                return None;
+            }
+        }
+    }
+
+    // Like `next_cell` going backward, the cell belongs to `back_leaf`
+    fn next_back_cell(&mut self) -> Option<Result<(usize, Cell), Box<dyn Error>>> {
+        loop {
+            if let Some(cell) = self.back_leaf_cells.next_back() {
+                return Some(Ok(cell));
+            }
+            if let Some(page) = self.pending_pages.pop_front() {
+                if let Err(e) = self.load_page(page, true) {
+                    return Some(Err(e));
+                }
+            } else if self.leaf_cells.len() > 0 {
+                std::mem::swap(&mut self.leaf, &mut self.back_leaf);
+                std::mem::swap(&mut self.leaf_cells, &mut self.back_leaf_cells);
+            } else {
+                return None;
+            }
+        }
+    }
+
+    // Visit a page: leaves become the current source of rows for the direction they were
+    // loaded from, interior pages queue their children (left child pointers in order followed
+    // by the right-most pointer) at that end of `pending_pages`
+    fn load_page(&mut self, page: u32, from_back: bool) -> Result<(), Box<dyn Error>> {
+        let mut btree_pg = BtreePage::default();
+        btree_pg
+            .read_page_header(self.db, page)
+            .map_err(|e| e.to_string())?;
+
+        match btree_pg.page_type {
+            PageType::LeafTable if from_back => {
+                self.back_leaf_cells = btree_pg.get_page_cells().into_iter().enumerate();
+                self.back_leaf = btree_pg;
+            }
+            PageType::LeafTable => {
+                self.leaf_cells = btree_pg.get_page_cells().into_iter().enumerate();
+                self.leaf = btree_pg;
+            }
+            PageType::InteriorTable => {
+                if !self.visited.insert(page) {
+                    // every page below a loop would be read again, so the scan ends here
+                    self.pending_pages.clear();
+                    return Err(RuSqliteError::Corrupt(format!(
+                        "page {} is referenced more than once in the b-tree rooted at page {}",
+                        page, self.root_page
+                    ))
+                    .into());
+                }
+                let mut children = vec![];
+                for cell in btree_pg.get_page_cells() {
+                    let content = CellContent::get_cell_data(&btree_pg, self.db, cell)?;
+                    children.push(content.get_left_child_pointer()?);
+                }
+                if let Some(rightmost_ptr) = btree_pg.rightmost_ptr {
+                    children.push(rightmost_ptr);
+                }
+                if from_back {
+                    for child in children {
+                        self.pending_pages.push_front(child);
+                    }
+                } else {
+                    self.pending_pages.extend(children.into_iter().rev());
+                }
+            }
+            _ => return Err(format!("page {} is not a table b-tree page", page).into()),
+        }
+        Ok(())
+    }
+
+    fn read_row(&mut self, cell: Cell) -> Result<(i64, Record), Box<dyn Error>> {
+        read_leaf_row(self.db, &self.leaf, cell)
+    }
+
+    // Keep going past rows and pages that fail to parse, yielding an error for each of them,
+    // so every intact row of a damaged table can still be read
+    pub fn robust(mut self) -> impl Iterator<Item = Result<(i64, Record), RowError>> + 'a {
+        std::iter::from_fn(move || self.next_robust())
+    }
+
+    fn next_robust(&mut self) -> Option<Result<(i64, Record), RowError>> {
+        loop {
+            if let Some((cell_index, cell)) = self.leaf_cells.next() {
+                let page_no = self.leaf.page_num;
+                return Some(self.read_row(cell).map_err(|e| RowError {
+                    page_no,
+                    cell_index: Some(cell_index),
+                    row_id: self.read_row_id(cell).ok(),
+                    details: e.to_string(),
+                }));
+            }
+            if let Some(page) = self.pending_pages.pop_back() {
+                if let Err(e) = self.load_page(page, false) {
+                    return Some(Err(RowError {
+                        page_no: page,
+                        cell_index: None,
+                        row_id: None,
+                        details: e.to_string(),
+                    }));
+                }
+            } else if self.back_leaf_cells.len() > 0 {
+                std::mem::swap(&mut self.leaf, &mut self.back_leaf);
+                std::mem::swap(&mut self.leaf_cells, &mut self.back_leaf_cells);
+            } else {
+                return None;
+            }
+        }
+    }
+
+    // Yield where each row is stored without decoding it. Records (overflow pages included)
+    // are only read for the rows passed to `LazyRows::record`.
+    pub fn lazy(self) -> LazyRows<'a> {
+        LazyRows { rows: self }
+    }
+
+    fn next_lazy(&mut self) -> Option<Result<LazyRow, Box<dyn Error>>> {
+        let (cell_index, cell) = match self.next_cell()? {
+            Ok(cell) => cell,
+            Err(e) => return Some(Err(e)),
+        };
+        let page_no = self.leaf.page_num;
+        let row_id = self.read_row_id(cell);
+        Some(row_id.map(|row_id| LazyRow {
+            page_no,
+            cell_index,
+            row_id,
+            cell,
+        }))
+    }
+
+    // The row_id follows the payload size varint at the start of the cell
+    fn read_row_id(&mut self, cell: Cell) -> Result<i64, Box<dyn Error>> {
+        let page_no = self.leaf.page_num;
+        let page_data = self.db.read_page(page_no).map_err(|e| e.to_string())?;
+        let cell_data = page_data
+            .get(cell.offset as usize..)
+            .ok_or_else(|| format!("cell offset {} is off page {}", cell.offset, page_no))?;
+        let (_, size_len) = decode_be(cell_data)?;
+        let (row_id, _) = decode_be(&cell_data[size_len..])?;
+        Ok(row_id as i64)
+    }
+}
+
+fn read_leaf_row(
+    db: &mut Database,
+    leaf: &BtreePage,
+    cell: Cell,
+) -> Result<(i64, Record), Box<dyn Error>> {
+    match CellContent::get_cell_data(leaf, db, cell)? {
+        CellContent::LeafTable {
+            row_id,
+            mut payload,
+            ..
+        } => {
+            payload.read_overflow(db)?;
+            let record = Record::parse_with_limit(&payload.payload, db.max_columns)?;
+            db.buffers.give_back(payload.payload);
+            Ok((row_id, record))
+        }
+        _ => Err(format!("page {} is not a table leaf page", leaf.page_num).into()),
+    }
+}
+
+// A row that hasn't been decoded yet, only its row_id and the cell it is stored in
+#[derive(Debug, Clone, Copy)]
+pub struct LazyRow {
+    pub page_no: u32,
+    pub cell_index: usize,
+    pub row_id: i64,
+    cell: Cell,
+}
+
+// Iterates the rows of a table b-tree like `TableIterator` but yields `LazyRow`s. The rows
+// worth keeping are decoded with `record`, so loop with `while let` rather than `for`.
+#[derive(Debug)]
+pub struct LazyRows<'a> {
+    rows: TableIterator<'a>,
+}
+
+impl LazyRows<'_> {
+    // Read and decode the record of `row`, following its overflow chain if there is one
+    pub fn record(&mut self, row: &LazyRow) -> Result<Record, Box<dyn Error>> {
+        let rows = &mut self.rows;
+        let (_, record) = if rows.leaf.page_num == row.page_no {
+            read_leaf_row(rows.db, &rows.leaf, row.cell)?
+        } else {
+            let leaf = BtreePage::at(rows.db, row.page_no, None)?;
+            read_leaf_row(rows.db, &leaf, row.cell)?
+        };
+        Ok(record)
+    }
+
+    // The payload of `row` (overflow included) with its columns left undecoded
+    pub fn lazy_record(&mut self, row: &LazyRow) -> Result<LazyRecord, Box<dyn Error>> {
+        let db = &mut *self.rows.db;
+        let leaf = BtreePage::at(db, row.page_no, None)?;
+        match CellContent::get_cell_data(&leaf, db, row.cell)? {
+            CellContent::LeafTable { mut payload, .. } => {
+                payload.read_overflow(db)?;
+                Ok(LazyRecord::new(payload.payload, db.max_columns))
+            }
+            _ => Err(format!("page {} is not a table leaf page", row.page_no).into()),
+        }
+    }
+}
+
+impl Iterator for LazyRows<'_> {
+    type Item = Result<LazyRow, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next_lazy()
+    }
+}
+
+impl Iterator for TableIterator<'_> {
+    type Item = Result<(i64, Record), Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.next_located()?;
+        Some(row.map(|(_, _, row_id, record)| (row_id, record)))
+    }
+
+    // the last row is found from the back instead of reading every row before it
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl DoubleEndedIterator for TableIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (_, cell) = match self.next_back_cell()? {
+            Ok(cell) => cell,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(read_leaf_row(self.db, &self.back_leaf, cell))
+    }
+}
+
+impl Database {
+    // Every row of the table `table_def` with all of its columns in declaration order, in
+    // row_id order or, for WITHOUT ROWID tables, primary key order. The rows of a WITHOUT
+    // ROWID table are the entries of the index b-tree at its root page.
+    pub fn table_records<'a>(
+        &'a mut self,
+        table_def: &'a TableDef,
+    ) -> Box<dyn Iterator<Item = Result<Record, Box<dyn Error>>> + 'a> {
+        if table_def.without_rowid {
+            return Box::new(IndexIterator::new(self, table_def.rootpage).records().map(
+                |record| {
+                    let mut record = record?;
+                    table_def.complete_without_rowid_record(&mut record);
+                    Ok(record)
+                },
+            ));
+        }
+        Box::new(TableIterator::new(self, table_def.rootpage).map(|row| {
+            let (row_id, mut record) = row?;
+            table_def.complete_record(row_id, &mut record);
+            Ok(record)
+        }))
+    }
+
+    // Map every row of `table` into a value of the caller's choosing, in row_id order (primary
+    // key order for WITHOUT ROWID tables). The INTEGER PRIMARY KEY column, if there is one,
+    // holds the row_id.
+    pub fn query_map<T, F>(&mut self, table: &str, mut f: F) -> Result<Vec<T>, Box<dyn Error>>
+    where
+        F: FnMut(&[FieldData]) -> Result<T, Box<dyn Error>>,
+    {
+        let table_def = self.table_def(table)?;
+        self.table_records(&table_def)
+            .map(|record| f(&record?.values))
+            .collect()
+    }
+
+    // Find the row of `table` with `row_id` by descending its b-tree from the root, binary
+    // searching the keys of each page. Row_ids and interior keys are compared as the signed
+    // integers SQLite orders them by, so sparse, negative and very large row_ids are found as
+    // readily as contiguous ones.
+    pub fn lookup_rowid(
+        &mut self,
+        table: &str,
+        row_id: i64,
+    ) -> Result<Option<Record>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+
+        let Some(payload) = self.find_row_payload(&table_def, row_id)? else {
+            return Ok(None);
+        };
+        let mut record = Record::parse_with_limit(&payload, self.max_columns)?;
+        table_def.complete_record(row_id, &mut record);
+        Ok(Some(record))
+    }
+
+    // The value of one column of the row with `row_id`, decoding only that column. None if
+    // there is no such row.
+    pub fn get(
+        &mut self,
+        table: &str,
+        row_id: i64,
+        column: &str,
+    ) -> Result<Option<FieldData>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+        let idx = table_def
+            .column_index(column)
+            .ok_or_else(|| format!("no such column: {}", column))?;
+
+        let Some(payload) = self.find_row_payload(&table_def, row_id)? else {
+            return Ok(None);
+        };
+        if table_def.rowid_alias == Some(idx) {
+            return Ok(Some(FieldData::Integer(row_id)));
+        }
+        let mut record = Record::new();
+        record.load_fields(&payload, self.max_columns)?;
+        match record.fields.as_deref().and_then(|fields| fields.get(idx)) {
+            Some(field) => {
+                let mut value = field.read_value(&payload)?;
+                table_def.restore_real(idx, &mut value);
+                Ok(Some(value))
+            }
+            // added by ALTER TABLE after the row was written
+            None => Ok(Some(
+                table_def.columns[idx]
+                    .default
+                    .clone()
+                    .unwrap_or(FieldData::Null(())),
+            )),
+        }
+    }
+
+    // Complete record payload (overflow included) of the row with `row_id`, found by descending
+    // the table's b-tree from the root
+    fn find_row_payload(
+        &mut self,
+        table_def: &TableDef,
+        row_id: i64,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let mut page = table_def.rootpage;
+        for _ in 0..self.page_count {
+            let btree_pg = BtreePage::at(self, page, None)?;
+            let cells = btree_pg.get_page_cells();
+
+            match btree_pg.page_type {
+                // the left child of a cell holds the keys up to and including the cell's key
+                PageType::InteriorTable => {
+                    // index of the first cell whose key is at least `row_id`
+                    let (mut low, mut high) = (0, cells.len());
+                    while low < high {
+                        let mid = low + (high - low) / 2;
+                        if cell_key(&btree_pg, self, cells[mid])? < row_id {
+                            low = mid + 1;
+                        } else {
+                            high = mid;
+                        }
+                    }
+                    page = match cells.get(low) {
+                        Some(cell) => CellContent::get_cell_data(&btree_pg, self, *cell)?
+                            .get_left_child_pointer()?,
+                        None => btree_pg.rightmost_ptr.unwrap_or_default(),
+                    };
+                }
+                PageType::LeafTable => {
+                    let Some(idx) = btree_pg.find_rowid(self, row_id)? else {
+                        return Ok(None);
+                    };
+                    let CellContent::LeafTable { mut payload, .. } =
+                        CellContent::get_cell_data(&btree_pg, self, cells[idx])?
+                    else {
+                        unreachable!("cells of a leaf table page are leaf table cells");
+                    };
+                    payload.read_overflow(self)?;
+                    return Ok(Some(payload.payload));
+                }
+                _ => return Err(format!("page {} is not a table b-tree page", page).into()),
+            }
+        }
+        Err(RuSqliteError::Corrupt(format!(
+            "b-tree of table {} is deeper than the database",
+            table_def.name
+        ))
+        .into())
+    }
+
+    // Call `f` with every row of `table`, spreading its leaf pages over rayon's thread pool.
+    // Each task reads through its own `reader` handle, all of them sharing this database's page
+    // cache, so rows arrive in no particular order.
+    #[cfg(feature = "parallel")]
+    pub fn par_for_each_row<F>(&self, table: &str, f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: Fn(i64, &Record) + Sync,
+    {
+        use rayon::prelude::*;
+
+        let mut db = self.reader()?;
+        let table_def = db.table_def(table)?;
+        table_def.check_rowid_table()?;
+        let leaves = db.leaf_pages(table_def.rootpage)?;
+
+        // errors are passed back as strings, `Box<dyn Error>` can't cross threads
+        leaves.par_iter().try_for_each_init(
+            || self.reader().map_err(|e| e.to_string()),
+            |reader, &page| -> Result<(), String> {
+                let db = reader.as_mut().map_err(|e| e.clone())?;
+                let leaf = BtreePage::at(db, page, None).map_err(|e| e.to_string())?;
+                for cell in leaf.get_page_cells() {
+                    let (row_id, mut record) =
+                        read_leaf_row(db, &leaf, cell).map_err(|e| e.to_string())?;
+                    table_def.complete_record(row_id, &mut record);
+                    f(row_id, &record);
+                }
+                Ok(())
+            },
+        )?;
+        Ok(())
+    }
+
+    // Row_ids of every row of `table` in order, decoded from the start of each leaf cell
+    // without reading its payload or following overflow pages
+    pub fn row_ids(&mut self, table: &str) -> Result<Vec<i64>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+
+        let mut row_ids = vec![];
+        for page in self.leaf_pages(table_def.rootpage)? {
+            let leaf = BtreePage::at(self, page, None)?;
+            let page_data = self.read_page(leaf.page_num).map_err(|e| e.to_string())?;
+            for ptr in &leaf.cell_pointers {
+                let cell = page_data
+                    .get(*ptr as usize..)
+                    .ok_or_else(|| format!("cell pointer {} is off page {}", ptr, leaf.page_num))?;
+                // the row_id follows the payload size varint
+                let (_, size_len) = decode_be(cell)?;
+                let (row_id, _) = decode_be(&cell[size_len..])?;
+                row_ids.push(row_id as i64);
+            }
+        }
+        Ok(row_ids)
+    }
+
+    // Number of rows in `table`, counted from the cell counts of its leaf pages
+    pub fn count_rows(&mut self, table: &str) -> Result<u64, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+
+        let mut count = 0;
+        for page in self.leaf_pages(table_def.rootpage)? {
+            count += u64::from(BtreePage::at(self, page, None)?.num_cells);
+        }
+        Ok(count)
+    }
+
+    // The row at position `n` (counting from 0) of `table` in row_id order, or None if the
+    // table has at most `n` rows. Whole leaves are skipped by their cell counts, so only the
+    // leaf holding the row is decoded.
+    pub fn nth_row(&mut self, table: &str, n: usize) -> Result<Option<Record>, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+
+        let mut remaining = n;
+        for page in self.leaf_pages(table_def.rootpage)? {
+            let leaf = BtreePage::at(self, page, None)?;
+            let num_cells = usize::from(leaf.num_cells);
+            if remaining >= num_cells {
+                remaining -= num_cells;
+                continue;
+            }
+            let cell = leaf.get_page_cells()[remaining];
+            let (row_id, mut record) = read_leaf_row(self, &leaf, cell)?;
+            table_def.complete_record(row_id, &mut record);
+            return Ok(Some(record));
+        }
+        Ok(None)
+    }
+
+    // Total number of payload bytes of `table` stored on overflow pages rather than in its
+    // leaf cells. Overflow pages themselves aren't read.
+    pub fn overflow_bytes(&mut self, table: &str) -> Result<u64, Box<dyn Error>> {
+        let table_def = self.table_def(table)?;
+        table_def.check_rowid_table()?;
+
+        let mut total = 0;
+        for page in self.leaf_pages(table_def.rootpage)? {
+            let leaf = BtreePage::at(self, page, None)?;
+            for cell in leaf.get_page_cells() {
+                let content = CellContent::get_cell_data(&leaf, self, cell)?;
+                if let CellContent::LeafTable { payload, .. } = content {
+                    total += payload.calculate_spillage(self, &leaf);
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    // Read every row of each named table, keyed by table name, so callers can join them in
+    // memory. The schema is only read once for all of the tables.
+    pub fn load_tables(
+        &mut self,
+        names: &[&str],
+    ) -> Result<HashMap<String, Vec<Record>>, Box<dyn Error>> {
+        let schema = self.read_schema()?;
+        let mut tables = HashMap::new();
+        for name in names {
+            let table_def = TableDef::from_schema(&schema, name)?;
+            table_def.check_rowid_table()?;
+            let records = TableIterator::new(self, table_def.rootpage)
+                .map(|row| {
+                    let (row_id, mut record) = row?;
+                    table_def.complete_record(row_id, &mut record);
+                    Ok(record)
+                })
+                .collect::<Result<Vec<Record>, Box<dyn Error>>>()?;
+            tables.insert(name.to_string(), records);
+        }
+        Ok(tables)
+    }
+}
+
+// The key a table b-tree cell is ordered by: the row_id of a leaf cell or the integer key of an
+// interior cell. Keys are stored as the varint of a signed 64-bit integer, negative ones
+// included, and are ordered as such.
+fn cell_key(btree_pg: &BtreePage, db: &mut Database, cell: Cell) -> Result<i64, Box<dyn Error>> {
+    match CellContent::get_cell_data(btree_pg, db, cell)? {
+        CellContent::LeafTable {
+            row_id, payload, ..
+        } => {
+            db.buffers.give_back(payload.payload);
+            Ok(row_id)
+        }
+        CellContent::InteriorTable { integer_key, .. } => Ok(integer_key),
+        _ => Err(format!("page {} is not a table b-tree page", btree_pg.page_num).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    // `t (id INTEGER PRIMARY KEY, n INTEGER)` holding n = 1..=count at the same row_ids
+    fn numbers(count: u32) -> Fixture {
+        Fixture::new(&format!(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER);
+            WITH RECURSIVE c(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM c WHERE i < {})
+            INSERT INTO t SELECT i, i FROM c;",
+            count
+        ))
+    }
+
+    #[test]
+    fn filter_rows_keeps_matching_rows() {
+        let fixture = numbers(25);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let even = TableIterator::new(&mut db, root)
+            .filter_rows(|record| record.values[1].as_integer().unwrap() % 2 == 0)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(even.len(), 12);
+        assert!(even.iter().all(|(row_id, _)| row_id % 2 == 0));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Person {
+        id: i64,
+        name: String,
+    }
+
+    #[test]
+    fn query_map_builds_structs() {
+        let fixture = Fixture::new(
+            "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT);
+            INSERT INTO people VALUES (1, 'Ada'), (7, 'Grace');",
+        );
+        let people = fixture
+            .open()
+            .query_map("people", |values| {
+                Ok(Person {
+                    id: values[0].as_integer().ok_or("id is not an integer")?,
+                    name: values[1].as_text().ok_or("name is not text")?.to_owned(),
+                })
+            })
+            .unwrap();
+        assert_eq!(
+            people,
+            [
+                Person {
+                    id: 1,
+                    name: "Ada".to_owned()
+                },
+                Person {
+                    id: 7,
+                    name: "Grace".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn null_only_row_is_read() {
+        let fixture = Fixture::new("CREATE TABLE t (a); INSERT INTO t VALUES (NULL);");
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let rows = TableIterator::new(&mut db, root)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].0, 1);
+        assert_eq!(rows[0].1.values(), [FieldData::Null(())]);
+    }
+
+    #[test]
+    fn load_tables_for_an_in_memory_join() {
+        let fixture = Fixture::new(
+            "CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT);
+            CREATE TABLE books (title TEXT, author_id INTEGER);
+            INSERT INTO authors VALUES (1, 'Le Guin'), (2, 'Lem');
+            INSERT INTO books VALUES ('Solaris', 2), ('The Dispossessed', 1), ('Fiasco', 2);",
+        );
+        let tables = fixture.open().load_tables(&["authors", "books"]).unwrap();
+        assert_eq!(tables.len(), 2);
+        let authors = tables["authors"]
+            .iter()
+            .map(|record| {
+                (
+                    record.values[0].as_integer().unwrap(),
+                    record.values[1].clone(),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let mut joined = tables["books"]
+            .iter()
+            .map(|book| {
+                let author = &authors[&book.values[1].as_integer().unwrap()];
+                (book.values[0].as_text().unwrap(), author.as_text().unwrap())
+            })
+            .collect::<Vec<_>>();
+        joined.sort();
+        assert_eq!(
+            joined,
+            [
+                ("Fiasco", "Lem"),
+                ("Solaris", "Lem"),
+                ("The Dispossessed", "Le Guin")
+            ]
+        );
+    }
+
+    #[test]
+    fn page_info_points_back_at_each_cell() {
+        let fixture = numbers(2000);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let located = TableIterator::new(&mut db, root)
+            .with_page_info()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(located.len(), 2000);
+
+        let mut pages = located.iter().map(|row| row.0).collect::<Vec<_>>();
+        pages.dedup();
+        assert!(pages.len() > 1);
+        for (position, (page_no, cell_index, row_id, record)) in located.iter().enumerate() {
+            assert_eq!(*row_id, position as i64 + 1);
+            assert_eq!(record.values[1].as_integer(), Some(*row_id));
+
+            let leaf = BtreePage::at(&mut db, *page_no, None).unwrap();
+            assert_eq!(leaf.page_type, PageType::LeafTable);
+            let ptr = leaf.cell_pointers[*cell_index] as usize;
+            let cell = &db.read_page(*page_no).unwrap()[ptr..];
+            let (_, size_len) = decode_be(cell).unwrap();
+            assert_eq!(decode_be(&cell[size_len..]).unwrap().0 as i64, *row_id);
+        }
+    }
+
+    #[test]
+    fn row_ids_skip_payloads() {
+        let fixture = Fixture::build(512, |conn| {
+            conn.execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY, b BLOB);")
+                .unwrap();
+            // every payload spills to overflow pages, leaving room for one cell per leaf
+            for row_id in [3, 10, 42, 1000] {
+                conn.execute(
+                    "INSERT INTO t VALUES (?1, ?2)",
+                    rusqlite::params![row_id, vec![7u8; 3000]],
+                )
+                .unwrap();
+            }
+        });
+        let mut db = fixture.open();
+        let before = db.file_reads();
+        assert_eq!(db.row_ids("t").unwrap(), [3, 10, 42, 1000]);
+        // page 1, the interior root and one leaf per row, none of the overflow pages
+        assert_eq!(db.file_reads() - before, 6);
+        assert_eq!(db.page_count, 26);
+    }
+
+    #[test]
+    fn lazy_rows_only_read_overflow_of_decoded_rows() {
+        let fixture = Fixture::build(512, |conn| {
+            conn.execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY, b BLOB);")
+                .unwrap();
+            // a 3004 byte payload keeps 464 bytes in its cell, the rest fills 5 overflow pages
+            for row_id in [3, 10, 42, 1000] {
+                conn.execute(
+                    "INSERT INTO t VALUES (?1, ?2)",
+                    rusqlite::params![row_id, vec![row_id as u8; 3000]],
+                )
+                .unwrap();
+            }
+        });
+        let overflow = fixture.open().all_overflow_pages().unwrap();
+        assert_eq!(overflow.len(), 20);
+
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let before = db.file_reads();
+        let mut rows = TableIterator::new(&mut db, root).lazy();
+        let mut kept = Vec::new();
+        while let Some(row) = rows.next() {
+            let row = row.unwrap();
+            if (5..=100).contains(&row.row_id) {
+                kept.push((row.row_id, rows.record(&row).unwrap()));
+            }
+        }
+        drop(rows);
+
+        assert_eq!(kept.len(), 2);
+        for (row_id, record) in &kept {
+            assert_eq!(record.values[1], FieldData::Blob(vec![*row_id as u8; 3000]));
+        }
+        // the root and the four leaves, then only the chains of rows 10 and 42
+        assert_eq!(db.file_reads() - before, 5 + 10);
+        let read = overflow
+            .iter()
+            .filter(|&&page| db.cached_page(page).is_some())
+            .count();
+        assert_eq!(read, 10);
+    }
+
+    #[test]
+    fn overflow_bytes_totals_spilled_payload() {
+        let fixture = Fixture::build(512, |conn| {
+            conn.execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY, b BLOB);")
+                .unwrap();
+            for size in [3000, 3000, 10] {
+                conn.execute("INSERT INTO t (b) VALUES (?1)", [vec![1u8; size]])
+                    .unwrap();
+            }
+        });
+        // a 3004 byte payload (4 header bytes) keeps 39 + (3004 - 39) % 508 = 464 bytes in its
+        // cell on 512 byte pages, and the 10 byte blob doesn't spill at all
+        assert_eq!(
+            fixture.open().overflow_bytes("t").unwrap(),
+            2 * (3004 - 464)
+        );
+
+        assert_eq!(numbers(100).open().overflow_bytes("t").unwrap(), 0);
+    }
+
+    #[test]
+    fn lookup_rowid_with_sparse_and_negative_row_ids() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 600)
+            INSERT INTO t SELECT (i - 300) * 1000003, printf('%0100d', i) FROM n;
+            INSERT INTO t VALUES (1, 'one'), (9000000000000, 'big'),
+                (-9223372036854775808, 'min'), (9223372036854775807, 'max');",
+        );
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert!(db.btree_depth(root).unwrap() > 1);
+
+        for (row_id, text) in [
+            (1, "one"),
+            (9_000_000_000_000, "big"),
+            (i64::MIN, "min"),
+            (i64::MAX, "max"),
+        ] {
+            let record = db.lookup_rowid("t", row_id).unwrap().unwrap();
+            assert_eq!(record.values[0], FieldData::Integer(row_id));
+            assert_eq!(record.values[1].as_text(), Some(text));
+        }
+        for i in [0, 1, 299, 300, 301, 600] {
+            let row_id = (i - 300) * 1_000_003;
+            let record = db.lookup_rowid("t", row_id).unwrap().unwrap();
+            assert_eq!(record.values[0], FieldData::Integer(row_id));
+            assert_eq!(
+                record.values[1].as_text(),
+                Some(format!("{:0100}", i).as_str())
+            );
+            assert_eq!(
+                db.get("t", row_id, "id").unwrap(),
+                Some(FieldData::Integer(row_id))
+            );
+        }
+        for missing in [
+            2,
+            -1,
+            -1_000_002,
+            1_000_000,
+            9_000_000_000_001,
+            i64::MIN + 1,
+        ] {
+            assert!(db.lookup_rowid("t", missing).unwrap().is_none());
+        }
+
+        // a scan returns them in ascending signed order, and each one looks up its own row
+        let mut expected = (0..=600)
+            .map(|i| (i - 300) * 1_000_003)
+            .collect::<Vec<i64>>();
+        expected.extend([1, 9_000_000_000_000, i64::MIN, i64::MAX]);
+        expected.sort();
+        let scanned = TableIterator::new(&mut db, root)
+            .map(|row| row.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(scanned, expected);
+        assert_eq!(scanned.first(), Some(&i64::MIN));
+        assert_eq!(scanned.last(), Some(&i64::MAX));
+        assert_eq!(db.row_ids("t").unwrap(), expected);
+        for row_id in db.row_ids("t").unwrap() {
+            assert!(db.lookup_rowid("t", row_id).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn reset_rewinds_to_the_first_row() {
+        let fixture = numbers(2000);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let mut rows = TableIterator::new(&mut db, root);
+        let row_ids = |rows: &mut TableIterator, n: usize| {
+            rows.by_ref()
+                .take(n)
+                .map(|row| row.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+        // stop halfway through a leaf and with the last leaf half read from the back
+        assert_eq!(row_ids(&mut rows, 3), [1, 2, 3]);
+        assert_eq!(rows.next_back().unwrap().unwrap().0, 2000);
+
+        rows.reset();
+        assert_eq!(row_ids(&mut rows, 3), [1, 2, 3]);
+        rows.reset();
+        let all = row_ids(&mut rows, usize::MAX);
+        assert_eq!(all, (1..=2000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn get_fetches_one_column_of_one_row() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, score REAL, note);
+            INSERT INTO t VALUES (7, 'seven', 7.0, NULL), (8, 'eight', 8.5, x'08');
+            ALTER TABLE t ADD COLUMN added INTEGER DEFAULT 42;
+            INSERT INTO t VALUES (9, 'nine', 9.25, 'n', 1);
+            CREATE TABLE w (k TEXT PRIMARY KEY, v) WITHOUT ROWID;",
+        );
+        let mut db = fixture.open();
+        assert_eq!(
+            db.get("t", 7, "name").unwrap(),
+            Some(FieldData::Text("seven".to_owned()))
+        );
+        assert_eq!(db.get("t", 8, "ID").unwrap(), Some(FieldData::Integer(8)));
+        // stored as the integer 7 but read back as a REAL
+        assert_eq!(db.get("t", 7, "score").unwrap(), Some(FieldData::Real(7.0)));
+        assert_eq!(
+            db.get("t", 8, "note").unwrap(),
+            Some(FieldData::Blob(vec![8]))
+        );
+        assert_eq!(db.get("t", 7, "note").unwrap(), Some(FieldData::Null(())));
+        assert_eq!(
+            db.get("t", 8, "added").unwrap(),
+            Some(FieldData::Integer(42))
+        );
+        assert_eq!(
+            db.get("t", 9, "added").unwrap().unwrap().as_integer(),
+            Some(1)
+        );
+
+        assert_eq!(db.get("t", 10, "name").unwrap(), None);
+        assert_eq!(db.get("t", -7, "id").unwrap(), None);
+        let err = db.get("t", 7, "missing").unwrap_err();
+        assert_eq!(err.to_string(), "no such column: missing");
+        assert!(db.get("w", 1, "v").is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn par_for_each_row_sums_like_a_serial_scan() {
+        use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER, s TEXT);
+            WITH RECURSIVE k(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM k WHERE i < 5000)
+            INSERT INTO t SELECT i, (i * 7919) % 1000 - 500, printf('%040d', i) FROM k;
+            CREATE TABLE w (k PRIMARY KEY) WITHOUT ROWID;",
+        );
+        let mut db = fixture.open();
+        let table_def = db.table_def("t").unwrap();
+        let serial = db
+            .table_records(&table_def)
+            .map(|record| record.unwrap().values[1].as_integer().unwrap())
+            .sum::<i64>();
+
+        let db = fixture.open();
+        let (sum, rows) = (AtomicI64::new(0), AtomicU64::new(0));
+        db.par_for_each_row("t", |row_id, record| {
+            assert_eq!(record.values[0], FieldData::Integer(row_id));
+            sum.fetch_add(record.values[1].as_integer().unwrap(), Ordering::Relaxed);
+            rows.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap();
+        assert_eq!(sum.into_inner(), serial);
+        assert_eq!(rows.into_inner(), 5000);
+
+        assert!(db.par_for_each_row("w", |_, _| {}).is_err());
+        assert!(db.par_for_each_row("missing", |_, _| {}).is_err());
+    }
+
+    #[test]
+    fn nth_row_by_position() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500)
+            INSERT INTO t SELECT i * i - 1000, printf('%050d', i) FROM n;",
+        );
+        let mut db = fixture.open();
+        let third = db.nth_row("t", 2).unwrap().unwrap();
+        // row_ids start at 1 - 1000, so positions and row_ids differ
+        assert_eq!(third.values[0], FieldData::Integer(9 - 1000));
+        assert_eq!(
+            third.values[1].as_text(),
+            Some(format!("{:050}", 3).as_str())
+        );
+        for n in [0, 3, 4, 100, 499] {
+            let row = db.nth_row("t", n).unwrap().unwrap();
+            let i = n as i64 + 1;
+            assert_eq!(row.values[0], FieldData::Integer(i * i - 1000));
+        }
+        assert!(db.nth_row("t", 500).unwrap().is_none());
+        assert!(db.nth_row("t", usize::MAX).unwrap().is_none());
+    }
+
+    #[test]
+    fn nth_row_skips_the_overflow_of_earlier_rows() {
+        let fixture = Fixture::build(512, |conn| {
+            conn.execute_batch("CREATE TABLE t (id INTEGER PRIMARY KEY, b BLOB);")
+                .unwrap();
+            for row_id in 1..=6 {
+                conn.execute(
+                    "INSERT INTO t VALUES (?1, ?2)",
+                    rusqlite::params![row_id, vec![row_id as u8; 3000]],
+                )
+                .unwrap();
+            }
+        });
+        let overflow = fixture.open().all_overflow_pages().unwrap();
+        let mut db = fixture.open();
+        let row = db.nth_row("t", 4).unwrap().unwrap();
+        assert_eq!(row.values[1], FieldData::Blob(vec![5; 3000]));
+        // only the 5 overflow pages of the row fetched
+        let read = overflow
+            .iter()
+            .filter(|&&page| db.cached_page(page).is_some())
+            .count();
+        assert_eq!(read, 5);
+    }
+
+    #[test]
+    fn rows_from_both_ends_meet_without_duplicates() {
+        for count in [0, 1, 2, 3, 50, 2000] {
+            let fixture = match count {
+                0 => Fixture::new("CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER);"),
+                count => numbers(count),
+            };
+            let mut db = fixture.open();
+            let root = db.table_def("t").unwrap().rootpage;
+            let all = (1..=i64::from(count)).collect::<Vec<_>>();
+
+            let reversed = TableIterator::new(&mut db, root)
+                .rev()
+                .map(|row| row.unwrap().0)
+                .collect::<Vec<_>>();
+            assert_eq!(reversed, all.iter().rev().copied().collect::<Vec<_>>());
+            let last = TableIterator::new(&mut db, root).last();
+            assert_eq!(last.map(|row| row.unwrap().0), all.last().copied());
+
+            // alternate sides in a few patterns, front rows then back rows make up every row
+            for pattern in [[true, false], [true, true], [false, false]] {
+                let mut rows = TableIterator::new(&mut db, root);
+                let (mut front, mut back) = (vec![], vec![]);
+                for step in pattern.iter().cycle() {
+                    let (row, side) = match step {
+                        true => (rows.next(), &mut front),
+                        false => (rows.next_back(), &mut back),
+                    };
+                    let Some(row) = row else { break };
+                    side.push(row.unwrap().0);
+                }
+                assert!(rows.next().is_none() && rows.next_back().is_none());
+                front.extend(back.into_iter().rev());
+                assert_eq!(front, all, "{} rows, {:?}", count, pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn robust_scan_reports_bad_rows_and_pages() {
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT);
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 60)
+            INSERT INTO t SELECT i, printf('row-%04d-%050d', i, 0) FROM n;",
+        );
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        let leaves = db.leaf_pages(root).unwrap();
+        // the leaf, the cell index and the offset in the file of the cell holding `row_id`
+        let mut find = |row_id: i64| {
+            for &page in &leaves {
+                let leaf = BtreePage::at(&mut db, page, None).unwrap();
+                if let Some(i) = leaf.find_rowid(&mut db, row_id).unwrap() {
+                    let offset = db.page_start(page) + leaf.get_page_cells()[i].offset;
+                    return (leaf, i, offset);
+                }
+            }
+            panic!("row {} not found", row_id);
+        };
+        // the leaf holding row 40, whose rows are all lost once its type byte is damaged
+        let (lost, _, _) = find(40);
+        let (lost, lost_rows) = (lost.page_num, lost.num_cells as usize);
+        // invalid UTF-8 in the text of row 7. Page splits leave stale copies of cells behind,
+        // so the text is looked for from the start of the live cell.
+        let (damaged, damaged_cell, cell_offset) = find(7);
+        // the cell pointer of row 20 pointing past the end of its page
+        let (off_page, off_page_cell, _) = find(20);
+        assert!(off_page.page_num != damaged.page_num && off_page.page_num != lost);
+        let pointer = db.page_start(off_page.page_num) + 8 + 2 * off_page_cell as u64;
+        let bytes = fixture.bytes();
+        let intact = bytes.clone();
+        let text = bytes[cell_offset as usize..]
+            .windows(9)
+            .position(|w| w == b"row-0007-")
+            .unwrap();
+        fixture.patch(cell_offset + text as u64, &[0xff]);
+        fixture.patch(db.page_start(lost), &[0x00]);
+        fixture.patch(pointer, &[0xff, 0xff]);
+
+        let mut db = fixture.open();
+        let (mut good, mut bad) = (vec![], vec![]);
+        for row in TableIterator::new(&mut db, root).robust() {
+            match row {
+                Ok((row_id, record)) => {
+                    let text = record.values[1].as_text().unwrap().to_owned();
+                    assert!(text.starts_with(&format!("row-{:04}-", row_id)));
+                    good.push(row_id);
+                }
+                Err(e) => bad.push(e),
+            }
+        }
+        assert_eq!(good.len(), 60 - 2 - lost_rows);
+        assert!(!good.contains(&7) && !good.contains(&20) && !good.contains(&40));
+        assert!(good.windows(2).all(|w| w[0] < w[1]));
+
+        assert_eq!(bad.len(), 3);
+        let row_error = bad.iter().find(|e| e.row_id == Some(7)).unwrap();
+        assert_eq!(row_error.cell_index, Some(damaged_cell));
+        let off_page_error = bad.iter().find(|e| e.page_no == off_page.page_num).unwrap();
+        assert_eq!(off_page_error.cell_index, Some(off_page_cell));
+        assert_eq!(off_page_error.row_id, None);
+        assert!(off_page_error.to_string().contains("is off page"));
+        let page_error = bad.iter().find(|e| e.cell_index.is_none()).unwrap();
+        assert_eq!(page_error.page_no, lost);
+        assert_eq!(page_error.row_id, None);
+
+        // collecting a plain scan stops at the first of them
+        let mut db = fixture.open();
+        let rows = TableIterator::new(&mut db, root).collect::<Result<Vec<_>, _>>();
+        assert!(rows.is_err());
+
+        // a root whose right-most pointer leads back to itself ends the scan with one error
+        let looping = Fixture::from_bytes(&intact);
+        looping.patch(db.page_start(root) + 8, &root.to_be_bytes());
+        let mut db = looping.open();
+        let rows = TableIterator::new(&mut db, root)
+            .robust()
+            .take(1000)
+            .collect::<Vec<_>>();
+        let (last, rows) = rows.split_last().unwrap();
+        let err = last.as_ref().unwrap_err();
+        assert_eq!(err.page_no, root);
+        assert!(err.to_string().contains("is referenced more than once"));
+        assert!(rows.len() < 60);
+        let row_ids = rows
+            .iter()
+            .map(|row| row.as_ref().unwrap().0)
+            .collect::<Vec<_>>();
+        assert!(row_ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn query_map_reads_without_rowid_tables_in_key_order() {
+        // the key (c, a) is stored ahead of b, one key spills to overflow pages and d was added
+        // after most rows were written
+        let fixture = Fixture::with_page_size(
+            512,
+            "CREATE TABLE w (a TEXT, b INTEGER, c TEXT, PRIMARY KEY (c, a)) WITHOUT ROWID;
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3000)
+            INSERT INTO w SELECT printf('a%d', i), i % 97, printf('c%03d', (i * 7919) % 500)
+            FROM n;
+            INSERT INTO w VALUES (printf('%01000d', 1), -1, 'c000');
+            ALTER TABLE w ADD COLUMN d TEXT DEFAULT 'later';
+            INSERT INTO w VALUES ('new', 5, 'c250', 'set');",
+        );
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        let expected = conn
+            .prepare("SELECT a, b, c, d FROM w ORDER BY c, a")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<Vec<(String, i64, String, String)>, _>>()
+            .unwrap();
+        drop(conn);
+
+        let mut db = fixture.open();
+        let table_def = db.table_def("w").unwrap();
+        assert!(db.btree_depth(table_def.rootpage).unwrap() > 2);
+        let text = |value: &FieldData| value.as_text().map(str::to_owned).ok_or("not text");
+        let rows = db
+            .query_map("w", |values| {
+                Ok((
+                    text(&values[0])?,
+                    values[1].as_integer().ok_or("b is not an integer")?,
+                    text(&values[2])?,
+                    text(&values[3])?,
+                ))
+            })
+            .unwrap();
+        assert_eq!(rows.len(), 3002);
+        assert_eq!(rows, expected);
+        // row_ids stay undefined for WITHOUT ROWID tables
+        assert!(db.row_ids("w").is_err());
+    }
+
+    #[test]
+    fn leaf_walks_stop_at_a_looping_btree() {
+        let fixture = numbers(2000);
+        let mut db = fixture.open();
+        let root = db.table_def("t").unwrap().rootpage;
+        assert!(db.btree_depth(root).unwrap() > 1);
+        // point the root's right-most child back at the root
+        fixture.patch(db.page_start(root) + 8, &root.to_be_bytes());
+
+        let mut db = fixture.open();
+        let looping = |err: Box<dyn Error>| {
+            assert!(
+                err.to_string().contains("is referenced more than once"),
+                "{}",
+                err
+            );
+        };
+        looping(db.row_ids("t").unwrap_err());
+        looping(db.count_rows("t").unwrap_err());
+        looping(db.nth_row("t", 1999).unwrap_err());
+        looping(db.overflow_bytes("t").unwrap_err());
+    }
+}
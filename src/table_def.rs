@@ -0,0 +1,674 @@
+#![allow(dead_code)]
+
+use std::error::Error;
+use std::fmt;
+
+use crate::{
+    db::Database,
+    error::RuSqliteError,
+    record::{Affinity, FieldData, Record},
+    schema::{SchemaEntry, SCHEMA_TABLE, SCHEMA_TABLE_SQL},
+};
+
+#[derive(Debug)]
+pub struct TableDefError {
+    details: String,
+}
+
+impl TableDefError {
+    fn new(table: &str, reason: &str) -> Self {
+        Self {
+            details: format!(
+                "could not parse definition of table `{}`: {}",
+                table, reason
+            ),
+        }
+    }
+}
+
+impl fmt::Display for TableDefError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl Error for TableDefError {}
+
+// keywords that end a column's type name and start its constraints
+const COLUMN_CONSTRAINTS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+// keywords that start a table constraint instead of a column definition
+const TABLE_CONSTRAINTS: [&str; 5] = ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
+#[derive(Debug)]
+pub struct ColumnDef {
+    pub name: String,
+    pub type_name: String,    // declared type as written, empty when omitted
+    constraints: Vec<String>, // words following the type name, as written
+    pub default: Option<FieldData>, // value of a constant DEFAULT clause
+}
+
+// Columns of a table as declared by its `CREATE TABLE` statement
+#[derive(Debug)]
+pub struct TableDef {
+    pub name: String,
+    pub rootpage: u32,
+    pub columns: Vec<ColumnDef>,
+    pub rowid_alias: Option<usize>, // index of the INTEGER PRIMARY KEY column, if any
+    // indexes of the primary key columns in key order, empty without a PRIMARY KEY
+    pub primary_key: Vec<usize>,
+    pub without_rowid: bool,
+}
+
+impl TableDef {
+    pub fn parse(name: &str, rootpage: u32, sql: &str) -> Result<Self, TableDefError> {
+        let body_start = sql
+            .find('(')
+            .ok_or_else(|| TableDefError::new(name, "missing column list"))?;
+        let body_end = sql
+            .rfind(')')
+            .filter(|end| *end > body_start)
+            .ok_or_else(|| TableDefError::new(name, "unterminated column list"))?;
+
+        let mut columns = vec![];
+        let mut table_pk = vec![];
+        for part in split_top_level(&sql[body_start + 1..body_end]) {
+            let first_word = part
+                .split(|c: char| c.is_whitespace() || c == '(')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_uppercase();
+            if TABLE_CONSTRAINTS.contains(&first_word.as_str()) {
+                if let Some(pk_columns) = primary_key_columns(part) {
+                    table_pk = pk_columns;
+                }
+                continue;
+            }
+            columns.push(parse_column(part).ok_or_else(|| TableDefError::new(name, part))?);
+        }
+        if columns.is_empty() {
+            return Err(TableDefError::new(name, "no columns"));
+        }
+
+        let without_rowid = sql[body_end + 1..]
+            .split_whitespace()
+            .map(|word| word.trim_matches(',').to_ascii_uppercase())
+            .collect::<Vec<_>>()
+            .windows(2)
+            .any(|words| words[0] == "WITHOUT" && words[1] == "ROWID");
+
+        // the key is either a table-level `PRIMARY KEY (a, b)` or a single column's constraint
+        let primary_key = if table_pk.is_empty() {
+            columns
+                .iter()
+                .position(|column| column.is_primary_key())
+                .into_iter()
+                .collect()
+        } else {
+            table_pk
+                .iter()
+                .map(|pk| {
+                    columns
+                        .iter()
+                        .position(|column| column.name.eq_ignore_ascii_case(pk))
+                        .ok_or_else(|| {
+                            TableDefError::new(name, &format!("no primary key column {}", pk))
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        // a single-column primary key declared as exactly INTEGER aliases the rowid
+        let rowid_alias = match primary_key.as_slice() {
+            [idx] if columns[*idx].type_name.eq_ignore_ascii_case("INTEGER") => Some(*idx),
+            _ => None,
+        }
+        .filter(|_| !without_rowid);
+
+        Ok(Self {
+            name: name.to_owned(),
+            rootpage,
+            columns,
+            rowid_alias,
+            primary_key,
+            without_rowid,
+        })
+    }
+
+    // WITHOUT ROWID tables are stored in an index b-tree keyed by their primary key, which only
+    // `Database::table_records` and the APIs built on it read so far
+    pub fn check_rowid_table(&self) -> Result<(), RuSqliteError> {
+        if self.without_rowid {
+            return Err(RuSqliteError::Unsupported("WITHOUT ROWID tables"));
+        }
+        Ok(())
+    }
+
+    pub fn column_index(&self, column: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|col| col.name.eq_ignore_ascii_case(column))
+    }
+
+    // Fill in the columns a stored record doesn't carry: trailing columns added by ALTER TABLE
+    // after the row was written take their DEFAULT (NULL without one), and the rowid alias
+    // takes the row_id
+    pub fn complete_record(&self, row_id: i64, record: &mut Record) {
+        let stored = record.values.len();
+        for column in self.columns.iter().skip(stored) {
+            let default = column.default.clone();
+            record.values.push(default.unwrap_or(FieldData::Null(())));
+        }
+        self.apply_rowid_alias(row_id, record);
+        self.restore_reals(record);
+    }
+
+    // SQLite stores REAL column values without a fractional part as integers to save space,
+    // and turns them back into reals when they are read
+    fn restore_reals(&self, record: &mut Record) {
+        for (idx, value) in record.values.iter_mut().enumerate() {
+            self.restore_real(idx, value);
+        }
+    }
+
+    // `restore_reals` for the single value of column `idx`
+    pub fn restore_real(&self, idx: usize, value: &mut FieldData) {
+        let Some(column) = self.columns.get(idx) else {
+            return;
+        };
+        if column.affinity() == Affinity::Real {
+            if let Some(integer) = value.as_integer() {
+                *value = FieldData::Real(integer as f64);
+            }
+        }
+    }
+
+    // A WITHOUT ROWID table stores its primary key columns first, in key order, followed by
+    // the other columns in declaration order. Put them back in declaration order, with the
+    // DEFAULT of columns added after the row was written.
+    pub fn complete_without_rowid_record(&self, record: &mut Record) {
+        let mut stored = std::mem::take(&mut record.values).into_iter();
+        let mut values = self
+            .columns
+            .iter()
+            .map(|column| column.default.clone().unwrap_or(FieldData::Null(())))
+            .collect::<Vec<_>>();
+        for idx in &self.primary_key {
+            if let Some(value) = stored.next() {
+                values[*idx] = value;
+            }
+        }
+        let other_columns = (0..self.columns.len()).filter(|idx| !self.primary_key.contains(idx));
+        for (idx, value) in other_columns.zip(stored) {
+            values[idx] = value;
+        }
+        record.values = values;
+        self.restore_reals(record);
+    }
+
+    // The rowid alias column is stored as NULL in the record, its value is the row_id
+    pub fn apply_rowid_alias(&self, row_id: i64, record: &mut Record) {
+        if let Some(value) = self.rowid_alias.and_then(|idx| record.values.get_mut(idx)) {
+            *value = FieldData::Integer(row_id);
+        }
+    }
+}
+
+impl ColumnDef {
+    // Name of the collation given by a COLLATE clause, if any
+    pub fn collation(&self) -> Option<&str> {
+        self.constraints
+            .windows(2)
+            .find(|words| words[0].eq_ignore_ascii_case("COLLATE"))
+            .map(|words| words[1].trim_matches(|c| "\"'`[],".contains(c)))
+    }
+
+    pub fn affinity(&self) -> Affinity {
+        Affinity::from_type_name(&self.type_name)
+    }
+
+    fn is_primary_key(&self) -> bool {
+        self.constraints.windows(2).any(|words| {
+            words[0].eq_ignore_ascii_case("PRIMARY") && words[1].eq_ignore_ascii_case("KEY")
+        })
+    }
+}
+
+impl TableDef {
+    // Find and parse the definition of `table` among already read schema entries
+    pub fn from_schema(schema: &[SchemaEntry], table: &str) -> Result<Self, Box<dyn Error>> {
+        // the schema table is always rooted at page 1
+        if table.eq_ignore_ascii_case(SCHEMA_TABLE) || table.eq_ignore_ascii_case("sqlite_schema") {
+            return Ok(TableDef::parse(SCHEMA_TABLE, 1, SCHEMA_TABLE_SQL)?);
+        }
+        let entry = schema
+            .iter()
+            .find(|entry| entry.entry_type == "table" && entry.name.eq_ignore_ascii_case(table))
+            .ok_or_else(|| format!("no such table: {}", table))?;
+        let sql = entry
+            .sql
+            .as_ref()
+            .ok_or_else(|| TableDefError::new(table, "missing CREATE TABLE statement"))?;
+        Ok(TableDef::parse(&entry.name, entry.rootpage, sql)?)
+    }
+}
+
+impl Database {
+    pub fn table_def(&mut self, table: &str) -> Result<TableDef, Box<dyn Error>> {
+        TableDef::from_schema(&self.read_schema()?, table)
+    }
+}
+
+// Split on commas that are not nested in parentheses or quotes
+fn split_top_level(body: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut start = 0;
+    for (idx, c) in body.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(body[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(body[start..].trim());
+    parts.into_iter().filter(|part| !part.is_empty()).collect()
+}
+
+fn unquote(identifier: &str) -> String {
+    let quoted = [('"', '"'), ('`', '`'), ('[', ']'), ('\'', '\'')]
+        .iter()
+        .any(|(open, close)| {
+            identifier.len() >= 2 && identifier.starts_with(*open) && identifier.ends_with(*close)
+        });
+    if quoted {
+        identifier[1..identifier.len() - 1].to_owned()
+    } else {
+        identifier.to_owned()
+    }
+}
+
+// Length of the identifier at the start of `text`, which may be quoted and contain whitespace
+fn identifier_end(text: &str) -> Option<usize> {
+    match text.chars().next()? {
+        open @ ('"' | '`' | '[' | '\'') => {
+            let close = if open == '[' { ']' } else { open };
+            Some(text[1..].find(close)? + 2)
+        }
+        _ => Some(text.find(char::is_whitespace).unwrap_or(text.len())),
+    }
+}
+
+fn parse_column(part: &str) -> Option<ColumnDef> {
+    let name_end = identifier_end(part)?;
+    let name = unquote(&part[..name_end]);
+
+    let mut type_words = vec![];
+    let mut constraints = vec![];
+    for word in part[name_end..].split_whitespace() {
+        let keyword = word.to_ascii_uppercase();
+        if constraints.is_empty() && !COLUMN_CONSTRAINTS.contains(&keyword.as_str()) {
+            type_words.push(word);
+        } else {
+            constraints.push(word.to_owned());
+        }
+    }
+
+    Some(ColumnDef {
+        name,
+        type_name: type_words.join(" "),
+        constraints,
+        default: parse_default(&part[name_end..]),
+    })
+}
+
+// Value of the `DEFAULT` clause among a column's type and constraints, if it is a constant
+fn parse_default(definition: &str) -> Option<FieldData> {
+    let mut quote: Option<char> = None;
+    let mut word_start = true;
+    for (idx, c) in definition.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, _) if word_start && keyword_at(definition, idx, "DEFAULT") => {
+                return parse_literal(definition[idx + "DEFAULT".len()..].trim_start());
+            }
+            _ => {}
+        }
+        word_start = quote.is_none() && (c.is_whitespace() || c == '(');
+    }
+    None
+}
+
+fn keyword_at(text: &str, idx: usize, keyword: &str) -> bool {
+    let end = idx + keyword.len();
+    text.get(idx..end)
+        .is_some_and(|word| word.eq_ignore_ascii_case(keyword))
+        && !text[end..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// A literal value: a number, 'string', X'hex' blob, NULL, TRUE or FALSE, optionally in
+// parentheses. Other expressions (e.g. CURRENT_TIMESTAMP) are evaluated on insert and
+// aren't supported.
+fn parse_literal(text: &str) -> Option<FieldData> {
+    if let Some(inner) = text.strip_prefix('(') {
+        let mut depth = 1;
+        let close = inner.char_indices().find_map(|(idx, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(idx)
+        })?;
+        return parse_literal(inner[..close].trim());
+    }
+    if let Some(rest) = text.strip_prefix('\'') {
+        let mut value = String::new();
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    value.push('\'');
+                }
+                '\'' => return Some(FieldData::Text(value)),
+                c => value.push(c),
+            }
+        }
+        return None;
+    }
+    if let Some(rest) = text.strip_prefix("X'").or_else(|| text.strip_prefix("x'")) {
+        let hex = &rest[..rest.find('\'')?];
+        if hex.len() % 2 != 0 {
+            return None;
+        }
+        let blob = (0..hex.len())
+            .step_by(2)
+            .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        return Some(FieldData::Blob(blob));
+    }
+
+    let word = text
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ')')
+        .next()
+        .unwrap_or_default();
+    match word.to_ascii_uppercase().as_str() {
+        "NULL" => Some(FieldData::Null(())),
+        "TRUE" => Some(FieldData::Integer(1)),
+        "FALSE" => Some(FieldData::Integer(0)),
+        _ => word
+            .parse::<i64>()
+            .map(FieldData::Integer)
+            .ok()
+            .or_else(|| word.parse::<f64>().ok().map(FieldData::Real))
+            // f64 parsing also accepts words like `inf` and `NaN`, which are identifiers in SQL
+            .filter(|_| word.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c))),
+    }
+}
+
+// Column names of a table-level `[CONSTRAINT name] PRIMARY KEY (a, b)` constraint. Other
+// constraints give None even when they mention a primary key, e.g. a FOREIGN KEY referencing
+// a table called `primary_users`.
+fn primary_key_columns(part: &str) -> Option<Vec<String>> {
+    let mut rest = part.trim_start();
+    if keyword_at(rest, 0, "CONSTRAINT") {
+        rest = rest["CONSTRAINT".len()..].trim_start();
+        rest = rest[identifier_end(rest)?..].trim_start();
+    }
+    for keyword in ["PRIMARY", "KEY"] {
+        if !keyword_at(rest, 0, keyword) {
+            return None;
+        }
+        rest = rest[keyword.len()..].trim_start();
+    }
+    let list = rest.strip_prefix('(')?;
+    let list_end = list.find(')')?;
+    Some(
+        split_top_level(&list[..list_end])
+            .into_iter()
+            .filter_map(parse_column)
+            .map(|column| column.name)
+            .collect(),
+    )
+}
+
+// A column of an index key, ordered with `collation` (the table column's own collation when
+// None) in ascending or descending order
+#[derive(Debug)]
+pub struct IndexColumn {
+    pub name: String,
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+// Columns of a `CREATE INDEX name ON table (a, b COLLATE NOCASE DESC)` statement. Indexed
+// expressions come back as written, so they won't match any column of the table.
+pub fn index_columns(sql: &str) -> Option<Vec<IndexColumn>> {
+    let on = sql
+        .to_ascii_uppercase()
+        .split_whitespace()
+        .position(|word| word == "ON")?;
+    let after_on = sql
+        .split_whitespace()
+        .skip(on + 1)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let list_start = after_on.find('(')?;
+    let mut depth = 0;
+    let list_end = after_on[list_start..].char_indices().find_map(|(idx, c)| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        (depth == 0).then_some(list_start + idx)
+    })?;
+    Some(
+        split_top_level(&after_on[list_start + 1..list_end])
+            .into_iter()
+            .filter_map(parse_column)
+            .map(|column| {
+                // a lone DESC or ASC is taken for the type name
+                let words = column
+                    .type_name
+                    .split_whitespace()
+                    .chain(column.constraints.iter().map(String::as_str))
+                    .collect::<Vec<_>>();
+                IndexColumn {
+                    collation: column.collation().map(str::to_owned),
+                    descending: words.iter().any(|word| word.eq_ignore_ascii_case("DESC")),
+                    name: column.name,
+                }
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn table_primary_key_constraint() {
+        let table = TableDef::parse(
+            "t",
+            2,
+            "CREATE TABLE t (a, b, c, CONSTRAINT pk PRIMARY KEY (c, a))",
+        )
+        .unwrap();
+        assert_eq!(table.primary_key, [2, 0]);
+        assert_eq!(table.rowid_alias, None);
+    }
+
+    #[test]
+    fn other_constraints_mentioning_primary_are_not_keys() {
+        let table = TableDef::parse(
+            "pets",
+            2,
+            "CREATE TABLE pets (id INTEGER PRIMARY KEY, owner, is_primary,
+                FOREIGN KEY (owner) REFERENCES primary_users(uid),
+                CHECK (is_primary IN (0, 1)),
+                CONSTRAINT primary_owner UNIQUE (owner))",
+        )
+        .unwrap();
+        assert_eq!(table.primary_key, [0]);
+        assert_eq!(table.rowid_alias, Some(0));
+        assert_eq!(table.columns.len(), 3);
+    }
+
+    #[test]
+    fn rows_written_before_add_column_take_the_defaults() {
+        let fixture = Fixture::new(
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a);
+            INSERT INTO t (a) VALUES ('old');
+            ALTER TABLE t ADD COLUMN b TEXT DEFAULT 'it''s';
+            ALTER TABLE t ADD COLUMN c DEFAULT X'0102';
+            ALTER TABLE t ADD COLUMN d INTEGER DEFAULT -7;
+            ALTER TABLE t ADD COLUMN e REAL DEFAULT (2.5);
+            ALTER TABLE t ADD COLUMN f DEFAULT NULL;
+            ALTER TABLE t ADD COLUMN g;
+            INSERT INTO t (a, b, c, d, e, f, g) VALUES ('new', 'x', 3, 4, 5, 6, 7);",
+        );
+        let mut db = fixture.open();
+        let old = db.lookup_rowid("t", 1).unwrap().unwrap();
+        assert_eq!(
+            old.values(),
+            [
+                FieldData::Integer(1),
+                FieldData::Text("old".to_owned()),
+                FieldData::Text("it's".to_owned()),
+                FieldData::Blob(vec![1, 2]),
+                FieldData::Integer(-7),
+                FieldData::Real(2.5),
+                FieldData::Null(()),
+                FieldData::Null(()),
+            ]
+        );
+        // rows written after the ALTER TABLE store their own values
+        let new = db.lookup_rowid("t", 2).unwrap().unwrap();
+        assert_eq!(new.values[2], FieldData::Text("x".to_owned()));
+        assert_eq!(new.values[5], FieldData::Real(5.0));
+        assert_eq!(new.values[7].as_integer(), Some(7));
+    }
+
+    #[test]
+    fn only_constant_defaults_are_parsed() {
+        let table = TableDef::parse(
+            "t",
+            2,
+            "CREATE TABLE t (a DEFAULT TRUE, b DEFAULT CURRENT_TIMESTAMP, c DEFAULT +1.5e3,
+                d DEFAULT inf, e NOT NULL)",
+        )
+        .unwrap();
+        let defaults = table
+            .columns
+            .iter()
+            .map(|column| column.default.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            defaults,
+            [
+                Some(FieldData::Integer(1)),
+                None,
+                Some(FieldData::Real(1500.0)),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn index_columns_with_collation_and_order() {
+        let columns = index_columns(
+            "CREATE UNIQUE INDEX i ON t (a, \"b c\" COLLATE NOCASE DESC, d ASC) WHERE a > 0",
+        )
+        .unwrap();
+        let described = columns
+            .iter()
+            .map(|column| {
+                (
+                    column.name.as_str(),
+                    column.collation.as_deref(),
+                    column.descending,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            described,
+            [
+                ("a", None, false),
+                ("b c", Some("NOCASE"), true),
+                ("d", None, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn primary_key_columns_match_sqlite_table_info() {
+        let fixture = Fixture::new(
+            "CREATE TABLE inline (a TEXT, b INTEGER PRIMARY KEY, c);
+            CREATE TABLE composite (a, \"b c\" TEXT, d INTEGER, PRIMARY KEY (d, \"b c\"));
+            CREATE TABLE keyless (a, b);
+            CREATE TABLE w (x TEXT, y INTEGER, PRIMARY KEY (y)) WITHOUT ROWID;",
+        );
+        let conn = rusqlite::Connection::open(&fixture.path).unwrap();
+        let mut db = fixture.open();
+        for (table, rowid_alias) in [
+            ("inline", Some(1)),
+            ("composite", None),
+            ("keyless", None),
+            ("w", None),
+        ] {
+            // `pk` is the position of the column within the key, 0 for columns outside it
+            let mut expected = conn
+                .prepare(&format!(
+                    "SELECT cid, pk FROM pragma_table_info('{}')",
+                    table
+                ))
+                .unwrap()
+                .query_map([], |row| Ok((row.get::<_, i64>(1)?, row.get::<_, i64>(0)?)))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            expected.retain(|(pk, _)| *pk > 0);
+            expected.sort();
+            let expected = expected
+                .into_iter()
+                .map(|(_, cid)| cid as usize)
+                .collect::<Vec<_>>();
+
+            let table_def = db.table_def(table).unwrap();
+            assert_eq!(table_def.primary_key, expected, "{}", table);
+            assert_eq!(table_def.rowid_alias, rowid_alias, "{}", table);
+        }
+        assert_eq!(db.table_def("composite").unwrap().primary_key, [2, 1]);
+
+        let err = TableDef::parse("t", 2, "CREATE TABLE t (a, PRIMARY KEY (a, z))").unwrap_err();
+        assert!(err.to_string().contains("no primary key column z"));
+    }
+}
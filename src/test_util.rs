@@ -0,0 +1,101 @@
+#![allow(dead_code)]
+
+// Database files for the unit tests, written by SQLite itself (through the `rusqlite`
+// dev-dependency) into a temporary directory that is removed along with the fixture
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+
+use rusqlite::Connection;
+use tempfile::TempDir;
+
+use crate::db::Database;
+
+pub struct Fixture {
+    _dir: TempDir,
+    pub path: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(sql: &str) -> Self {
+        Fixture::with_page_size(4096, sql)
+    }
+
+    pub fn with_page_size(page_size: u32, sql: &str) -> Self {
+        Fixture::build(page_size, |conn| conn.execute_batch(sql).unwrap())
+    }
+
+    // Run `f` on a fresh connection to populate the database, e.g. to bind blob parameters
+    pub fn build<F>(page_size: u32, f: F) -> Self
+    where
+        F: FnOnce(&Connection),
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(&format!("PRAGMA page_size = {};", page_size))
+            .unwrap();
+        f(&conn);
+        conn.close().unwrap();
+        Self { _dir: dir, path }
+    }
+
+    // A file holding exactly `bytes`, for inputs SQLite wouldn't write
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        std::fs::write(&path, bytes).unwrap();
+        Self { _dir: dir, path }
+    }
+
+    pub fn open(&self) -> Database {
+        Database::new(&self.path).unwrap()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        std::fs::read(&self.path).unwrap()
+    }
+
+    // Overwrite the file at `offset` with `bytes`, to damage an otherwise valid database
+    pub fn patch(&self, offset: u64, bytes: &[u8]) {
+        use std::io::{Seek, SeekFrom, Write};
+        let mut file = OpenOptions::new().write(true).open(&self.path).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    // Append `bytes` to the end of the file
+    pub fn append(&self, bytes: &[u8]) {
+        use std::io::Write;
+        let mut file = OpenOptions::new().append(true).open(&self.path).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+}
+
+// Counts the allocations made on each thread, so a test can measure its own code while other
+// tests run in parallel
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Number of allocations made so far by the current thread
+pub fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
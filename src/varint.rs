@@ -26,51 +26,116 @@ impl fmt::Display for MaxBytesExceededError {
 
 impl Error for MaxBytesExceededError {}
 
-// Encode an unsigned integer up to 64 bits in size to a big-endian varint
+// Encode an unsigned integer up to 64 bits in size to a big-endian varint. Values that need
+// more than 56 bits use the 9-byte form, whose last byte holds a full 8 bits.
 pub fn encode_be<T>(value: T) -> (usize, Vec<u8>)
 where
     T: Into<u64>,
 {
     let value_64bit: u64 = value.into();
 
-    let result: Vec<u8> = (0..64)
-        .step_by(7)
-        .rev()
-        .filter_map(|shift| {
-            let byte_value = ((value_64bit >> shift) & 0x7f) as u8;
-            if byte_value != 0 || shift == 0 {
-                Some(if shift == 0 {
+    let result: Vec<u8> = if value_64bit >> 56 != 0 {
+        (0..8)
+            .rev()
+            .map(|group| ((value_64bit >> (8 + 7 * group)) & 0x7f) as u8 | 0x80)
+            .chain(std::iter::once(value_64bit as u8))
+            .collect()
+    } else {
+        // skip leading zero groups only, zero groups in the middle are significant
+        let num_bytes = ((64 - value_64bit.leading_zeros()) as usize)
+            .div_ceil(7)
+            .max(1);
+        (0..num_bytes)
+            .rev()
+            .map(|group| {
+                let byte_value = ((value_64bit >> (7 * group)) & 0x7f) as u8;
+                if group == 0 {
                     byte_value
                 } else {
                     byte_value | 0x80
-                })
-            } else {
-                None
-            }
-        })
-        .collect();
+                }
+            })
+            .collect()
+    };
 
     (result.len(), result)
 }
 
-// Read a big-endian varint from a slice of bytes
+// Read a big-endian varint from a slice of bytes, returning the value and the number of bytes
+// it occupied (1 to 9)
 pub fn decode_be(input: &[u8]) -> Result<(u64, usize), MaxBytesExceededError> {
     let mut result = 0u64;
-    let mut position = 0;
 
     for (idx, &byte) in input.iter().enumerate() {
-        // If MSB is set, keep accumulating up to max bytes
-        if byte > 0x7f {
-            if position > 7 {
-                return Err(MaxBytesExceededError::new());
-            }
-            result = (result << 7) | u64::from(byte & 0x7f);
-        } else {
-            result = (result << 7) | u64::from(byte);
-            position = idx;
-            break;
+        // The 9th byte contributes all 8 of its bits and always ends the varint
+        if idx == 8 {
+            return Ok(((result << 8) | u64::from(byte), 9));
         }
+        result = (result << 7) | u64::from(byte & 0x7f);
+        // If MSB is set, keep accumulating up to max bytes; a clear MSB marks the last byte
+        if byte <= 0x7f {
+            return Ok((result, idx + 1));
+        }
+    }
+
+    // the input ended before a byte without a continuation flag
+    Err(MaxBytesExceededError::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_known_encodings() {
+        assert_eq!(decode_be(&[0x00]).unwrap(), (0, 1));
+        assert_eq!(decode_be(&[0x7f]).unwrap(), (127, 1));
+        assert_eq!(decode_be(&[0x81, 0x00]).unwrap(), (128, 2));
+        // bytes after the varint are left alone
+        assert_eq!(decode_be(&[0x81, 0x80, 0x00, 0x55]).unwrap(), (1 << 14, 3));
+    }
+
+    #[test]
+    fn nine_byte_form_uses_all_bits_of_the_last_byte() {
+        assert_eq!(decode_be(&[0xff; 9]).unwrap(), (u64::MAX, 9));
+        // a continuation flag on the 9th byte is part of the value, not a 10th byte
+        assert_eq!(decode_be(&[0xff; 10]).unwrap(), (u64::MAX, 9));
+        let (len, bytes) = encode_be(u64::MAX);
+        assert_eq!((len, bytes), (9, vec![0xff; 9]));
+        let (len, bytes) = encode_be(1u64 << 56);
+        assert_eq!(len, 9);
+        assert_eq!(decode_be(&bytes).unwrap(), (1 << 56, 9));
+    }
+
+    #[test]
+    fn encoded_length_grows_every_seven_bits() {
+        assert_eq!(encode_be(0u64).0, 1);
+        assert_eq!(encode_be((1u64 << 7) - 1).0, 1);
+        assert_eq!(encode_be(1u64 << 7).0, 2);
+        assert_eq!(encode_be((1u64 << 56) - 1).0, 8);
+        assert_eq!(encode_be(1u64 << 56).0, 9);
     }
 
-    Ok((result, position + 1))
+    #[test]
+    fn truncated_input_is_an_error() {
+        assert!(decode_be(&[]).is_err());
+        assert!(decode_be(&[0x81]).is_err());
+        assert!(decode_be(&[0xff; 8]).is_err());
+    }
+
+    #[test]
+    fn round_trips_pseudorandom_values() {
+        // a fixed xorshift sequence, shifted down by varying amounts so every length is covered
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        for i in 0..10_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let value = state >> (i % 64);
+            let (len, mut bytes) = encode_be(value);
+            assert_eq!(len, bytes.len());
+            bytes.push(0xaa);
+            assert_eq!(decode_be(&bytes).unwrap(), (value, len), "value {}", value);
+        }
+    }
 }
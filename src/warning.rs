@@ -0,0 +1,68 @@
+use std::fmt;
+
+use crate::db::Database;
+
+// Damage a read can work around. Outside strict mode the read carries on and the warning goes
+// to the callback set with `Database::set_warning_callback`; strict mode fails the read with a
+// `RuSqliteError::Corrupt` instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    // the space between a cell and the next one (or the end of the page) is smaller than the
+    // cell's varints and local payload take
+    CellTooSmall {
+        page_no: u32,
+        offset: usize,
+        size: usize,
+        required: usize,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::CellTooSmall {
+                page_no,
+                offset,
+                size,
+                required,
+            } => write!(
+                f,
+                "cell at offset {} on page {} is {} bytes but its contents take {}",
+                offset, page_no, size, required
+            ),
+        }
+    }
+}
+
+// Send and Sync so a database can be shared between threads
+pub type WarningCallback = Box<dyn FnMut(&Warning) + Send + Sync>;
+
+// Hands warnings to the registered callback. Without a callback they are dropped.
+#[derive(Default)]
+pub struct WarningReporter {
+    callback: Option<WarningCallback>,
+}
+
+impl fmt::Debug for WarningReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WarningReporter")
+            .field("enabled", &self.callback.is_some())
+            .finish()
+    }
+}
+
+impl WarningReporter {
+    pub fn warn(&mut self, warning: Warning) {
+        if let Some(callback) = self.callback.as_mut() {
+            callback(&warning);
+        }
+    }
+}
+
+impl Database {
+    // Call `f` with each warning raised while reading, e.g. to log or collect them. Replaces any
+    // earlier callback.
+    pub fn set_warning_callback(&mut self, f: WarningCallback) {
+        self.warnings = WarningReporter { callback: Some(f) };
+    }
+}